@@ -0,0 +1,33 @@
+import numpy as np
+import numpy.typing as npt
+import scipy.sparse
+
+class MosoxError(Exception): ...
+
+class Model:
+    def generate(self) -> Matrix: ...
+
+class Matrix:
+    row_names: list[str]
+    col_names: list[str]
+    row_types: list[str]
+    @property
+    def rhs(self) -> npt.NDArray[np.float64]: ...
+    @property
+    def ranges(self) -> npt.NDArray[np.float64]: ...
+    @property
+    def col_lower(self) -> npt.NDArray[np.float64]: ...
+    @property
+    def col_upper(self) -> npt.NDArray[np.float64]: ...
+    @property
+    def rows(self) -> npt.NDArray[np.int64]: ...
+    @property
+    def cols(self) -> npt.NDArray[np.int64]: ...
+    @property
+    def vals(self) -> npt.NDArray[np.float64]: ...
+    @property
+    def shape(self) -> tuple[int, int]: ...
+    def to_scipy(self) -> scipy.sparse.csc_array: ...
+
+def load(path: str, data_path: str | list[str] | None = None) -> Model: ...
+def generate(path: str, data_path: str | list[str] | None = None) -> Matrix: ...
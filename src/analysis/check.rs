@@ -0,0 +1,444 @@
+//! Semantic checks on a merged model, run before any matrix generation.
+//!
+//! Catches the mistakes that would otherwise surface as a missed lookup (and a
+//! panic) deep inside the generator: wrong subscript counts, unbound indices,
+//! vars where only constants are allowed and non-linear terms.
+
+use std::collections::HashMap;
+
+use lasso::Spur;
+
+use crate::analysis::Diagnostic;
+use crate::analysis::graph::{Graph, NodeKind};
+use crate::ir::interner::intern_resolve;
+use crate::ir::model::ModelWithData;
+use crate::ir::op::RowType;
+use crate::ir::{
+    Domain, DomainPartVar, Expr, LogicExpr, MathOp, Param, ParamAssign, RelOp, Set, SetExpr,
+    SetValue, Subscript,
+};
+
+/// What a name is declared as, and how many subscripts it takes
+struct Decl {
+    kind: NodeKind,
+    arity: usize,
+}
+
+fn domain_arity(domain: &Option<Domain>) -> usize {
+    domain.as_ref().map_or(0, Domain::dimen)
+}
+
+pub fn check_model(model: &ModelWithData) -> Vec<Diagnostic> {
+    let mut diags = Vec::new();
+
+    let graph = Graph::from_model(model);
+    for name in graph.unused() {
+        diags.push(Diagnostic::warning(format!(
+            "{name} is declared but never used"
+        )));
+    }
+    for (user, name) in graph.undeclared() {
+        diags.push(Diagnostic::error(format!(
+            "{user} refers to undeclared symbol {name}"
+        )));
+    }
+
+    let mut decls: HashMap<Spur, Decl> = HashMap::new();
+    for set in &model.sets {
+        let arity = set.decl.dims.len();
+        decls.insert(
+            set.decl.name,
+            Decl {
+                kind: NodeKind::Set,
+                arity,
+            },
+        );
+    }
+    for par in &model.pars {
+        let arity = domain_arity(&par.decl.domain);
+        decls.insert(
+            par.decl.name,
+            Decl {
+                kind: NodeKind::Param,
+                arity,
+            },
+        );
+    }
+    for var in &model.vars {
+        let arity = domain_arity(&var.domain);
+        decls.insert(
+            var.name,
+            Decl {
+                kind: NodeKind::Var,
+                arity,
+            },
+        );
+    }
+
+    let mut checker = Checker {
+        decls: &decls,
+        bound: Vec::new(),
+        context: String::new(),
+        no_vars: None,
+        diags: Vec::new(),
+    };
+    for set in &model.sets {
+        checker.context = format!("set {}", intern_resolve(set.decl.name));
+        checker.set(&set.decl);
+    }
+    for par in &model.pars {
+        checker.context = format!("param {}", intern_resolve(par.decl.name));
+        checker.param(&par.decl);
+    }
+    for var in &model.vars {
+        checker.context = format!("var {}", intern_resolve(var.name));
+        if let Some(domain) = &var.domain {
+            checker.domain(domain, |_| {});
+        }
+        if let Some(bounds) = &var.bounds {
+            match bounds.op {
+                RelOp::Ne | RelOp::Ne2 => {
+                    checker.error(format!("{} is not allowed in var bounds", bounds.op))
+                }
+                op => checker.strict(op),
+            }
+        }
+    }
+    for con in &model.constraints {
+        let kind = match con.row_type {
+            RowType::Unconstrained => "objective",
+            _ => "constraint",
+        };
+        checker.context = format!("{kind} {}", intern_resolve(con.name));
+        match con.relation_error() {
+            Some(message) => checker.error(message),
+            None => con.ops.iter().for_each(|op| checker.strict(*op)),
+        }
+        let body = |c: &mut Checker| {
+            c.expr(&con.lhs);
+            match &con.upper {
+                Some(upper) => c.without_vars("double inequality bound", |c| {
+                    c.expr(&con.rhs);
+                    c.expr(upper);
+                }),
+                None => {
+                    c.expr(&con.rhs);
+                }
+            }
+        };
+        match &con.domain {
+            Some(domain) => checker.domain(domain, body),
+            None => body(&mut checker),
+        }
+    }
+
+    diags.extend(checker.diags);
+    diags
+}
+
+struct Checker<'a> {
+    decls: &'a HashMap<Spur, Decl>,
+    /// Dummy indices bound by the enclosing domains
+    bound: Vec<Spur>,
+    /// The declaration being checked, eg "constraint EQ_SpecifiedDemand"
+    context: String,
+    /// Set while checking somewhere vars aren't allowed, eg "domain condition"
+    no_vars: Option<&'static str>,
+    diags: Vec<Diagnostic>,
+}
+
+impl Checker<'_> {
+    fn error(&mut self, message: String) {
+        self.diags
+            .push(Diagnostic::error(format!("{}: {message}", self.context)));
+    }
+
+    fn warning(&mut self, message: String) {
+        self.diags
+            .push(Diagnostic::warning(format!("{}: {message}", self.context)));
+    }
+
+    /// Warn that `<` or `>` is taken as `<=` or `>=`, as in GLPK
+    fn strict(&mut self, op: RelOp) {
+        let relaxed = match op {
+            RelOp::Lt => RelOp::Le,
+            RelOp::Gt => RelOp::Ge,
+            _ => return,
+        };
+        self.warning(format!("{op} is treated as {relaxed}"));
+    }
+
+    fn without_vars(&mut self, place: &'static str, inner: impl FnOnce(&mut Self)) {
+        let outer = self.no_vars;
+        self.no_vars = outer.or(Some(place));
+        inner(self);
+        self.no_vars = outer;
+    }
+
+    /// Check the subscripts of a reference to a declared set, param or var.
+    /// Undeclared names are skipped as they're already reported from the graph.
+    fn subscripted(&mut self, name: Spur, subscript: &Subscript) -> Option<NodeKind> {
+        let decl = self.decls.get(&name)?;
+        let (kind, arity) = (decl.kind, decl.arity);
+        let symbol = intern_resolve(name);
+        if subscript.0.len() != arity {
+            self.error(format!(
+                "{symbol} takes {arity} subscript(s) but is given {}",
+                subscript.0.len()
+            ));
+        }
+        for part in subscript.iter() {
+            let is_literal = intern_resolve(part.var).parse::<u32>().is_ok();
+            if !is_literal && !self.bound.contains(&part.var) {
+                self.error(format!(
+                    "index {} in {symbol}[...] is not bound by any domain",
+                    intern_resolve(part.var)
+                ));
+            }
+        }
+        Some(kind)
+    }
+
+    fn domain(&mut self, domain: &Domain, inner: impl FnOnce(&mut Self)) {
+        let depth = self.bound.len();
+        for part in &domain.parts {
+            self.subscripted(part.set, &part.subscript);
+            match &part.var {
+                DomainPartVar::Single(id) => self.bound.push(*id),
+                DomainPartVar::Tuple(ids) => self.bound.extend(ids),
+            }
+        }
+        if let Some(cond) = &domain.condition {
+            self.without_vars("domain condition", |c| c.logic(cond));
+        }
+        inner(self);
+        self.bound.truncate(depth);
+    }
+
+    fn logic(&mut self, logic: &LogicExpr) {
+        match logic {
+            LogicExpr::Comparison { lhs, op: _, rhs } => {
+                self.expr(lhs);
+                self.expr(rhs);
+            }
+            LogicExpr::BoolOp { lhs, op: _, rhs } => {
+                self.logic(lhs);
+                self.logic(rhs);
+            }
+        }
+    }
+
+    /// Check an expression, returning whether it contains any vars
+    fn expr(&mut self, expr: &Expr) -> bool {
+        match expr {
+            Expr::Number(_) => false,
+            Expr::VarSubscripted(v) => {
+                if v.subscript.is_empty() && self.bound.contains(&v.var) {
+                    return false;
+                }
+                match self.subscripted(v.var, &v.subscript) {
+                    Some(NodeKind::Var) => {
+                        if let Some(place) = self.no_vars {
+                            let symbol = intern_resolve(v.var);
+                            self.error(format!("var {symbol} is not allowed in a {place}"));
+                        }
+                        true
+                    }
+                    _ => false,
+                }
+            }
+            Expr::FuncSum(func) => {
+                let mut has_vars = false;
+                self.domain(&func.domain, |c| has_vars = c.expr(&func.operand));
+                has_vars
+            }
+            Expr::FuncMin(func) => {
+                self.minmax(&func.domain, func.var);
+                false
+            }
+            Expr::FuncMax(func) => {
+                self.minmax(&func.domain, func.var);
+                false
+            }
+            Expr::Conditional(cond) => {
+                self.without_vars("conditional expression", |c| c.logic(&cond.condition));
+                let then_vars = self.expr(&cond.then_expr);
+                let else_vars = cond.else_expr.as_ref().is_some_and(|e| self.expr(e));
+                then_vars || else_vars
+            }
+            Expr::UnaryNeg(inner) => self.expr(inner),
+            Expr::BinOp { lhs, op, rhs } => {
+                let lhs_vars = self.expr(lhs);
+                let rhs_vars = self.expr(rhs);
+                match op {
+                    MathOp::Mul if lhs_vars && rhs_vars => {
+                        self.error(format!("product of two vars is not linear: {expr}"));
+                    }
+                    MathOp::Div if rhs_vars => {
+                        self.error(format!("division by a var is not linear: {expr}"));
+                    }
+                    MathOp::Pow if lhs_vars || rhs_vars => {
+                        self.error(format!("power of a var is not linear: {expr}"));
+                    }
+                    _ => {}
+                }
+                lhs_vars || rhs_vars
+            }
+        }
+    }
+
+    fn minmax(&mut self, domain: &Domain, var: Spur) {
+        self.domain(domain, |c| {
+            if !c.bound.contains(&var) {
+                c.error(format!(
+                    "{} in min/max is not bound by its domain",
+                    intern_resolve(var)
+                ));
+            }
+        });
+    }
+
+    fn set(&mut self, set: &Set) {
+        let depth = self.bound.len();
+        self.bound
+            .extend(set.dims.iter().filter_map(|part| part.id));
+        self.without_vars("set expression", |c| {
+            if let Some(expr) = &set.expr {
+                c.set_expr(expr);
+            }
+            if let Some(SetValue::Expr(expr)) = &set.default {
+                c.set_expr(expr);
+            }
+        });
+        self.bound.truncate(depth);
+    }
+
+    fn set_expr(&mut self, expr: &SetExpr) {
+        match expr {
+            SetExpr::Domain(domain) => self.domain(domain, |_| {}),
+            SetExpr::SetMath(set_math) => {
+                for v in &set_math.intersection {
+                    self.subscripted(v.var, &v.subscript);
+                }
+            }
+            SetExpr::SetOf(set_of) => self.domain(&set_of.domain, |c| {
+                let ids = match &set_of.integrand {
+                    DomainPartVar::Single(id) => std::slice::from_ref(id),
+                    DomainPartVar::Tuple(ids) => ids.as_slice(),
+                };
+                for id in ids {
+                    if !c.bound.contains(id) {
+                        c.error(format!(
+                            "{} in setof is not bound by its domain",
+                            intern_resolve(*id)
+                        ));
+                    }
+                }
+            }),
+        }
+    }
+
+    fn param(&mut self, param: &Param) {
+        let body = |c: &mut Self| {
+            c.without_vars("param expression", |c| {
+                if let Some(default) = &param.default {
+                    c.expr(default);
+                }
+                if let Some(ParamAssign::Expr(expr)) = &param.assign {
+                    c.expr(expr);
+                }
+                if let Some(expr) = &param.param_in {
+                    c.expr(expr);
+                }
+                for cond in &param.conditions {
+                    c.expr(&cond.value);
+                }
+            })
+        };
+        match &param.domain {
+            Some(domain) => self.domain(domain, body),
+            None => body(self),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gmpl::loader::{consume, parse};
+
+    fn errors(text: &str) -> Vec<String> {
+        let model = ModelWithData::from_entries(consume(parse(text)));
+        check_model(&model)
+            .into_iter()
+            .filter(|d| d.is_error())
+            .map(|d| d.message)
+            .collect()
+    }
+
+    #[test]
+    fn test_check_relations() {
+        let text = r#"
+            var x > 0;
+            var y <> 1;
+            minimize cost: x;
+            s.t. strict: x < 5;
+            s.t. band: 1 < x + y <= 4;
+            s.t. apart: x <> y;
+            s.t. mixed: 1 <= x >= 0;
+        "#;
+        let model = ModelWithData::from_entries(consume(parse(text)));
+        let diags: Vec<String> = check_model(&model)
+            .into_iter()
+            .map(|d| d.to_string())
+            .collect();
+        assert_eq!(
+            diags,
+            vec![
+                "warning: var x: > is treated as >=",
+                "error: var y: <> is not allowed in var bounds",
+                "warning: constraint strict: < is treated as <=",
+                "warning: constraint band: < is treated as <=",
+                "error: constraint apart: <> is not allowed in a constraint",
+                "error: constraint mixed: a double inequality needs <= or >= on both sides, not <= and >=",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_check_ok() {
+        let text = r#"
+            set R;
+            param cap{r in R};
+            var x{r in R} >= 0;
+            minimize cost: sum{r in R} x[r];
+            s.t. limit{r in R: cap[r] > 0}: x[r] <= cap[r];
+        "#;
+        assert!(errors(text).is_empty());
+    }
+
+    #[test]
+    fn test_check_errors() {
+        let text = r#"
+            set R;
+            param cap{r in R};
+            param bad{r in R} := cap[r, r];
+            var x{r in R} >= 0;
+            var y >= 0;
+            minimize cost: sum{r in R} x[r] * y;
+            s.t. limit{r in R: x[r] > 0}: x[q] <= cap[r];
+            s.t. band{r in R}: 0 <= x[r] <= y;
+        "#;
+        let errs = errors(text);
+        assert_eq!(
+            errs,
+            vec![
+                "param bad: cap takes 1 subscript(s) but is given 2",
+                "constraint limit: var x is not allowed in a domain condition",
+                "constraint limit: index q in x[...] is not bound by any domain",
+                "constraint band: var y is not allowed in a double inequality bound",
+                "objective cost: product of two vars is not linear: (x[...] * y)",
+            ]
+        );
+    }
+}
@@ -0,0 +1,212 @@
+//! Reference graph between the sets, params, vars and constraints of a model.
+
+use std::collections::HashSet;
+use std::fmt::Write;
+
+use indexmap::{IndexMap, IndexSet};
+use lasso::Spur;
+
+use crate::ir::interner::{intern, intern_resolve};
+use crate::ir::model::ModelWithData;
+use crate::ir::op::RowType;
+use crate::ir::refs::Refs;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NodeKind {
+    Set,
+    Param,
+    Var,
+    Constraint,
+    Objective,
+}
+
+impl NodeKind {
+    fn as_str(&self) -> &'static str {
+        match self {
+            NodeKind::Set => "set",
+            NodeKind::Param => "param",
+            NodeKind::Var => "var",
+            NodeKind::Constraint => "constraint",
+            NodeKind::Objective => "objective",
+        }
+    }
+
+    fn dot_shape(&self) -> &'static str {
+        match self {
+            NodeKind::Set => "box",
+            NodeKind::Param => "ellipse",
+            NodeKind::Var => "diamond",
+            NodeKind::Constraint => "note",
+            NodeKind::Objective => "doubleoctagon",
+        }
+    }
+}
+
+/// Every declaration in the model and the symbols it refers to
+pub struct Graph {
+    /// Declarations in model order
+    pub nodes: IndexMap<Spur, NodeKind>,
+    /// (user, used) pairs, eg (EQ_SpecifiedDemand, SpecifiedAnnualDemand)
+    pub edges: IndexSet<(Spur, Spur)>,
+}
+
+impl Graph {
+    pub fn from_model(model: &ModelWithData) -> Self {
+        let mut nodes = IndexMap::new();
+        let mut edges = IndexSet::new();
+        let mut add = |name: Spur, kind: NodeKind, refs: HashSet<Spur>| {
+            nodes.insert(name, kind);
+            // HashSet order is random, so sort to keep the output stable
+            let mut refs: Vec<Spur> = refs.into_iter().collect();
+            refs.sort_by_key(|r| intern_resolve(*r));
+            edges.extend(refs.into_iter().map(|r| (name, r)));
+        };
+
+        for set in &model.sets {
+            add(set.decl.name, NodeKind::Set, Refs::of_set(&set.decl));
+        }
+        for par in &model.pars {
+            add(par.decl.name, NodeKind::Param, Refs::of_param(&par.decl));
+        }
+        for var in &model.vars {
+            let mut refs = Refs::default();
+            if let Some(domain) = &var.domain {
+                refs.domain(domain, |_| {});
+            }
+            add(var.name, NodeKind::Var, refs.names);
+        }
+        for con in &model.constraints {
+            let kind = match con.row_type {
+                RowType::Unconstrained => NodeKind::Objective,
+                _ => NodeKind::Constraint,
+            };
+            add(con.name, kind, Refs::of_constraint(con));
+        }
+
+        Graph { nodes, edges }
+    }
+
+    /// Sets, params and vars that nothing else refers to
+    pub fn unused(&self) -> Vec<&'static str> {
+        let used: HashSet<Spur> = self.edges.iter().map(|(_, to)| *to).collect();
+        self.nodes
+            .iter()
+            .filter(|(name, kind)| {
+                matches!(kind, NodeKind::Set | NodeKind::Param | NodeKind::Var)
+                    && !used.contains(*name)
+            })
+            .map(|(name, _)| intern_resolve(*name))
+            .collect()
+    }
+
+    /// (user, name) pairs where `name` is not declared anywhere in the model
+    pub fn undeclared(&self) -> Vec<(&'static str, &'static str)> {
+        self.edges
+            .iter()
+            .filter(|(_, to)| !self.nodes.contains_key(to))
+            .map(|(from, to)| (intern_resolve(*from), intern_resolve(*to)))
+            .collect()
+    }
+
+    /// Constraints and objectives that depend on `name`, directly or through
+    /// other params and sets
+    pub fn users_of(&self, name: &str) -> Vec<&'static str> {
+        let name = intern(name);
+        let mut seen: HashSet<Spur> = HashSet::from([name]);
+        let mut todo = vec![name];
+        while let Some(used) = todo.pop() {
+            for (from, _) in self.edges.iter().filter(|(_, to)| *to == used) {
+                if seen.insert(*from) {
+                    todo.push(*from);
+                }
+            }
+        }
+        self.nodes
+            .iter()
+            .filter(|(name, kind)| {
+                matches!(kind, NodeKind::Constraint | NodeKind::Objective) && seen.contains(*name)
+            })
+            .map(|(name, _)| intern_resolve(*name))
+            .collect()
+    }
+
+    pub fn to_dot(&self) -> String {
+        let mut out = String::from("digraph model {\n");
+        for (name, kind) in &self.nodes {
+            let name = intern_resolve(*name);
+            writeln!(out, "  \"{name}\" [shape={}];", kind.dot_shape()).unwrap();
+        }
+        for (from, to) in &self.edges {
+            let (from, to) = (intern_resolve(*from), intern_resolve(*to));
+            writeln!(out, "  \"{from}\" -> \"{to}\";").unwrap();
+        }
+        out.push_str("}\n");
+        out
+    }
+
+    pub fn to_json(&self) -> String {
+        let nodes: Vec<String> = self
+            .nodes
+            .iter()
+            .map(|(name, kind)| {
+                format!(
+                    "{{\"name\":{},\"kind\":\"{}\"}}",
+                    json_str(intern_resolve(*name)),
+                    kind.as_str()
+                )
+            })
+            .collect();
+        let edges: Vec<String> = self
+            .edges
+            .iter()
+            .map(|(from, to)| {
+                format!(
+                    "{{\"from\":{},\"to\":{}}}",
+                    json_str(intern_resolve(*from)),
+                    json_str(intern_resolve(*to))
+                )
+            })
+            .collect();
+        let undeclared: Vec<String> = self
+            .undeclared()
+            .iter()
+            .map(|(from, name)| {
+                format!("{{\"in\":{},\"name\":{}}}", json_str(from), json_str(name))
+            })
+            .collect();
+        let unused: Vec<String> = self.unused().iter().copied().map(json_str).collect();
+
+        format!(
+            "{{\"nodes\":[{}],\"edges\":[{}],\"unused\":[{}],\"undeclared\":[{}]}}\n",
+            nodes.join(","),
+            edges.join(","),
+            unused.join(","),
+            undeclared.join(","),
+        )
+    }
+}
+
+/// A JSON string literal, quoted and with `"` and `\` escaped
+fn json_str(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        if matches!(c, '"' | '\\') {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    out.push('"');
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_json_str() {
+        assert_eq!(json_str("cost"), r#""cost""#);
+        assert_eq!(json_str(r#"a"b\c"#), r#""a\"b\\c""#);
+    }
+}
@@ -0,0 +1,416 @@
+//! Data validation: checks the values given in the data section against what the
+//! model declares for them (domains, conditions, types, `in` and `within`).
+//!
+//! Unlike the semantic checks this needs the resolved sets, so it builds the
+//! lookups and should only be run on a model that passes `check_model`.
+
+use std::collections::{HashMap, HashSet};
+
+use lasso::Spur;
+
+use crate::analysis::Diagnostic;
+use crate::ir::interner::{intern, intern_resolve};
+use crate::ir::model::{ConstraintOrObjective, ModelWithData};
+use crate::ir::{
+    self, Domain, DomainPartVar, Expr, Index, LogicExpr, ParamType, SetVal, SetValTerminal,
+    index_name,
+};
+use crate::matrix::GenOptions;
+use crate::matrix::constraint::{
+    IdxValMap, check_domain_condition, concrete_index, filter_domain, in_domain_product,
+};
+use crate::matrix::lookup::Lookups;
+use crate::matrix::param::ParamVal;
+
+pub fn validate_data(model: &ModelWithData) -> Vec<Diagnostic> {
+    validate(model, true, |_| true)
+}
+
+/// Validate the data of the params in `names` only, eg those overridden on the
+/// command line. Builds nothing if there are none.
+pub fn validate_params(model: &ModelWithData, names: &[impl AsRef<str>]) -> Vec<Diagnostic> {
+    if names.is_empty() {
+        return vec![];
+    }
+    validate(model, false, |name| {
+        names.iter().any(|n| n.as_ref() == intern_resolve(name))
+    })
+}
+
+fn validate(
+    model: &ModelWithData,
+    with_sets: bool,
+    params: impl Fn(Spur) -> bool,
+) -> Vec<Diagnostic> {
+    let sets: Vec<(ir::Set, Vec<Index>)> = model
+        .sets
+        .iter()
+        .filter(|_| with_sets)
+        .map(|set| {
+            let indexes = set.data.iter().map(|data| data.index.clone()).collect();
+            (set.decl.clone(), indexes)
+        })
+        .collect();
+    let pars: Vec<ir::Param> = model
+        .pars
+        .iter()
+        .filter(|par| params(par.decl.name))
+        .map(|par| par.decl.clone())
+        .collect();
+
+    let model = model.clone();
+    let lookups = Lookups::from_model(model.sets, model.vars, model.pars);
+    let mut validator = Validator {
+        lookups: &lookups,
+        members: HashMap::new(),
+        diags: Vec::new(),
+    };
+    for (set, indexes) in &sets {
+        validator.set(set, indexes);
+    }
+    for par in &pars {
+        validator.param(par);
+    }
+    validator.diags
+}
+
+/// Check that the row `select_objective` chose is one its objective has, once
+/// the sets are restricted as they will be when generating
+pub fn validate_objective(model: &ModelWithData, options: &GenOptions) -> Vec<Diagnostic> {
+    let selected: Vec<(&ConstraintOrObjective, &Index)> = model
+        .constraints
+        .iter()
+        .filter_map(|con| Some((con, con.active.as_ref()?)))
+        .collect();
+    if selected.is_empty() {
+        return vec![];
+    }
+    let mut lookups =
+        Lookups::from_model(model.sets.clone(), model.vars.clone(), model.pars.clone());
+    options.restrict_sets(&mut lookups);
+    let empty = IdxValMap::new();
+    selected
+        .into_iter()
+        .filter(|(con, active)| {
+            let domain = con.domain.as_ref().unwrap();
+            !in_domain_product(domain, active, &lookups)
+                || filter_domain(domain, std::iter::once((*active).clone()), &lookups, &empty)
+                    .next()
+                    .is_none()
+        })
+        .map(|(con, active)| {
+            Diagnostic::error(format!(
+                "{} isn't in the domain of {}",
+                index_name(con.name, active),
+                intern_resolve(con.name)
+            ))
+        })
+        .collect()
+}
+
+struct Validator<'a> {
+    lookups: &'a Lookups,
+    /// Resolved set members, by set name and index
+    members: HashMap<(Spur, Index), HashSet<SetVal>>,
+    diags: Vec<Diagnostic>,
+}
+
+impl Validator<'_> {
+    fn members(&mut self, set: Spur, index: Index) -> &HashSet<SetVal> {
+        let lookups = self.lookups;
+        self.members
+            .entry((set, index))
+            .or_insert_with_key(|(set, index)| {
+                lookups.set_map[set]
+                    .resolve(index, lookups)
+                    .iter()
+                    .copied()
+                    .collect()
+            })
+    }
+
+    /// Report every offending key (or member) for one rule in a single diagnostic
+    fn report(&mut self, context: String, rule: &str, mut offending: Vec<String>) {
+        if offending.is_empty() {
+            return;
+        }
+        offending.sort();
+        self.diags.push(Diagnostic::error(format!(
+            "{context}: {rule}: {}",
+            offending.join(", ")
+        )));
+    }
+
+    /// Match a flat data key against a domain, returning the index values it binds
+    /// if the key is in the domain
+    fn locate(&mut self, domain: &Domain, key: &Index) -> Option<IdxValMap> {
+        let mut idx_map = IdxValMap::new();
+        let mut rest = key.as_slice();
+        for part in &domain.parts {
+            let val = match &part.var {
+                DomainPartVar::Single(id) => {
+                    let (val, tail) = rest.split_first()?;
+                    rest = tail;
+                    idx_map.push((*id, *val));
+                    *val
+                }
+                DomainPartVar::Tuple(ids) => {
+                    if rest.len() < ids.len() {
+                        return None;
+                    }
+                    let (vals, tail) = rest.split_at(ids.len());
+                    rest = tail;
+                    idx_map.extend(ids.iter().copied().zip(vals.iter().copied()));
+                    let terms: Vec<SetValTerminal> =
+                        vals.iter().map(to_terminal).collect::<Option<_>>()?;
+                    SetVal::Tuple(terms.try_into().ok()?)
+                }
+            };
+            let subscript = concrete_index(&part.subscript, &idx_map);
+            if !self.members(part.set, subscript).contains(&val) {
+                return None;
+            }
+        }
+        if !rest.is_empty() {
+            return None;
+        }
+        match &domain.condition {
+            Some(cond) if !check_domain_condition(cond, self.lookups, &idx_map) => None,
+            _ => Some(idx_map),
+        }
+    }
+
+    fn param(&mut self, param: &ir::Param) {
+        let values: Vec<(Index, f64)> = match &self.lookups.par_map[&param.name].data {
+            ParamVal::Arr(arr) => arr.iter().map(|(key, val)| (key.clone(), *val)).collect(),
+            ParamVal::Scalar(val) => vec![(Index::new(), *val)],
+            // Computed params and params without data have nothing to validate
+            ParamVal::Expr(_) | ParamVal::None => return,
+        };
+
+        let context = format!("param {}", intern_resolve(param.name));
+        let mut outside = Vec::new();
+        // Offending entries for each rule, in the order the rules are declared
+        let mut broken: Vec<(String, Vec<String>)> = param
+            .conditions
+            .iter()
+            .map(|cond| format!("values breaking {} {}", cond.op, cond.value))
+            .chain(match param.param_type {
+                Some(ParamType::Integer) => Some("values that are not integer".to_string()),
+                Some(ParamType::Binary) => Some("values that are not binary".to_string()),
+                _ => None,
+            })
+            .chain(
+                param
+                    .param_in
+                    .as_ref()
+                    .map(|set| format!("values not in {set}")),
+            )
+            .map(|rule| (rule, Vec::new()))
+            .collect();
+        let mut add_broken = |rule: String, entry: String| {
+            if let Some((_, entries)) = broken.iter_mut().find(|(r, _)| *r == rule) {
+                entries.push(entry);
+            }
+        };
+
+        for (key, val) in values {
+            let idx_map = match &param.domain {
+                Some(domain) => match self.locate(domain, &key) {
+                    Some(idx_map) => idx_map,
+                    None => {
+                        outside.push(index_name(param.name, &key));
+                        continue;
+                    }
+                },
+                None => IdxValMap::new(),
+            };
+            let entry = || format!("{} = {val}", index_name(param.name, &key));
+
+            for cond in &param.conditions {
+                let logic = LogicExpr::Comparison {
+                    lhs: Expr::Number(val),
+                    op: cond.op,
+                    rhs: cond.value.clone(),
+                };
+                if !check_domain_condition(&logic, self.lookups, &idx_map) {
+                    add_broken(
+                        format!("values breaking {} {}", cond.op, cond.value),
+                        entry(),
+                    );
+                }
+            }
+            match param.param_type {
+                Some(ParamType::Integer) if val.fract() != 0.0 => {
+                    add_broken("values that are not integer".to_string(), entry());
+                }
+                Some(ParamType::Binary) if val != 0.0 && val != 1.0 => {
+                    add_broken("values that are not binary".to_string(), entry());
+                }
+                _ => {}
+            }
+            if let Some(param_in @ Expr::VarSubscripted(set)) = &param.param_in
+                && self.lookups.set_map.contains_key(&set.var)
+            {
+                let subscript = concrete_index(&set.subscript, &idx_map);
+                let is_member = val.fract() == 0.0
+                    && val >= 0.0
+                    && self
+                        .members(set.var, subscript)
+                        .contains(&SetVal::Int(val as u32));
+                if !is_member {
+                    let rule = format!("values not in {param_in}");
+                    add_broken(rule, entry());
+                }
+            }
+        }
+
+        self.report(context.clone(), "keys outside its domain", outside);
+        for (rule, entries) in broken {
+            self.report(context.clone(), &rule, entries);
+        }
+    }
+
+    fn set(&mut self, set: &ir::Set, indexes: &[Index]) {
+        let name = intern_resolve(set.name);
+
+        // Indexed sets are checked for each index given in the data,
+        // plain sets for their (single) resolved value
+        let indexes: Vec<Index> = if set.dims.is_empty() {
+            vec![Index::new()]
+        } else {
+            let mut outside = Vec::new();
+            let mut inside = Vec::new();
+            for index in indexes {
+                let in_domain = index.len() == set.dims.len()
+                    && set
+                        .dims
+                        .iter()
+                        .zip(index.iter())
+                        .all(|(dim, val)| self.members(dim.set, Index::new()).contains(val));
+                if in_domain {
+                    inside.push(index.clone());
+                } else {
+                    outside.push(index_name(set.name, index));
+                }
+            }
+            self.report(format!("set {name}"), "indices outside its domain", outside);
+            inside
+        };
+
+        let Some(within) = &set.within else {
+            return;
+        };
+        let within = intern(within);
+        let cross = set.cross.as_deref().map(intern);
+        if !self.lookups.set_map.contains_key(&within)
+            || cross.is_some_and(|cross| !self.lookups.set_map.contains_key(&cross))
+        {
+            // Undeclared parents are already reported by `check_model`
+            return;
+        }
+        let rule = match cross {
+            Some(cross) => format!(
+                "members not within {} cross {}",
+                intern_resolve(within),
+                intern_resolve(cross)
+            ),
+            None => format!("members not within {}", intern_resolve(within)),
+        };
+
+        for index in indexes {
+            let values = self.lookups.set_map[&set.name].resolve(&index, self.lookups);
+            let offending = values
+                .iter()
+                .filter(|val| !self.is_within(**val, within, cross))
+                .map(|val| val.to_string())
+                .collect();
+            self.report(
+                format!("set {}", index_name(set.name, &index)),
+                &rule,
+                offending,
+            );
+        }
+    }
+
+    fn is_within(&mut self, val: SetVal, within: Spur, cross: Option<Spur>) -> bool {
+        match (cross, val) {
+            (None, val) => self.members(within, Index::new()).contains(&val),
+            (Some(cross), SetVal::Tuple([a, b])) => {
+                self.members(within, Index::new())
+                    .contains(&from_terminal(a))
+                    && self
+                        .members(cross, Index::new())
+                        .contains(&from_terminal(b))
+            }
+            (Some(_), _) => false,
+        }
+    }
+}
+
+fn to_terminal(val: &SetVal) -> Option<SetValTerminal> {
+    match val {
+        SetVal::Str(s) => Some(SetValTerminal::Str(*s)),
+        SetVal::Int(n) => Some(SetValTerminal::Int(*n)),
+        SetVal::Tuple(_) => None,
+    }
+}
+
+fn from_terminal(val: SetValTerminal) -> SetVal {
+    match val {
+        SetValTerminal::Str(s) => SetVal::Str(s),
+        SetValTerminal::Int(n) => SetVal::Int(n),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gmpl::loader::{consume, parse};
+
+    #[test]
+    fn test_validate_data() {
+        let text = r#"
+            set R;
+            set S within R;
+            set T{r in R} within R;
+            param cap{r in R} >= 0, integer;
+            param flag{r in R} binary;
+            var x{r in R} >= 0;
+            minimize cost: sum{r in R} x[r];
+            s.t. limit{r in R}: x[r] <= cap[r] * flag[r];
+            data;
+            set R := a b c;
+            set S := a d e;
+            set T[a] := b;
+            set T[z] := a;
+            param cap := a 1 b -2 c 1.5 q 3;
+            param flag := a 0 b 1 c 2;
+        "#;
+        let model = ModelWithData::from_entries(consume(parse(text)));
+        let messages: Vec<String> = validate_data(&model)
+            .into_iter()
+            .map(|d| d.message)
+            .collect();
+        assert_eq!(
+            messages,
+            vec![
+                "set S: members not within R: d, e",
+                "set T: indices outside its domain: T[z]",
+                "param cap: keys outside its domain: cap[q]",
+                "param cap: values breaking >= 0: cap[b] = -2",
+                "param cap: values that are not integer: cap[c] = 1.5",
+                "param flag: values that are not binary: flag[c] = 2",
+            ]
+        );
+
+        let messages: Vec<String> = validate_params(&model, &["flag"])
+            .into_iter()
+            .map(|d| d.message)
+            .collect();
+        assert_eq!(
+            messages,
+            vec!["param flag: values that are not binary: flag[c] = 2"]
+        );
+    }
+}
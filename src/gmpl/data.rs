@@ -0,0 +1,547 @@
+//! Hand-written streaming parser for data files.
+//!
+//! Data files can be far bigger than models (national-scale OSeMOSYS data runs to
+//! gigabytes), so rather than reading the whole file into a string and building a
+//! pest tree over it, this reads tokens straight from a `BufRead` and builds the
+//! data entries as it goes. It accepts the same statements as the data part of
+//! `grammar.pest`, which is still used for data sections inside model files.
+
+use std::io::BufRead;
+
+use crate::ir::interner::intern;
+use crate::ir::{
+    Entry, Index, ParamData, ParamDataBody, ParamDataRecord, ParamDataRow, ParamDataTable,
+    ParamDataTarget, ParamDataToken, ParamTabbing, SetData, SetVal, SetValTerminal, SetVals,
+};
+
+/// Parse a data file (with or without a leading `data;`)
+pub fn parse_data(reader: impl BufRead) -> Vec<Entry> {
+    let mut parser = Parser {
+        lexer: Lexer {
+            reader,
+            line: 1,
+            text: Vec::new(),
+            peeked: None,
+        },
+    };
+    let mut entries = Vec::new();
+    loop {
+        match parser.lexer.next() {
+            Tok::Eof => return entries,
+            Tok::Word => match parser.lexer.word() {
+                "data" | "end" => parser.expect(Tok::Semi),
+                "set" => entries.push(Entry::DataSet(parser.set())),
+                "param" => entries.push(parser.param()),
+                "include" => {
+                    parser.expect(Tok::Str);
+                    let path = parser.lexer.word().to_string();
+                    parser.expect(Tok::Semi);
+                    entries.push(Entry::Include(path));
+                }
+                other => {
+                    let other = other.to_string();
+                    parser.lexer.error(&format!("unexpected '{other}'"))
+                }
+            },
+            tok => parser.lexer.error(&format!("unexpected {tok:?}")),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Tok {
+    /// An id, number or `.`, with the text in `Lexer::text`
+    Word,
+    /// A quoted string, with the text (without quotes) in `Lexer::text`
+    Str,
+    Semi,
+    Colon,
+    Assign,
+    Comma,
+    LBracket,
+    RBracket,
+    LParen,
+    RParen,
+    LBrace,
+    RBrace,
+    Star,
+    Eof,
+}
+
+fn is_word(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || matches!(b, b'_' | b'.' | b'-' | b'+')
+}
+
+fn fill(reader: &mut impl BufRead) -> &[u8] {
+    match reader.fill_buf() {
+        Ok(buf) => buf,
+        Err(err) => panic!("cannot read data: {err}"),
+    }
+}
+
+struct Lexer<R> {
+    reader: R,
+    line: usize,
+    /// Text of the last word or string read
+    text: Vec<u8>,
+    peeked: Option<Tok>,
+}
+
+impl<R: BufRead> Lexer<R> {
+    fn error(&self, msg: &str) -> ! {
+        panic!("data line {}: {msg}", self.line)
+    }
+
+    fn buf(&mut self) -> &[u8] {
+        fill(&mut self.reader)
+    }
+
+    fn peek_byte(&mut self) -> Option<u8> {
+        self.buf().first().copied()
+    }
+
+    fn bump(&mut self) {
+        self.reader.consume(1);
+    }
+
+    fn word(&self) -> &str {
+        // Words are ASCII, strings are checked when they're read
+        std::str::from_utf8(&self.text).unwrap()
+    }
+
+    fn peek(&mut self) -> Tok {
+        match self.peeked {
+            Some(tok) => tok,
+            None => {
+                let tok = self.lex();
+                self.peeked = Some(tok);
+                tok
+            }
+        }
+    }
+
+    fn next(&mut self) -> Tok {
+        match self.peeked.take() {
+            Some(tok) => tok,
+            None => self.lex(),
+        }
+    }
+
+    fn lex(&mut self) -> Tok {
+        self.skip_space();
+        let Some(b) = self.peek_byte() else {
+            return Tok::Eof;
+        };
+        self.bump();
+        match b {
+            b';' => Tok::Semi,
+            b',' => Tok::Comma,
+            b'[' => Tok::LBracket,
+            b']' => Tok::RBracket,
+            b'(' => Tok::LParen,
+            b')' => Tok::RParen,
+            b'{' => Tok::LBrace,
+            b'}' => Tok::RBrace,
+            b'*' => Tok::Star,
+            b':' if self.peek_byte() == Some(b'=') => {
+                self.bump();
+                Tok::Assign
+            }
+            b':' => Tok::Colon,
+            b'"' | b'\'' => {
+                self.text.clear();
+                loop {
+                    match self.peek_byte() {
+                        None => self.error("unterminated string"),
+                        Some(c) if c == b => break,
+                        Some(c) => {
+                            self.line += usize::from(c == b'\n');
+                            self.text.push(c);
+                        }
+                    }
+                    self.bump();
+                }
+                self.bump();
+                if std::str::from_utf8(&self.text).is_err() {
+                    self.error("string is not valid UTF-8");
+                }
+                Tok::Str
+            }
+            b if is_word(b) => {
+                self.text.clear();
+                self.text.push(b);
+                loop {
+                    let buf = fill(&mut self.reader);
+                    let n = buf.iter().take_while(|c| is_word(**c)).count();
+                    let more = n == buf.len() && n > 0;
+                    self.text.extend_from_slice(&buf[..n]);
+                    self.reader.consume(n);
+                    if !more {
+                        return Tok::Word;
+                    }
+                }
+            }
+            b => self.error(&format!("unexpected character '{}'", b as char)),
+        }
+    }
+
+    /// Skip whitespace and comments
+    fn skip_space(&mut self) {
+        loop {
+            let buf = self.buf();
+            if buf.is_empty() {
+                return;
+            }
+            let n = buf.iter().take_while(|c| c.is_ascii_whitespace()).count();
+            let lines = buf[..n].iter().filter(|c| **c == b'\n').count();
+            let next = buf.get(n).copied();
+            self.reader.consume(n);
+            self.line += lines;
+            match next {
+                // Ran off the end of the buffer, keep going
+                None => {}
+                Some(b'#') => self.skip_line(),
+                Some(b'/') => {
+                    self.bump();
+                    if self.peek_byte() != Some(b'*') {
+                        self.error("unexpected character '/'");
+                    }
+                    self.bump();
+                    self.skip_block_comment();
+                }
+                Some(_) => return,
+            }
+        }
+    }
+
+    /// Skip to the end of the line, leaving the newline
+    fn skip_line(&mut self) {
+        loop {
+            let buf = self.buf();
+            if buf.is_empty() {
+                return;
+            }
+            match buf.iter().position(|c| *c == b'\n') {
+                Some(n) => {
+                    self.reader.consume(n);
+                    return;
+                }
+                None => {
+                    let n = buf.len();
+                    self.reader.consume(n);
+                }
+            }
+        }
+    }
+
+    fn skip_block_comment(&mut self) {
+        let mut star = false;
+        loop {
+            let Some(c) = self.peek_byte() else {
+                self.error("unterminated comment");
+            };
+            self.bump();
+            if star && c == b'/' {
+                return;
+            }
+            star = c == b'*';
+            self.line += usize::from(c == b'\n');
+        }
+    }
+}
+
+struct Parser<R> {
+    lexer: Lexer<R>,
+}
+
+impl<R: BufRead> Parser<R> {
+    fn expect(&mut self, expected: Tok) {
+        let tok = self.lexer.next();
+        if tok != expected {
+            self.lexer
+                .error(&format!("expected {expected:?}, found {tok:?}"));
+        }
+    }
+
+    fn peek_word(&mut self, word: &str) -> bool {
+        self.lexer.peek() == Tok::Word && self.lexer.word() == word
+    }
+
+    fn set_val(&mut self) -> SetVal {
+        self.expect(Tok::Word);
+        let word = self.lexer.word();
+        if !word.starts_with(|c: char| c.is_ascii_alphanumeric()) {
+            let word = word.to_string();
+            self.lexer
+                .error(&format!("expected a set value, found '{word}'"));
+        }
+        SetVal::parse(word)
+    }
+
+    fn terminal(&mut self) -> SetValTerminal {
+        match self.set_val() {
+            SetVal::Str(s) => SetValTerminal::Str(s),
+            SetVal::Int(n) => SetValTerminal::Int(n),
+            SetVal::Tuple(_) => unreachable!(),
+        }
+    }
+
+    fn number(&mut self) -> f64 {
+        self.expect(Tok::Word);
+        match self.lexer.word().parse() {
+            Ok(num) => num,
+            Err(_) => {
+                let word = self.lexer.word().to_string();
+                self.lexer
+                    .error(&format!("expected a number, found '{word}'"))
+            }
+        }
+    }
+
+    /// A table value: a number, or `.` for the default
+    fn value(&mut self) -> Option<f64> {
+        if self.peek_word(".") {
+            self.lexer.next();
+            None
+        } else {
+            Some(self.number())
+        }
+    }
+
+    fn token(&mut self) -> ParamDataToken {
+        self.expect(Tok::Word);
+        ParamDataToken::parse(self.lexer.word())
+    }
+
+    /// A run of tokens, which may be separated by commas
+    fn tokens(&mut self) -> Vec<ParamDataToken> {
+        let mut tokens = Vec::new();
+        loop {
+            match self.lexer.peek() {
+                Tok::Word => tokens.push(self.token()),
+                Tok::Comma => {
+                    self.lexer.next();
+                }
+                _ => return tokens,
+            }
+        }
+    }
+
+    /// `set NAME [index]? (:= values)? ;`, after the `set`
+    fn set(&mut self) -> SetData {
+        self.expect(Tok::Word);
+        let name = intern(self.lexer.word());
+
+        let mut index = Index::new();
+        if self.lexer.peek() == Tok::LBracket {
+            self.lexer.next();
+            index.push(self.set_val());
+            while self.lexer.peek() == Tok::Comma {
+                self.lexer.next();
+                index.push(self.set_val());
+            }
+            self.expect(Tok::RBracket);
+        }
+
+        let mut values = Vec::new();
+        if self.lexer.peek() == Tok::Assign {
+            self.lexer.next();
+            if self.lexer.peek() == Tok::LParen {
+                while self.lexer.peek() == Tok::LParen {
+                    self.lexer.next();
+                    let a = self.terminal();
+                    self.expect(Tok::Comma);
+                    let b = self.terminal();
+                    self.expect(Tok::RParen);
+                    values.push(SetVal::Tuple([a, b]));
+                }
+            } else {
+                while self.lexer.peek() == Tok::Word {
+                    values.push(self.set_val());
+                }
+            }
+        }
+        self.expect(Tok::Semi);
+
+        SetData {
+            name,
+            index,
+            values: SetVals(values),
+        }
+    }
+
+    /// A param data or tabbing statement, after the `param`
+    fn param(&mut self) -> Entry {
+        match self.lexer.peek() {
+            Tok::Colon => {
+                self.lexer.next();
+                return Entry::DataTabbing(self.tabbing(None));
+            }
+            _ if self.peek_word("default") => {
+                self.lexer.next();
+                let default = self.number();
+                self.expect(Tok::Colon);
+                return Entry::DataTabbing(self.tabbing(Some(default)));
+            }
+            _ => {}
+        }
+
+        self.expect(Tok::Word);
+        let name = intern(self.lexer.word());
+
+        // A domain on a data statement (eg `param Cost{REGION}`) is ignored
+        if self.lexer.peek() == Tok::LBrace {
+            while self.lexer.next() != Tok::RBrace {}
+        }
+        let mut default = None;
+        if self.peek_word("default") {
+            self.lexer.next();
+            default = Some(self.number());
+        }
+        if self.lexer.peek() == Tok::Assign {
+            self.lexer.next();
+        }
+
+        let mut records = Vec::new();
+        loop {
+            match self.lexer.peek() {
+                Tok::Semi => {
+                    self.lexer.next();
+                    break;
+                }
+                Tok::LBracket => records.push(ParamDataRecord::Slice(self.slice())),
+                Tok::LParen => {
+                    self.lexer.next();
+                    if !self.peek_word("tr") {
+                        self.lexer.error("expected (tr)");
+                    }
+                    self.lexer.next();
+                    self.expect(Tok::RParen);
+                    self.expect(Tok::Colon);
+                    records.push(ParamDataRecord::Table(self.table(true)));
+                }
+                Tok::Colon => {
+                    self.lexer.next();
+                    records.push(ParamDataRecord::Table(self.table(false)));
+                }
+                Tok::Comma => {
+                    self.lexer.next();
+                }
+                Tok::Word => records.push(ParamDataRecord::List(self.tokens())),
+                tok => self
+                    .lexer
+                    .error(&format!("unexpected {tok:?} in param data")),
+            }
+        }
+
+        let body = match records.as_slice() {
+            [] => None,
+            // A single number is a scalar param
+            [ParamDataRecord::List(tokens)] if tokens.len() == 1 => match tokens[0].value() {
+                Some(num) => Some(ParamDataBody::Num(num)),
+                None => self.lexer.error("expected a number"),
+            },
+            _ => Some(ParamDataBody::Records(records)),
+        };
+        Entry::DataParam(ParamData {
+            name,
+            default,
+            body,
+        })
+    }
+
+    /// `[a,*,b]`
+    fn slice(&mut self) -> Vec<ParamDataTarget> {
+        self.expect(Tok::LBracket);
+        let mut targets = Vec::new();
+        loop {
+            if self.lexer.peek() == Tok::Star {
+                self.lexer.next();
+                targets.push(ParamDataTarget::Any);
+            } else {
+                targets.push(ParamDataTarget::IndexVar(self.set_val()));
+            }
+            match self.lexer.next() {
+                Tok::Comma => {}
+                Tok::RBracket => return targets,
+                tok => self
+                    .lexer
+                    .error(&format!("expected ',' or ']', found {tok:?}")),
+            }
+        }
+    }
+
+    /// `c1 c2 := r1 v11 v12 r2 v21 v22`, after the `:`
+    fn table(&mut self, transposed: bool) -> ParamDataTable {
+        let mut cols = Vec::new();
+        while self.lexer.peek() == Tok::Word {
+            cols.push(self.set_val());
+        }
+        self.expect(Tok::Assign);
+
+        let mut rows = Vec::new();
+        while self.lexer.peek() == Tok::Word {
+            let label = self.set_val();
+            let values = (0..cols.len()).map(|_| self.value()).collect();
+            rows.push(ParamDataRow { label, values });
+        }
+        ParamDataTable {
+            transposed,
+            cols,
+            rows,
+        }
+    }
+
+    /// `SET : p1 p2 := tokens ;` (the set being optional), after the first `:`
+    fn tabbing(&mut self, default: Option<f64>) -> ParamTabbing {
+        let mut set = None;
+        let mut params = Vec::new();
+        loop {
+            match self.lexer.next() {
+                Tok::Word => params.push(intern(self.lexer.word())),
+                Tok::Comma => {}
+                Tok::Colon if set.is_none() && params.len() == 1 => set = params.pop(),
+                Tok::Assign => break,
+                tok => self
+                    .lexer
+                    .error(&format!("unexpected {tok:?} in tabbing data")),
+            }
+        }
+        let tokens = self.tokens();
+        self.expect(Tok::Semi);
+
+        ParamTabbing {
+            default,
+            set,
+            params,
+            tokens,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gmpl::loader::{consume, parse};
+
+    #[test]
+    fn test_same_as_grammar() {
+        let text = r#"
+            # A comment
+            set J := b c;  /* and a
+            block comment */
+            set K[a] := x y;
+            set PAIRS := (a, b) (c, 2014);
+            param p default 0 := a b y 5 [a,*,x] b 1 c -2.5e-1 [*,c,*] (tr) : a b := y 3 .;
+            param q : b c := a 1 . b 3 4;
+            param r{I} := a 1, b 2, [*] c 3;
+            param s := 7;
+            param default 1 : I : t, u := a 1 2, b 3 .;
+            include "other.dat";
+            end;
+        "#;
+        let grammar = consume(parse(&format!("data;\n{text}")));
+        let streamed = parse_data(text.as_bytes());
+        assert_eq!(format!("{streamed:?}"), format!("{grammar:?}"));
+    }
+}
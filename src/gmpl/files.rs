@@ -0,0 +1,135 @@
+//! Reading model and data files, from disk or from memory, and resolving
+//! `include` statements between them.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::{Component, Path, PathBuf};
+
+use crate::gmpl::{data, loader};
+use crate::ir::Entry;
+
+/// Somewhere to read model and data files from
+pub trait Files {
+    /// Open the file at `path` for reading, panicking if it doesn't exist
+    fn open(&self, path: &Path) -> Box<dyn BufRead + '_>;
+
+    /// Read the whole file at `path`
+    fn read(&self, path: &Path) -> String {
+        let mut text = String::new();
+        self.open(path)
+            .read_to_string(&mut text)
+            .unwrap_or_else(|err| panic!("cannot read file {}: {err}", path.display()));
+        text
+    }
+}
+
+/// Read files from disk
+pub struct Disk;
+
+impl Files for Disk {
+    fn open(&self, path: &Path) -> Box<dyn BufRead + '_> {
+        let file = File::open(path)
+            .unwrap_or_else(|err| panic!("cannot read file {}: {err}", path.display()));
+        // Data files can be large, so read them in bigger chunks than the default
+        Box::new(BufReader::with_capacity(1 << 20, file))
+    }
+}
+
+/// Files held in memory, keyed by path (eg `"data/base.dat"`)
+impl Files for HashMap<String, String> {
+    fn open(&self, path: &Path) -> Box<dyn BufRead + '_> {
+        let key = path.to_string_lossy();
+        match self.get(key.as_ref()) {
+            Some(text) => Box::new(text.as_bytes()),
+            None => panic!("cannot read file {key}: not in the file map"),
+        }
+    }
+}
+
+pub fn load_model(files: &impl Files, path: &str) -> Vec<Entry> {
+    let path = normalise(Path::new(path));
+    let text = files.read(&path);
+    let mut including = vec![path.clone()];
+    expand_includes(files, parse_model(&text), &path, &mut including)
+}
+
+pub fn load_data(files: &impl Files, path: &str) -> Vec<Entry> {
+    load_data_file(files, normalise(Path::new(path)), &mut vec![])
+}
+
+/// Parse model text, which may include a data section
+pub fn model_from_str(files: &impl Files, text: &str) -> Vec<Entry> {
+    expand_includes(files, parse_model(text), Path::new(""), &mut vec![])
+}
+
+/// Parse data text, with or without a leading `data;`
+pub fn data_from_str(files: &impl Files, text: &str) -> Vec<Entry> {
+    expand_includes(
+        files,
+        data::parse_data(text.as_bytes()),
+        Path::new(""),
+        &mut vec![],
+    )
+}
+
+/// Parse data from a reader, with or without a leading `data;`
+pub fn data_from_reader(files: &impl Files, reader: impl BufRead) -> Vec<Entry> {
+    expand_includes(files, data::parse_data(reader), Path::new(""), &mut vec![])
+}
+
+fn parse_model(text: &str) -> Vec<Entry> {
+    loader::consume(loader::parse(text))
+}
+
+fn load_data_file(files: &impl Files, path: PathBuf, including: &mut Vec<PathBuf>) -> Vec<Entry> {
+    if including.contains(&path) {
+        panic!("include cycle: {} is already being loaded", path.display());
+    }
+    // Data is streamed rather than read into memory, as it can be huge
+    let parsed = data::parse_data(files.open(&path));
+    including.push(path.clone());
+    let entries = expand_includes(files, parsed, &path, including);
+    including.pop();
+    entries
+}
+
+/// Replace `include` entries with the entries of the included file, resolving
+/// its path relative to the file (at `path`) that includes it
+fn expand_includes(
+    files: &impl Files,
+    entries: Vec<Entry>,
+    path: &Path,
+    including: &mut Vec<PathBuf>,
+) -> Vec<Entry> {
+    let dir = path.parent().unwrap_or(Path::new(""));
+    let mut expanded = Vec::with_capacity(entries.len());
+    for entry in entries {
+        match entry {
+            Entry::Include(file) => {
+                let path = normalise(&dir.join(file));
+                expanded.extend(load_data_file(files, path, including));
+            }
+            other => expanded.push(other),
+        }
+    }
+    expanded
+}
+
+/// Remove `.` and `..` from a path without touching the filesystem, so that the
+/// same file included in different ways has the same path (and key in a file map)
+fn normalise(path: &Path) -> PathBuf {
+    let mut out = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir
+                if matches!(out.components().next_back(), Some(Component::Normal(_))) =>
+            {
+                out.pop();
+            }
+            other => out.push(other),
+        }
+    }
+    out
+}
@@ -52,6 +52,17 @@ pub fn consume(entries: Pairs<'_, Rule>) -> Vec<Entry> {
                 debug!("DATA:PARAM");
                 dirs.push(Entry::DataParam(ir::ParamData::from_entry(entry)));
             }
+            Rule::PARAM_TABBING => {
+                debug!("DATA:TABBING");
+                dirs.push(Entry::DataTabbing(ir::ParamTabbing::from_entry(entry)));
+            }
+            Rule::INCLUDE => {
+                debug!("DATA:INCLUDE");
+                let literal = entry.into_inner().next().unwrap().as_str();
+                // Strip the quotes
+                let path = &literal[1..literal.len() - 1];
+                dirs.push(Entry::Include(path.to_string()));
+            }
 
             // Ignored
             Rule::END => {}
@@ -82,7 +93,7 @@ mod tests {
     #[test]
     fn test_parse() {
         let text = r#"set YEAR;"#;
-        let _entries = parse(&text);
+        let _entries = parse(text);
     }
 
     #[test]
@@ -91,7 +102,7 @@ mod tests {
         let text = r#"
             INVALID MODEL STUFF
         "#;
-        let entries = parse(&text);
+        let entries = parse(text);
         assert!(entries.len() == 1);
     }
 
@@ -100,7 +111,19 @@ mod tests {
         let text = r#"
             param DiscountRate{r in REGION};
         "#;
-        let entries = parse(&text);
+        let entries = parse(text);
         consume(entries);
     }
+
+    #[test]
+    fn test_data_commas() {
+        let data = |text: &str| {
+            let model = format!("set I; param p{{i in I}}; param q{{i in I}};\ndata;\n{text}");
+            format!("{:?}", consume(parse(&model)))
+        };
+        assert_eq!(
+            data("param p := a 1, b 2, [*] c 3;\nparam : q, p := a 1, 2, b 3, 4;"),
+            data("param p := a 1 b 2 [*] c 3;\nparam : q p := a 1 2 b 3 4;")
+        );
+    }
 }
@@ -1,2 +1,4 @@
+pub(crate) mod data;
+pub(crate) mod files;
 pub(crate) mod grammar;
 pub(crate) mod loader;
@@ -1,7 +1,7 @@
 use std::sync::LazyLock;
 
-use lasso::Spur;
 use lasso::ThreadedRodeo;
+use lasso::{Key, Spur};
 
 /// An interner using lasso: https://crates.io/crates/lasso
 /// Not actually used in any threaded context (that all ust operates on the Spurs)
@@ -17,3 +17,13 @@ pub fn intern(s: &str) -> Spur {
 pub fn intern_resolve(spur: Spur) -> &'static str {
     INTERNER.resolve(&spur)
 }
+
+/// All the interned strings, indexed by `Spur::into_usize`, for resolving many
+/// keys at once (eg when writing out a matrix) without locking the interner for each
+pub fn intern_snapshot() -> Vec<&'static str> {
+    let mut strings = vec![""; INTERNER.len()];
+    for (spur, s) in INTERNER.iter() {
+        strings[spur.into_usize()] = s;
+    }
+    strings
+}
@@ -1,8 +1,9 @@
 pub(crate) mod interner;
 pub(crate) mod model;
 pub(crate) mod op;
+pub(crate) mod refs;
 
-use std::fmt;
+use std::fmt::{self, Write};
 use std::ops::Deref;
 use std::sync::LazyLock;
 
@@ -56,8 +57,7 @@ impl Var {
             match pair.as_rule() {
                 Rule::name => name = Some(intern(pair.as_str())),
                 Rule::domain => domain = Some(Domain::from_entry(pair)),
-                // simple_domain is parsed but not used - var access uses index values from the set
-                Rule::simple_domain => {}
+                Rule::simple_domain => domain = Some(Domain::from_simple_domain(pair)),
                 Rule::var_bounds => bounds = Some(VarBounds::from_entry(pair)),
                 Rule::param_type => param_type = Some(ParamType::from_entry(pair)),
                 _ => {}
@@ -122,8 +122,7 @@ impl Param {
             match pair.as_rule() {
                 Rule::name => name = Some(intern(pair.as_str())),
                 Rule::domain => domain = Some(Domain::from_entry(pair)),
-                // simple_domain is parsed but not used - param access uses index values from the set
-                Rule::simple_domain => {}
+                Rule::simple_domain => domain = Some(Domain::from_simple_domain(pair)),
                 Rule::param_type => param_type = Some(ParamType::from_entry(pair)),
                 Rule::param_condition => conditions.push(ParamCondition::from_entry(pair)),
                 Rule::param_in => param_in = pair.into_inner().next().map(|p| Expr::from_entry(p)),
@@ -420,6 +419,8 @@ impl SetOf {
 pub struct Objective {
     pub sense: ObjSense,
     pub name: Spur,
+    /// Indexed objectives give one row per index, eg `cost{r in REGION}`
+    pub domain: Option<Domain>,
     pub expr: Expr,
 }
 
@@ -427,12 +428,14 @@ impl Objective {
     pub fn from_entry(entry: Pair<Rule>) -> Self {
         let mut sense = ObjSense::Minimize;
         let mut name: Option<Spur> = None;
+        let mut domain = None;
         let mut expr = None;
 
         for pair in entry.into_inner() {
             match pair.as_rule() {
                 Rule::obj_sense => sense = ObjSense::from_entry(pair),
                 Rule::id => name = Some(intern(pair.as_str())),
+                Rule::domain => domain = Some(Domain::from_entry(pair)),
                 Rule::expr => expr = Some(Expr::from_entry(pair)),
                 _ => {}
             }
@@ -441,6 +444,7 @@ impl Objective {
         Self {
             sense,
             name: name.unwrap(),
+            domain,
             expr: expr.unwrap(),
         }
     }
@@ -448,7 +452,11 @@ impl Objective {
 
 impl fmt::Display for Objective {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{} {}: <expr>", self.sense, intern_resolve(self.name))
+        write!(f, "{} {}", self.sense, intern_resolve(self.name))?;
+        if self.domain.is_some() {
+            write!(f, " <domain>")?;
+        }
+        write!(f, ": <expr>")
     }
 }
 
@@ -543,33 +551,80 @@ impl fmt::Display for SetData {
     }
 }
 
-/// Data parameter values
-#[derive(Clone, Debug)]
-pub struct ParamDataPair {
-    pub key: SetVal,
-    pub value: f64,
+/// A token in list format param data. Whether it's a key or a value is only
+/// known once the records are split by the dimension of the param
+#[derive(Clone, Copy, Debug)]
+pub enum ParamDataToken {
+    /// An id or integer, usable as a key or a value
+    Val(SetVal),
+    /// Any other number, only usable as a value
+    Num(f64),
+    /// `.` in place of a value: leave the key unset so the default applies
+    Skip,
 }
 
-impl ParamDataPair {
+impl ParamDataToken {
     pub fn from_entry(entry: Pair<Rule>) -> Self {
-        let mut tokens = entry.into_inner();
-        let key = tokens.next().unwrap().as_str();
-        let key = key
-            .parse::<u32>()
-            .map(SetVal::Int)
-            .unwrap_or_else(|_| SetVal::Str(intern(key)));
-        let value = tokens.next().unwrap().as_str().parse().unwrap();
-        Self { key, value }
+        Self::parse(entry.as_str())
+    }
+
+    pub fn parse(raw: &str) -> Self {
+        if raw == "." {
+            ParamDataToken::Skip
+        } else if let Ok(int) = raw.parse::<u32>() {
+            ParamDataToken::Val(SetVal::Int(int))
+        } else if raw.starts_with(|c: char| c.is_ascii_alphabetic()) {
+            ParamDataToken::Val(SetVal::Str(intern(raw)))
+        } else {
+            ParamDataToken::Num(raw.parse().unwrap())
+        }
+    }
+
+    pub fn key(&self) -> SetVal {
+        match self {
+            ParamDataToken::Val(val) => *val,
+            other => panic!("expected a key in param data, got {other}"),
+        }
+    }
+
+    /// The value, or None for `.`
+    pub fn value(&self) -> Option<f64> {
+        match self {
+            ParamDataToken::Val(SetVal::Int(int)) => Some(*int as f64),
+            ParamDataToken::Val(val) => panic!("expected a value in param data, got {val}"),
+            ParamDataToken::Num(num) => Some(*num),
+            ParamDataToken::Skip => None,
+        }
+    }
+}
+
+impl fmt::Display for ParamDataToken {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParamDataToken::Val(val) => write!(f, "{}", val),
+            ParamDataToken::Num(num) => write!(f, "{}", num),
+            ParamDataToken::Skip => write!(f, "."),
+        }
     }
 }
 
 #[derive(Clone, Debug)]
 pub enum ParamDataBody {
-    Tables(Vec<ParamDataTable>),
-    List(Vec<ParamDataPair>),
+    Records(Vec<ParamDataRecord>),
     Num(f64),
 }
 
+/// One record of param data, applied in order
+#[derive(Clone, Debug)]
+pub enum ParamDataRecord {
+    /// Eg `[Atlantis,*,*]`: fixes the key positions that aren't `*` for the
+    /// records after it
+    Slice(Vec<ParamDataTarget>),
+    Table(ParamDataTable),
+    /// Flat keys and values, eg `a 1 b 2` or `a x 1 a y 2` for a 2D param
+    List(Vec<ParamDataToken>),
+}
+
 #[derive(Clone, Debug)]
 pub struct ParamData {
     pub name: Spur,
@@ -609,11 +664,8 @@ impl fmt::Display for ParamData {
             write!(f, " default <value>")?;
         }
         match &self.body {
-            Some(ParamDataBody::Tables(tables)) => {
-                write!(f, " := <{} table(s)>", tables.len())?;
-            }
-            Some(ParamDataBody::List(pairs)) => {
-                write!(f, " := <{} pair(s)>", pairs.len())?;
+            Some(ParamDataBody::Records(records)) => {
+                write!(f, " := <{} record(s)>", records.len())?;
             }
             Some(ParamDataBody::Num(num)) => {
                 write!(f, " := {}", num)?;
@@ -624,12 +676,110 @@ impl fmt::Display for ParamData {
     }
 }
 
-/// Constraint expression (e.g., "expr <= expr")
+/// Several params given at once in tabbing format, eg
+/// `param default 0 : REGION : DiscountRate DepreciationMethod := Atlantis 0.05 1;`
+#[derive(Clone, Debug)]
+pub struct ParamTabbing {
+    pub default: Option<f64>,
+    /// Set that is assigned the keys of the records, if given
+    pub set: Option<Spur>,
+    pub params: Vec<Spur>,
+    pub tokens: Vec<ParamDataToken>,
+}
+
+impl ParamTabbing {
+    pub fn from_entry(entry: Pair<Rule>) -> Self {
+        let mut default = None;
+        let mut set = None;
+        let mut params = Vec::new();
+        let mut tokens = Vec::new();
+
+        for pair in entry.into_inner() {
+            match pair.as_rule() {
+                Rule::param_data_default => default = Some(pair.as_str().parse().unwrap()),
+                Rule::param_tabbing_set => set = Some(intern(pair.as_str())),
+                Rule::param_tabbing_params => {
+                    params = pair.into_inner().map(|p| intern(p.as_str())).collect();
+                }
+                Rule::param_tabbing_body => {
+                    tokens = pair.into_inner().map(ParamDataToken::from_entry).collect();
+                }
+                _ => {}
+            }
+        }
+
+        Self {
+            default,
+            set,
+            params,
+            tokens,
+        }
+    }
+
+    /// Split into one `ParamData` per param, plus the data for the key set if one
+    /// was given. `dimen` is the number of keys in each record.
+    pub fn split(self, dimen: usize) -> (Vec<ParamData>, Option<SetData>) {
+        let width = dimen + self.params.len();
+        if !self.tokens.len().is_multiple_of(width) {
+            panic!(
+                "tabbing data for {} has {} tokens, which doesn't split into records of {dimen} key(s) and {} value(s)",
+                self.params_str(),
+                self.tokens.len(),
+                self.params.len()
+            );
+        }
+
+        let records: Vec<&[ParamDataToken]> = self.tokens.chunks(width).collect();
+        let data = self
+            .params
+            .iter()
+            .enumerate()
+            .map(|(i, name)| {
+                let tokens = records
+                    .iter()
+                    .flat_map(|record| record[..dimen].iter().chain([&record[dimen + i]]))
+                    .copied()
+                    .collect();
+                ParamData {
+                    name: *name,
+                    default: self.default,
+                    body: Some(ParamDataBody::Records(vec![ParamDataRecord::List(tokens)])),
+                }
+            })
+            .collect();
+        let set = self.set.map(|name| SetData {
+            name,
+            index: smallvec![],
+            values: records
+                .iter()
+                .flat_map(|record| record[..dimen].iter().map(|token| token.key()))
+                .collect::<Vec<_>>()
+                .into(),
+        });
+        (data, set)
+    }
+
+    fn params_str(&self) -> String {
+        let names: Vec<&str> = self.params.iter().map(|p| intern_resolve(*p)).collect();
+        names.join(", ")
+    }
+}
+
+impl fmt::Display for ParamTabbing {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "data: param : {}", self.params_str())?;
+        write!(f, " := <{} token(s)>", self.tokens.len())
+    }
+}
+
+/// Constraint expression (e.g., "expr <= expr" or "lo <= expr <= hi")
 #[derive(Clone, Debug)]
 pub struct ConstraintExpr {
     pub lhs: Expr,
     pub op: RelOp,
     pub rhs: Expr,
+    /// The second relation of a double inequality, eg `<= hi` in `lo <= expr <= hi`
+    pub range: Option<(RelOp, Expr)>,
 }
 
 impl ConstraintExpr {
@@ -638,14 +788,27 @@ impl ConstraintExpr {
         let lhs = Expr::from_entry(pairs.next().unwrap());
         let op = RelOp::from_entry(pairs.next().unwrap());
         let rhs = Expr::from_entry(pairs.next().unwrap());
+        let range = pairs.next().map(|op| {
+            let op = RelOp::from_entry(op);
+            (op, Expr::from_entry(pairs.next().unwrap()))
+        });
 
-        Self { lhs, op, rhs }
+        Self {
+            lhs,
+            op,
+            rhs,
+            range,
+        }
     }
 }
 
 impl fmt::Display for ConstraintExpr {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "<expr> {} <expr>", self.op)
+        write!(f, "<expr> {} <expr>", self.op)?;
+        if let Some((op, _)) = &self.range {
+            write!(f, " {op} <expr>")?;
+        }
+        Ok(())
     }
 }
 
@@ -679,7 +842,7 @@ impl fmt::Display for ParamType {
 }
 
 /// Objective sense
-#[derive(Clone, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum ObjSense {
     Minimize,
     Maximize,
@@ -770,41 +933,25 @@ impl fmt::Display for ParamCondition {
 /// Parameter data table
 #[derive(Clone, Debug)]
 pub struct ParamDataTable {
-    pub target: Option<Vec<ParamDataTarget>>,
+    /// With `(tr)` the columns give the first free key position and the rows the second
+    pub transposed: bool,
     pub cols: Vec<SetVal>,
     pub rows: Vec<ParamDataRow>,
 }
 
 impl ParamDataTable {
     pub fn from_entry(entry: Pair<Rule>) -> Self {
-        let mut target = None;
+        let mut transposed = false;
         let mut cols: Vec<SetVal> = Vec::new();
         let mut rows = Vec::new();
 
         for pair in entry.into_inner() {
             match pair.as_rule() {
-                Rule::param_data_target => {
-                    let mut targets = Vec::new();
-                    for inner in pair.into_inner() {
-                        match inner.as_rule() {
-                            Rule::set_val => {
-                                targets.push(ParamDataTarget::IndexVar(SetVal::from_entry(inner)))
-                            }
-                            Rule::param_data_any => targets.push(ParamDataTarget::Any),
-                            _ => {}
-                        }
-                    }
-                    target = Some(targets);
-                }
+                Rule::param_data_tr => transposed = true,
                 Rule::param_data_cols => {
                     for inner in pair.into_inner() {
                         if inner.as_rule() == Rule::set_val {
-                            let raw = inner.as_str();
-                            let col = raw
-                                .parse::<u32>()
-                                .map(SetVal::Int)
-                                .unwrap_or_else(|_| SetVal::Str(intern(raw)));
-                            cols.push(col);
+                            cols.push(SetVal::parse(inner.as_str()));
                         }
                     }
                 }
@@ -813,14 +960,18 @@ impl ParamDataTable {
             }
         }
 
-        Self { target, cols, rows }
+        Self {
+            transposed,
+            cols,
+            rows,
+        }
     }
 }
 
 impl fmt::Display for ParamDataTable {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        if self.target.is_some() {
-            write!(f, " [<target>]")?;
+        if self.transposed {
+            write!(f, " (tr)")?;
         }
         write!(f, " {} cols, {} rows", self.cols.len(), self.rows.len())
     }
@@ -833,6 +984,19 @@ pub enum ParamDataTarget {
     Any,
 }
 
+impl ParamDataTarget {
+    pub fn from_slice(entry: Pair<Rule>) -> Vec<Self> {
+        entry
+            .into_inner()
+            .filter_map(|inner| match inner.as_rule() {
+                Rule::set_val => Some(ParamDataTarget::IndexVar(SetVal::from_entry(inner))),
+                Rule::param_data_any => Some(ParamDataTarget::Any),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
 impl fmt::Display for ParamDataTarget {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
@@ -846,7 +1010,8 @@ impl fmt::Display for ParamDataTarget {
 #[derive(Clone, Debug)]
 pub struct ParamDataRow {
     pub label: SetVal,
-    pub values: Vec<f64>,
+    /// None where the table has `.`
+    pub values: Vec<Option<f64>>,
 }
 
 impl ParamDataRow {
@@ -856,24 +1021,15 @@ impl ParamDataRow {
 
         for pair in entry.into_inner() {
             match pair.as_rule() {
-                Rule::set_val => {
-                    if label.is_none() {
-                        let raw = pair.as_str();
-                        label = Some(
-                            raw.parse::<u32>()
-                                .map(SetVal::Int)
-                                .unwrap_or_else(|_| SetVal::Str(intern(raw))),
-                        );
-                    }
-                }
+                Rule::set_val if label.is_none() => label = Some(SetVal::parse(pair.as_str())),
                 Rule::param_data_row_vals => {
                     for inner in pair.into_inner() {
                         if inner.as_rule() == Rule::param_data_val {
-                            values.push(inner.as_str().parse().unwrap_or(0.0));
+                            values.push(parse_param_data_val(inner.as_str()));
                         }
                     }
                 }
-                Rule::param_data_val => values.push(pair.as_str().parse().unwrap_or(0.0)),
+                Rule::param_data_val => values.push(parse_param_data_val(pair.as_str())),
                 _ => {}
             }
         }
@@ -885,6 +1041,13 @@ impl ParamDataRow {
     }
 }
 
+fn parse_param_data_val(raw: &str) -> Option<f64> {
+    match raw {
+        "." => None,
+        _ => Some(raw.parse().unwrap_or(0.0)),
+    }
+}
+
 impl fmt::Display for ParamDataRow {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "{} <{} values>", self.label, self.values.len())
@@ -905,6 +1068,9 @@ pub enum Entry {
     Constraint(Constraint),
     DataSet(SetData),
     DataParam(ParamData),
+    DataTabbing(ParamTabbing),
+    /// Path of a data file to include, replaced by its entries when loading
+    Include(String),
 }
 
 impl fmt::Display for Entry {
@@ -917,6 +1083,8 @@ impl fmt::Display for Entry {
             Entry::Constraint(c) => write!(f, "{}", c),
             Entry::DataSet(ds) => write!(f, "{}", ds),
             Entry::DataParam(dp) => write!(f, "{}", dp),
+            Entry::DataTabbing(dt) => write!(f, "{}", dt),
+            Entry::Include(path) => write!(f, "include \"{}\"", path),
         }
     }
 }
@@ -1134,6 +1302,13 @@ pub enum SetValTerminal {
 }
 
 impl SetVal {
+    /// An integer or an id, as written in data
+    pub fn parse(raw: &str) -> Self {
+        raw.parse::<u32>()
+            .map(SetVal::Int)
+            .unwrap_or_else(|_| SetVal::Str(intern(raw)))
+    }
+
     pub fn from_entry(entry: Pair<Rule>) -> Self {
         let inner = entry.into_inner().next().unwrap();
         match inner.as_rule() {
@@ -1168,6 +1343,10 @@ impl fmt::Display for SetValTerminal {
 pub struct Domain {
     pub parts: Vec<DomainPart>,
     pub condition: Option<LogicExpr>,
+    /// Identifies the domain to the expansions cached by `Lookups::prepare`, so
+    /// they still apply after the model is cloned or moved. Assigned when the
+    /// model is built from its entries; 0 means the domain isn't cached.
+    pub id: usize,
 }
 
 impl Domain {
@@ -1183,7 +1362,69 @@ impl Domain {
             }
         }
 
-        Self { parts, condition }
+        Self {
+            parts,
+            condition,
+            id: 0,
+        }
+    }
+
+    /// Parse a simple_domain (eg `{REGION, YEAR}`) as a domain, with unnamed parts
+    /// where no dummy index is given
+    pub fn from_simple_domain(entry: Pair<Rule>) -> Self {
+        let parts = entry
+            .into_inner()
+            .filter(|p| p.as_rule() == Rule::simple_domain_part)
+            .map(|p| {
+                let part = SetDomainPart::from_simple_domain_part(p);
+                DomainPart {
+                    var: DomainPartVar::Single(part.id.unwrap_or_else(|| intern(""))),
+                    set: part.set,
+                    subscript: Subscript::default(),
+                }
+            })
+            .collect();
+
+        Self {
+            parts,
+            condition: None,
+            id: 0,
+        }
+    }
+
+    /// Number of index values this domain produces, counting each tuple element
+    pub fn dimen(&self) -> usize {
+        self.parts
+            .iter()
+            .map(|part| match &part.var {
+                DomainPartVar::Single(_) => 1,
+                DomainPartVar::Tuple(ids) => ids.len(),
+            })
+            .sum()
+    }
+
+    /// Read an index of this domain as written in a row or column name (eg
+    /// `RE1,ID,2030`), grouping the values of tuple parts. `None` if it has
+    /// the wrong number of values.
+    pub fn parse_index(&self, raw: &str) -> Option<Index> {
+        let mut vals = raw.split(',').map(|val| SetVal::parse(val.trim()));
+        let mut index = Index::new();
+        for part in &self.parts {
+            let val = match &part.var {
+                DomainPartVar::Single(_) => vals.next()?,
+                DomainPartVar::Tuple(ids) if ids.len() == 2 => {
+                    let mut terminal = || match vals.next()? {
+                        SetVal::Str(s) => Some(SetValTerminal::Str(s)),
+                        SetVal::Int(n) => Some(SetValTerminal::Int(n)),
+                        SetVal::Tuple(_) => None,
+                    };
+                    SetVal::Tuple([terminal()?, terminal()?])
+                }
+                DomainPartVar::Tuple(_) => return None,
+            };
+            index.push(val);
+        }
+        vals.next().is_none().then_some(index)
     }
 }
 
@@ -1438,6 +1679,51 @@ impl From<Vec<SetVal>> for SetVals {
 /// Index
 pub type Index = SmallVec<[SetVal; 6]>;
 
+/// Format a symbol with its index as it appears in MPS output, eg `Cost[RE1,2030]`
+pub fn index_name(name: Spur, idx: &Index) -> String {
+    let mut out = intern_resolve(name).to_string();
+    if !idx.is_empty() {
+        out.push('[');
+        for (i, val) in idx.iter().enumerate() {
+            if i > 0 {
+                out.push(',');
+            }
+            write!(out, "{val}").unwrap();
+        }
+        out.push(']');
+    }
+    out
+}
+
+/// Whether `name` matches `glob`, where `*` is any run of characters and `?` any one
+pub(crate) fn glob_match(glob: &str, name: &str) -> bool {
+    let (glob, name): (Vec<char>, Vec<char>) = (glob.chars().collect(), name.chars().collect());
+    let (mut g, mut n) = (0, 0);
+    // Where to go back to if what follows the last `*` doesn't match
+    let mut star: Option<(usize, usize)> = None;
+    while n < name.len() {
+        match glob.get(g) {
+            Some('*') => {
+                star = Some((g, n));
+                g += 1;
+            }
+            Some(c) if *c == '?' || *c == name[n] => {
+                g += 1;
+                n += 1;
+            }
+            _ => match star {
+                Some((star_g, star_n)) => {
+                    g = star_g + 1;
+                    n = star_n + 1;
+                    star = Some((star_g, star_n + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    glob[g..].iter().all(|c| *c == '*')
+}
+
 /// Parse set_vals or set_tuples directly into SetVals
 fn parse_set_vals_or_tuples(pair: Pair<Rule>) -> SetVals {
     let mut values = Vec::new();
@@ -1490,25 +1776,23 @@ fn parse_set_assign(pair: Pair<Rule>) -> SetVals {
 
 /// Parse param_data_body into ParamDataBody (reused by Param and ParamData)
 fn parse_param_data_body(pair: Pair<Rule>) -> ParamDataBody {
-    let mut inner_pairs = pair.into_inner();
-    let first = inner_pairs.next().unwrap();
-
-    match first.as_rule() {
-        Rule::param_data_list => {
-            let pairs = first.into_inner().map(ParamDataPair::from_entry).collect();
-            ParamDataBody::List(pairs)
-        }
-        Rule::param_data_matrix => {
-            let mut tables = vec![ParamDataTable::from_entry(first)];
-            tables.extend(inner_pairs.map(ParamDataTable::from_entry));
-            ParamDataBody::Tables(tables)
-        }
-        Rule::param_data_scalar => {
-            let num: f64 = first.as_str().parse().unwrap();
-            ParamDataBody::Num(num)
+    let mut records = Vec::new();
+    for inner in pair.into_inner() {
+        match inner.as_rule() {
+            Rule::param_data_scalar => return ParamDataBody::Num(inner.as_str().parse().unwrap()),
+            Rule::param_data_target => {
+                records.push(ParamDataRecord::Slice(ParamDataTarget::from_slice(inner)))
+            }
+            Rule::param_data_matrix => {
+                records.push(ParamDataRecord::Table(ParamDataTable::from_entry(inner)))
+            }
+            Rule::param_data_list => records.push(ParamDataRecord::List(
+                inner.into_inner().map(ParamDataToken::from_entry).collect(),
+            )),
+            _ => unreachable!(),
         }
-        _ => unreachable!(),
     }
+    ParamDataBody::Records(records)
 }
 
 /// Subscript (array indexing with optional shifts)
@@ -4,9 +4,12 @@ use std::fmt;
 use lasso::Spur;
 use smallvec::smallvec;
 
+use crate::analysis::Diagnostic;
 use crate::ir::{
-    Constraint, ConstraintExpr, Domain, Entry, Expr, Objective, Param, ParamAssign, ParamData, Set,
-    SetData, SetVal, SetValTerminal, SetVals, Var, intern_resolve, op::RowType,
+    Constraint, ConstraintExpr, Domain, Entry, Expr, FuncSum, Index, LogicExpr, MathOp, ObjSense,
+    Objective, Param, ParamAssign, ParamData, ParamDataBody, ParamDataRecord, ParamDataToken,
+    RelOp, Set, SetData, SetVal, SetValTerminal, SetVals, Var, glob_match, intern, intern_resolve,
+    op::RowType,
 };
 
 /// A set declaration with optional data
@@ -28,17 +31,19 @@ impl fmt::Display for SetWithData {
     }
 }
 
-/// A parameter declaration with optional data
+/// A parameter declaration with its data
 #[derive(Clone, Debug)]
 pub struct ParamWithData {
     pub decl: Param,
-    pub data: Option<ParamData>,
+    /// Data statements in the order given, each overriding the ones before key by key
+    /// (eg a scenario file on top of a base file)
+    pub data: Vec<ParamData>,
 }
 
 impl fmt::Display for ParamWithData {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "{}", self.decl)?;
-        if let Some(data) = &self.data {
+        for data in &self.data {
             write!(f, "\n  {}", data)?;
         }
         Ok(())
@@ -52,6 +57,42 @@ pub struct ConstraintOrObjective {
     pub row_type: RowType,
     pub lhs: Expr,
     pub rhs: Expr,
+    /// The upper bound of a double inequality `rhs <= lhs <= upper`, whose
+    /// `row_type` is then `>=`
+    pub upper: Option<Expr>,
+    /// The relations as written, eg `[<=, <=]` for `lo <= expr <= hi`
+    pub ops: Vec<RelOp>,
+    /// The index of the row of an indexed objective to write first (eg `RE1` of
+    /// `cost[RE1]`), which is then generated ahead of the others rather than in
+    /// domain order
+    pub active: Option<Index>,
+    /// Whether an objective is minimised or maximised, `None` for a constraint
+    pub sense: Option<ObjSense>,
+}
+
+impl ConstraintOrObjective {
+    /// Why the relations as written can't make a row: `<>` (which is only for
+    /// conditions), or a double inequality that doesn't go the same way twice
+    pub fn relation_error(&self) -> Option<String> {
+        if let Some(op) = self
+            .ops
+            .iter()
+            .find(|op| matches!(op, RelOp::Ne | RelOp::Ne2))
+        {
+            return Some(format!("{op} is not allowed in a constraint"));
+        }
+        match self.ops.as_slice() {
+            [op, op2]
+                if RowType::from_rel_op(op) == Some(RowType::Equal)
+                    || RowType::from_rel_op(op) != RowType::from_rel_op(op2) =>
+            {
+                Some(format!(
+                    "a double inequality needs <= or >= on both sides, not {op} and {op2}"
+                ))
+            }
+            _ => None,
+        }
+    }
 }
 
 impl fmt::Display for ConstraintOrObjective {
@@ -61,7 +102,11 @@ impl fmt::Display for ConstraintOrObjective {
             write!(f, " <domain>")?;
         }
         write!(f, ": {}", self.lhs)?;
-        write!(f, ": {}", self.rhs)
+        write!(f, ": {}", self.rhs)?;
+        if let Some(upper) = &self.upper {
+            write!(f, ": {upper}")?;
+        }
+        Ok(())
     }
 }
 
@@ -70,6 +115,8 @@ pub struct ModelWithData {
     pub sets: Vec<SetWithData>,
     pub vars: Vec<Var>,
     pub pars: Vec<ParamWithData>,
+    /// The constraints and then the objectives. The first objective is written
+    /// as the first `N` row, the others as free rows.
     pub constraints: Vec<ConstraintOrObjective>,
 }
 
@@ -98,32 +145,40 @@ impl fmt::Display for ModelWithData {
 impl ModelWithData {
     /// Build a ModelWithData from a list of entries, matching data to model statements
     pub fn from_entries(entries: Vec<Entry>) -> Self {
-        let mut objective = None;
+        let mut objectives = Vec::new();
         let mut sets = Vec::new();
         let mut params = Vec::new();
         let mut vars = Vec::new();
         let mut constraints = Vec::new();
         let mut data_sets = Vec::new();
         let mut data_params = Vec::new();
+        let mut data_tabbings = Vec::new();
 
         // First pass: separate model and data entries
         for entry in entries {
             match entry {
-                Entry::Objective(obj) => {
-                    if objective.is_some() {
-                        panic!("Multiple objectives found");
-                    }
-                    objective = Some(obj);
-                }
+                Entry::Objective(obj) => objectives.push(obj),
                 Entry::Set(set) => sets.push(set),
                 Entry::Param(param) => params.push(param),
                 Entry::Var(var) => vars.push(var),
                 Entry::Constraint(constraint) => constraints.push(constraint),
                 Entry::DataSet(data_set) => data_sets.push(data_set),
                 Entry::DataParam(data_param) => data_params.push(data_param),
+                Entry::DataTabbing(tabbing) => data_tabbings.push(tabbing),
+                Entry::Include(path) => {
+                    panic!("include \"{path}\" should have been resolved when loading the data")
+                }
             }
         }
 
+        // Split tabbing data into data for each param, now that their dimensions are known
+        for tabbing in data_tabbings {
+            let dimen = param_dimen(&params, tabbing.params[0]);
+            let (data, set) = tabbing.split(dimen);
+            data_params.extend(data);
+            data_sets.extend(set);
+        }
+
         // Convert inline set data to SetData entries
         for set in &sets {
             if let Some(ref inline_data) = set.inline_data {
@@ -135,16 +190,17 @@ impl ModelWithData {
             }
         }
 
-        // Convert inline param data to ParamData entries
-        for param in &params {
-            if let Some(ParamAssign::Data(ref body)) = param.assign {
-                data_params.push(ParamData {
-                    name: param.name,
-                    default: None,
-                    body: Some(body.clone()),
-                });
-            }
-        }
+        // Convert inline param data to ParamData entries,
+        // ahead of the data section so that it can be overridden there
+        let inline_params = params.iter().filter_map(|param| match &param.assign {
+            Some(ParamAssign::Data(body)) => Some(ParamData {
+                name: param.name,
+                default: None,
+                body: Some(body.clone()),
+            }),
+            _ => None,
+        });
+        let data_params: Vec<ParamData> = inline_params.chain(data_params).collect();
 
         // Group data sets by name
         let mut data_set_map: HashMap<Spur, Vec<SetData>> = HashMap::new();
@@ -180,36 +236,31 @@ impl ModelWithData {
             );
         }
 
-        let mut param_map: HashMap<Spur, Param> = HashMap::new();
-        for param in params {
-            param_map.insert(param.name, param);
+        let mut data_param_map: HashMap<Spur, Vec<ParamData>> = HashMap::new();
+        for data_param in data_params {
+            data_param_map
+                .entry(data_param.name)
+                .or_default()
+                .push(data_param);
         }
 
-        // Match data params to model params
+        // Match data params to model params, keeping declaration order
         let mut matched_params = Vec::new();
-        for data_param in data_params {
-            if let Some(param_decl) = param_map.remove(&data_param.name) {
-                matched_params.push(ParamWithData {
-                    decl: param_decl,
-                    data: Some(data_param),
-                });
-            } else {
-                panic!(
-                    "Data param '{}' has no matching model declaration",
-                    intern_resolve(data_param.name)
-                );
-            }
+        for param in params {
+            let data = data_param_map.remove(&param.name).unwrap_or_default();
+            matched_params.push(ParamWithData { decl: param, data });
         }
 
-        // Add remaining params without data
-        for (_, param_decl) in param_map {
-            matched_params.push(ParamWithData {
-                decl: param_decl,
-                data: None,
-            });
+        // Check for orphaned data params
+        if let Some((name, _)) = data_param_map.into_iter().next() {
+            panic!(
+                "Data param '{}' has no matching model declaration",
+                intern_resolve(name)
+            );
         }
 
-        let all_constraints = prep_constraints(objective.unwrap(), constraints);
+        let mut all_constraints = prep_constraints(objectives, constraints);
+        number_domains(&mut all_constraints);
 
         ModelWithData {
             sets: matched_sets,
@@ -220,41 +271,328 @@ impl ModelWithData {
     }
 }
 
+impl ModelWithData {
+    /// Remove the constraints whose names match `glob` (eg `E8_*`), returning how
+    /// many there were. Objectives are never removed.
+    pub fn drop_constraints(&mut self, glob: &str) -> usize {
+        let before = self.constraints.len();
+        self.constraints.retain(|con| {
+            con.row_type == RowType::Unconstrained || !glob_match(glob, intern_resolve(con.name))
+        });
+        before - self.constraints.len()
+    }
+
+    /// Set a param's value at `index`, as if a data file after all the others set it
+    pub fn set_param(
+        &mut self,
+        name: &str,
+        index: &[impl AsRef<str>],
+        value: f64,
+    ) -> Result<(), Diagnostic> {
+        let Some(param) = self
+            .pars
+            .iter_mut()
+            .find(|param| intern_resolve(param.decl.name) == name)
+        else {
+            return Err(Diagnostic::error(format!("there's no param named {name}")));
+        };
+        if let Some(ParamAssign::Expr(_)) = param.decl.assign {
+            return Err(Diagnostic::error(format!(
+                "{name} is computed from an expression, so it can't be set"
+            )));
+        }
+        let dimen = param.decl.domain.as_ref().map_or(0, Domain::dimen);
+        if index.len() != dimen {
+            return Err(Diagnostic::error(format!(
+                "{name} has {dimen} indices, but {} were given",
+                index.len()
+            )));
+        }
+
+        let body = match dimen {
+            0 => ParamDataBody::Num(value),
+            _ => {
+                let mut tokens: Vec<ParamDataToken> = index
+                    .iter()
+                    .map(|val| ParamDataToken::Val(SetVal::parse(val.as_ref())))
+                    .collect();
+                tokens.push(ParamDataToken::Num(value));
+                ParamDataBody::Records(vec![ParamDataRecord::List(tokens)])
+            }
+        };
+        param.data.push(ParamData {
+            name: param.decl.name,
+            default: None,
+            body: Some(body),
+        });
+        Ok(())
+    }
+
+    /// Make `name` the objective, written as the first `N` row (which solvers
+    /// minimise, as no `OBJSENSE` is written), and the others as free rows. For
+    /// an indexed objective, `name` can be one of its rows (eg `cost[RE1]`), or
+    /// else its first row is used. Whether that row is in the objective's
+    /// domain depends on the data, so it's left to `validate_objective`.
+    pub fn select_objective(&mut self, name: &str) -> Result<(), Diagnostic> {
+        let row: String = name.chars().filter(|c| !c.is_whitespace()).collect();
+        let (name, index) = match row.split_once('[') {
+            Some((name, index)) => {
+                let Some(index) = index.strip_suffix(']') else {
+                    return Err(Diagnostic::error(format!("missing ']' in '{row}'")));
+                };
+                if index.contains(['[', ']']) {
+                    return Err(Diagnostic::error(format!("stray bracket in '{row}'")));
+                }
+                (name, Some(index))
+            }
+            None => (row.as_str(), None),
+        };
+        let Some(at) = self.objective_position(name) else {
+            return Err(Diagnostic::error(format!(
+                "there's no objective named {name}"
+            )));
+        };
+        let active = match index {
+            None => None,
+            Some(index) => {
+                let domain = self.constraints[at].domain.as_ref();
+                let Some(active) = domain.and_then(|domain| domain.parse_index(index)) else {
+                    let dimen = domain.map_or(0, Domain::dimen);
+                    let given = index.split(',').count();
+                    return Err(Diagnostic::error(format!(
+                        "{name} has {dimen} indices, but {given} were given"
+                    )));
+                };
+                Some(active)
+            }
+        };
+        let mut objective = self.constraints.remove(at);
+        objective.active = active;
+        self.constraints.insert(self.objectives_start(), objective);
+        Ok(())
+    }
+
+    /// Add the objective `name` as the sum of other objectives times their
+    /// weights, eg `cost` 1 and `emissions` 50, and minimise it. Objectives that
+    /// are maximised are weighted negatively, so minimising the sum maximises them.
+    pub fn weight_objectives(
+        &mut self,
+        name: &str,
+        weights: &[(impl AsRef<str>, f64)],
+    ) -> Result<(), Diagnostic> {
+        if self
+            .constraints
+            .iter()
+            .any(|con| intern_resolve(con.name) == name)
+        {
+            return Err(Diagnostic::error(format!(
+                "there's already a constraint or objective named {name}"
+            )));
+        }
+        let mut expr: Option<Expr> = None;
+        for (objective, weight) in weights {
+            let objective = objective.as_ref();
+            let Some(at) = self.objective_position(objective) else {
+                return Err(Diagnostic::error(format!(
+                    "there's no objective named {objective}"
+                )));
+            };
+            // All the rows of an indexed objective are weighted the same. The
+            // cloned domains keep their ids, as they expand the same way here.
+            let ConstraintOrObjective {
+                domain, lhs, sense, ..
+            } = &self.constraints[at];
+            let weight = match sense {
+                Some(ObjSense::Maximize) => -weight,
+                _ => *weight,
+            };
+            let objective = match domain {
+                Some(domain) => Expr::FuncSum(Box::new(FuncSum {
+                    domain: domain.clone(),
+                    operand: Box::new(lhs.clone()),
+                })),
+                None => lhs.clone(),
+            };
+            let term = Expr::BinOp {
+                lhs: Box::new(Expr::Number(weight)),
+                op: MathOp::Mul,
+                rhs: Box::new(objective),
+            };
+            expr = Some(match expr {
+                Some(sum) => Expr::BinOp {
+                    lhs: Box::new(sum),
+                    op: MathOp::Add,
+                    rhs: Box::new(term),
+                },
+                None => term,
+            });
+        }
+        let Some(expr) = expr else {
+            return Err(Diagnostic::error(format!(
+                "{name} has no objectives to weight"
+            )));
+        };
+        self.constraints.insert(
+            self.objectives_start(),
+            objective_row(intern(name), None, expr, ObjSense::Minimize),
+        );
+        Ok(())
+    }
+
+    fn objective_position(&self, name: &str) -> Option<usize> {
+        self.constraints.iter().position(|con| {
+            con.row_type == RowType::Unconstrained && intern_resolve(con.name) == name
+        })
+    }
+
+    /// Where the objectives start, after the constraints
+    fn objectives_start(&self) -> usize {
+        self.constraints
+            .iter()
+            .position(|con| con.row_type == RowType::Unconstrained)
+            .unwrap_or(self.constraints.len())
+    }
+}
+
 fn prep_constraints(
-    objective: Objective,
+    objectives: Vec<Objective>,
     constraints: Vec<Constraint>,
 ) -> Vec<ConstraintOrObjective> {
     let mut all: Vec<ConstraintOrObjective> = constraints
         .into_iter()
         .map(|Constraint { name, domain, expr }| {
-            let ConstraintExpr { lhs, rhs, op } = expr;
+            let ConstraintExpr {
+                lhs,
+                op,
+                rhs,
+                range,
+            } = expr;
+            let Some((op2, third)) = range else {
+                return ConstraintOrObjective {
+                    name,
+                    domain,
+                    // `<>` has no row type, but is reported by `relation_error`
+                    row_type: RowType::from_rel_op(&op).unwrap_or(RowType::Equal),
+                    lhs,
+                    rhs,
+                    upper: None,
+                    ops: vec![op],
+                    active: None,
+                    sense: None,
+                };
+            };
+            // The middle expression is the row, between the outer two. If the
+            // relations don't go the same way, that's reported by `relation_error`.
+            let (lower, upper) = match RowType::from_rel_op(&op) {
+                Some(RowType::GreaterThanOrEqual) => (third, lhs),
+                _ => (lhs, third),
+            };
             ConstraintOrObjective {
                 name,
                 domain,
-                row_type: RowType::from_rel_op(&op),
-                lhs,
-                rhs,
+                row_type: RowType::GreaterThanOrEqual,
+                lhs: rhs,
+                rhs: lower,
+                upper: Some(upper),
+                ops: vec![op, op2],
+                active: None,
+                sense: None,
             }
         })
         .collect();
-    let Objective {
-        name,
-        expr,
-        sense: _,
-    } = objective;
-    all.push(ConstraintOrObjective {
+    all.extend(objectives.into_iter().map(|objective| {
+        let Objective {
+            name,
+            domain,
+            expr,
+            sense,
+        } = objective;
+        objective_row(name, domain, expr, sense)
+    }));
+    all
+}
+
+/// Give each domain in the constraints and objectives its own id, so that
+/// `Lookups::prepare` can cache their expansions
+fn number_domains(constraints: &mut [ConstraintOrObjective]) {
+    fn domain(domain: &mut Domain, next: &mut usize) {
+        *next += 1;
+        domain.id = *next;
+        if let Some(cond) = &mut domain.condition {
+            logic(cond, next);
+        }
+    }
+    fn expr(e: &mut Expr, next: &mut usize) {
+        match e {
+            Expr::Number(_) | Expr::VarSubscripted(_) => {}
+            Expr::FuncSum(func) => {
+                domain(&mut func.domain, next);
+                expr(&mut func.operand, next);
+            }
+            Expr::FuncMin(func) => domain(&mut func.domain, next),
+            Expr::FuncMax(func) => domain(&mut func.domain, next),
+            Expr::Conditional(cond) => {
+                logic(&mut cond.condition, next);
+                expr(&mut cond.then_expr, next);
+                if let Some(else_expr) = &mut cond.else_expr {
+                    expr(else_expr, next);
+                }
+            }
+            Expr::UnaryNeg(inner) => expr(inner, next),
+            Expr::BinOp { lhs, op: _, rhs } => {
+                expr(lhs, next);
+                expr(rhs, next);
+            }
+        }
+    }
+    fn logic(l: &mut LogicExpr, next: &mut usize) {
+        match l {
+            LogicExpr::Comparison { lhs, op: _, rhs } => {
+                expr(lhs, next);
+                expr(rhs, next);
+            }
+            LogicExpr::BoolOp { lhs, op: _, rhs } => {
+                logic(lhs, next);
+                logic(rhs, next);
+            }
+        }
+    }
+
+    let mut next = 0;
+    for con in constraints {
+        if let Some(d) = &mut con.domain {
+            domain(d, &mut next);
+        }
+        expr(&mut con.lhs, &mut next);
+        expr(&mut con.rhs, &mut next);
+        if let Some(upper) = &mut con.upper {
+            expr(upper, &mut next);
+        }
+    }
+}
+
+fn objective_row(
+    name: Spur,
+    domain: Option<Domain>,
+    expr: Expr,
+    sense: ObjSense,
+) -> ConstraintOrObjective {
+    ConstraintOrObjective {
         name,
-        domain: None,
+        domain,
         row_type: RowType::Unconstrained,
         lhs: expr,
         rhs: Expr::Number(0.0),
-    });
-    all
+        upper: None,
+        ops: vec![],
+        active: None,
+        sense: Some(sense),
+    }
 }
 
 /// Regroup flat set values into tuples based on dimension
 /// e.g., with dimen=2: [A, 1, B, 2, ...] -> [(A,1), (B,2), ...]
-fn regroup_set_values(values: &SetVals, dimen: usize) -> SetVals {
+pub(crate) fn regroup_set_values(values: &SetVals, dimen: usize) -> SetVals {
     // If already tuples or dimen is 1, return as-is
     if dimen <= 1 {
         return values.clone();
@@ -289,3 +627,14 @@ fn regroup_set_values(values: &SetVals, dimen: usize) -> SetVals {
 
     SetVals(tuples)
 }
+
+/// Number of keys the data for param `name` has
+pub(crate) fn param_dimen(params: &[Param], name: Spur) -> usize {
+    let Some(param) = params.iter().find(|p| p.name == name) else {
+        panic!(
+            "Data param '{}' has no matching model declaration",
+            intern_resolve(name)
+        );
+    };
+    param.domain.as_ref().map_or(0, Domain::dimen)
+}
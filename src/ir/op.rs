@@ -5,25 +5,63 @@ use std::fmt;
 
 use crate::ir::{self, RelOp};
 
-#[derive(Copy, Clone, Debug)]
+/// Bounds on a var, infinite on the sides it isn't bounded
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub struct Bounds {
-    pub op: BoundsOp,
-    pub val: Option<f64>,
+    pub lower: f64,
+    pub upper: f64,
 }
 
 impl Bounds {
+    pub const FREE: Bounds = Bounds {
+        lower: f64::NEG_INFINITY,
+        upper: f64::INFINITY,
+    };
+
     pub fn from_gmpl_bounds(bounds: Option<ir::VarBounds>) -> Self {
-        match bounds {
-            Some(bounds) => Bounds {
-                op: BoundsOp::from_rel_op(&bounds.op),
-                val: Some(bounds.value),
+        let Some(bounds) = bounds else {
+            return Bounds::FREE;
+        };
+        let val = bounds.value;
+        // `<` and `>` are taken as `<=` and `>=`, like GLPK does
+        match bounds.op {
+            RelOp::Lt | RelOp::Le => Bounds {
+                lower: f64::NEG_INFINITY,
+                upper: val,
+            },
+            RelOp::Eq | RelOp::EqEq => Bounds {
+                lower: val,
+                upper: val,
             },
-            None => Bounds {
-                op: BoundsOp::Free,
-                val: None,
+            RelOp::Ge | RelOp::Gt => Bounds {
+                lower: val,
+                upper: f64::INFINITY,
             },
+            // Reported by `check_model`
+            op @ (RelOp::Ne | RelOp::Ne2) => panic!("{op} is not allowed in var bounds"),
         }
     }
+
+    /// The MPS bound entries that give these bounds, leaving out a lower
+    /// bound of zero as that's the MPS default. A var with only an upper bound
+    /// (eg `var v <= 5`) just has its `UP` entry.
+    pub fn mps_entries(self) -> impl Iterator<Item = (BoundsOp, Option<f64>)> {
+        let Bounds { lower, upper } = self;
+        let entries: [Option<(BoundsOp, Option<f64>)>; 2] = if lower == upper {
+            [Some((BoundsOp::Fixed, Some(lower))), None]
+        } else if lower == f64::NEG_INFINITY && upper == f64::INFINITY {
+            [Some((BoundsOp::Free, None)), None]
+        } else {
+            [
+                match lower {
+                    0.0 | f64::NEG_INFINITY => None,
+                    _ => Some((BoundsOp::Lower, Some(lower))),
+                },
+                (upper < f64::INFINITY).then_some((BoundsOp::Upper, Some(upper))),
+            ]
+        };
+        entries.into_iter().flatten()
+    }
 }
 
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
@@ -34,21 +72,6 @@ pub enum BoundsOp {
     Fixed,
 }
 
-impl BoundsOp {
-    pub fn from_rel_op(op: &ir::RelOp) -> Self {
-        match op {
-            ir::RelOp::Lt => panic!("Less than not supported"),
-            ir::RelOp::Le => BoundsOp::Upper,
-            ir::RelOp::Eq => BoundsOp::Fixed,
-            ir::RelOp::EqEq => BoundsOp::Fixed,
-            ir::RelOp::Ne => panic!("Not equal not supported"),
-            ir::RelOp::Ne2 => panic!("Not equal not supported"),
-            ir::RelOp::Ge => BoundsOp::Lower,
-            ir::RelOp::Gt => panic!("Greater than not supported"),
-        }
-    }
-}
-
 impl fmt::Display for BoundsOp {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
@@ -65,6 +88,9 @@ pub enum RowType {
     LessThanOrEqual,
     Equal,
     GreaterThanOrEqual,
+    /// A double inequality `lo <= expr <= hi`, with `lo` as the RHS and this
+    /// width (`hi - lo`) to the upper side. Written as a `G` row plus a RANGES entry.
+    Ranged(f64),
     /// Used for the objective function
     Unconstrained,
 }
@@ -74,23 +100,44 @@ impl fmt::Display for RowType {
         match self {
             RowType::LessThanOrEqual => write!(f, "L"),
             RowType::Equal => write!(f, "E"),
-            RowType::GreaterThanOrEqual => write!(f, "G"),
+            RowType::GreaterThanOrEqual | RowType::Ranged(_) => write!(f, "G"),
             RowType::Unconstrained => write!(f, "N"),
         }
     }
 }
 
 impl RowType {
-    pub fn from_rel_op(op: &RelOp) -> Self {
+    /// The relation as written in GMPL, eg `<=` (the objective has none)
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            RowType::LessThanOrEqual => "<=",
+            RowType::Equal => "=",
+            RowType::GreaterThanOrEqual => ">=",
+            // Its RHS is the lower bound
+            RowType::Ranged(_) => ">=",
+            RowType::Unconstrained => "",
+        }
+    }
+
+    /// The lowest and highest value a row with this RHS can take
+    pub fn bounds(&self, rhs: f64) -> (f64, f64) {
+        match self {
+            RowType::LessThanOrEqual => (f64::NEG_INFINITY, rhs),
+            RowType::Equal => (rhs, rhs),
+            RowType::GreaterThanOrEqual => (rhs, f64::INFINITY),
+            RowType::Ranged(width) => (rhs, rhs + width),
+            RowType::Unconstrained => (f64::NEG_INFINITY, f64::INFINITY),
+        }
+    }
+
+    /// `<` and `>` are taken as `<=` and `>=`, like GLPK does. `<>` has no
+    /// row type.
+    pub fn from_rel_op(op: &RelOp) -> Option<Self> {
         match op {
-            RelOp::Lt => panic!("Less than not supported"),
-            RelOp::Le => RowType::LessThanOrEqual,
-            RelOp::Eq => RowType::Equal,
-            RelOp::EqEq => RowType::Equal,
-            RelOp::Ne => panic!("Not equal not supported"),
-            RelOp::Ne2 => panic!("Not equal not supported"),
-            RelOp::Ge => RowType::GreaterThanOrEqual,
-            RelOp::Gt => panic!("Greater than not supported"),
+            RelOp::Lt | RelOp::Le => Some(RowType::LessThanOrEqual),
+            RelOp::Eq | RelOp::EqEq => Some(RowType::Equal),
+            RelOp::Ge | RelOp::Gt => Some(RowType::GreaterThanOrEqual),
+            RelOp::Ne | RelOp::Ne2 => None,
         }
     }
 }
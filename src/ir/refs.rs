@@ -0,0 +1,155 @@
+//! Walks the IR to find which sets, params and vars a statement refers to.
+
+use std::collections::HashSet;
+
+use lasso::Spur;
+
+use crate::ir::interner::intern;
+use crate::ir::model::ConstraintOrObjective;
+use crate::ir::{
+    Domain, DomainPartVar, Expr, LogicExpr, Param, ParamAssign, Set, SetExpr, SetValue,
+};
+
+/// Collects the symbols referenced by expressions, skipping the dummy indices
+/// bound by an enclosing domain (eg `y` in `sum{y in YEAR} Cost[y]`)
+#[derive(Default)]
+pub struct Refs {
+    pub names: HashSet<Spur>,
+    bound: Vec<Spur>,
+}
+
+impl Refs {
+    /// All symbols referenced by a constraint or objective, including its domain
+    pub fn of_constraint(con: &ConstraintOrObjective) -> HashSet<Spur> {
+        let mut refs = Refs::default();
+        let body = |refs: &mut Self| {
+            refs.expr(&con.lhs);
+            refs.expr(&con.rhs);
+            if let Some(upper) = &con.upper {
+                refs.expr(upper);
+            }
+        };
+        match &con.domain {
+            Some(domain) => refs.domain(domain, body),
+            None => body(&mut refs),
+        }
+        refs.names
+    }
+
+    /// All symbols referenced by a param declaration (domain, default, assign and conditions)
+    pub fn of_param(param: &Param) -> HashSet<Spur> {
+        let mut refs = Refs::default();
+        match &param.domain {
+            Some(domain) => refs.domain(domain, |refs| refs.param_body(param)),
+            None => refs.param_body(param),
+        }
+        refs.names
+    }
+
+    /// All symbols referenced by a set declaration (indexing sets, within, expr and default)
+    pub fn of_set(set: &Set) -> HashSet<Spur> {
+        let mut refs = Refs::default();
+        for part in &set.dims {
+            refs.names.insert(part.set);
+            if let Some(id) = part.id {
+                refs.bound.push(id);
+            }
+        }
+        if let Some(within) = &set.within {
+            refs.names.insert(intern(within));
+        }
+        if let Some(cross) = &set.cross {
+            refs.names.insert(intern(cross));
+        }
+        if let Some(expr) = &set.expr {
+            refs.set_expr(expr);
+        }
+        if let Some(SetValue::Expr(expr)) = &set.default {
+            refs.set_expr(expr);
+        }
+        refs.names
+    }
+
+    fn param_body(&mut self, param: &Param) {
+        if let Some(default) = &param.default {
+            self.expr(default);
+        }
+        if let Some(ParamAssign::Expr(expr)) = &param.assign {
+            self.expr(expr);
+        }
+        if let Some(expr) = &param.param_in {
+            self.expr(expr);
+        }
+        for cond in &param.conditions {
+            self.expr(&cond.value);
+        }
+    }
+
+    /// Visit the domain's sets and condition, then run `inner` with its dummies bound
+    pub fn domain(&mut self, domain: &Domain, inner: impl FnOnce(&mut Self)) {
+        let depth = self.bound.len();
+        for part in &domain.parts {
+            self.names.insert(part.set);
+            match &part.var {
+                DomainPartVar::Single(id) => self.bound.push(*id),
+                DomainPartVar::Tuple(ids) => self.bound.extend(ids),
+            }
+        }
+        if let Some(cond) = &domain.condition {
+            self.logic(cond);
+        }
+        inner(self);
+        self.bound.truncate(depth);
+    }
+
+    pub fn expr(&mut self, expr: &Expr) {
+        match expr {
+            Expr::Number(_) => {}
+            Expr::VarSubscripted(v) => {
+                if !(v.subscript.is_empty() && self.bound.contains(&v.var)) {
+                    self.names.insert(v.var);
+                }
+            }
+            Expr::FuncSum(func) => self.domain(&func.domain, |refs| refs.expr(&func.operand)),
+            Expr::FuncMin(func) => self.domain(&func.domain, |_| {}),
+            Expr::FuncMax(func) => self.domain(&func.domain, |_| {}),
+            Expr::Conditional(cond) => {
+                self.logic(&cond.condition);
+                self.expr(&cond.then_expr);
+                if let Some(else_expr) = &cond.else_expr {
+                    self.expr(else_expr);
+                }
+            }
+            Expr::UnaryNeg(inner) => self.expr(inner),
+            Expr::BinOp { lhs, op: _, rhs } => {
+                self.expr(lhs);
+                self.expr(rhs);
+            }
+        }
+    }
+
+    pub fn logic(&mut self, logic: &LogicExpr) {
+        match logic {
+            LogicExpr::Comparison { lhs, op: _, rhs } => {
+                self.expr(lhs);
+                self.expr(rhs);
+            }
+            LogicExpr::BoolOp { lhs, op: _, rhs } => {
+                self.logic(lhs);
+                self.logic(rhs);
+            }
+        }
+    }
+
+    pub fn set_expr(&mut self, expr: &SetExpr) {
+        match expr {
+            SetExpr::Domain(domain) => self.domain(domain, |_| {}),
+            SetExpr::SetMath(set_math) => {
+                for v in &set_math.intersection {
+                    self.names.insert(v.var);
+                }
+            }
+            SetExpr::SetOf(set_of) => self.domain(&set_of.domain, |_| {}),
+        }
+    }
+}
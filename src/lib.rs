@@ -2,45 +2,93 @@
 //!
 //! `mosox` is a GMPL parser and matrix generator.
 
+mod analysis;
 mod gmpl;
 mod ir;
 mod matrix;
 mod mps;
+#[cfg(feature = "python")]
+mod python;
 
+use std::io::{BufReader, Read};
 use std::path::Path;
 
-use crate::gmpl::loader;
+use crate::analysis::graph::Graph;
+use crate::analysis::{check, validate};
+use crate::gmpl::files;
 use crate::ir::Entry;
-use crate::ir::model::ModelWithData;
 use crate::matrix::{Compiled, gen_matrix};
 use crate::mps::output::print_mps;
 
+pub use crate::analysis::graph::NodeKind;
+pub use crate::analysis::{Diagnostic, Severity};
+pub use crate::gmpl::files::{Disk, Files};
+pub use crate::ir::model::ModelWithData;
+pub use crate::matrix::GenOptions;
+pub use crate::matrix::explain::{Explanation, Traced};
+pub use crate::matrix::incremental::{ChangeKind, Generator, RowChange, Update};
+pub use crate::matrix::param::ParamMemo;
+pub use crate::matrix::reduce::{Reduced, Reduction};
+pub use crate::matrix::stats::{Family, Range, Stats};
+
 /// Loads the GMPL model file at `path` into an internal representation
 pub fn load_model(path: &str) -> Vec<Entry> {
-    let text = std::fs::read_to_string(path).expect("cannot read file");
-    let pairs = loader::parse(&text);
-    loader::consume(pairs)
+    files::load_model(&Disk, path)
 }
 
-/// Loads the GMPL data file at `path` into an internal representation
+/// Loads the GMPL data file at `path` into an internal representation.
+/// `include "other.dat";` statements are replaced by the entries of that file.
 pub fn load_data(path: &str) -> Vec<Entry> {
-    let text = std::fs::read_to_string(path).expect("cannot read file");
+    files::load_data(&Disk, path)
+}
+
+/// Parse GMPL model text (which may also have a data section).
+/// Any `include`s are read from disk, relative to the current directory.
+///
+/// ```
+/// use mosox::{generate_matrix, merge_model, parse_model};
+/// let entries = parse_model("var x >= 0; minimize cost: x; s.t. c: x >= 1;");
+/// let compiled = generate_matrix(merge_model(entries));
+/// assert_eq!(compiled.cons.len(), 2);
+/// ```
+pub fn parse_model(text: &str) -> Vec<Entry> {
+    files::model_from_str(&Disk, text)
+}
+
+/// Parse GMPL data text, with or without a leading `data;`.
+/// Any `include`s are read from disk, relative to the current directory.
+pub fn parse_data(text: &str) -> Vec<Entry> {
+    files::data_from_str(&Disk, text)
+}
+
+/// Read and parse a GMPL model, eg from stdin or a network stream
+pub fn read_model(mut reader: impl Read) -> Vec<Entry> {
+    let mut text = String::new();
+    reader.read_to_string(&mut text).expect("cannot read model");
+    parse_model(&text)
+}
+
+/// Read and parse GMPL data, eg from stdin or a network stream.
+/// The data is parsed as it's read, so it's never all held in memory.
+pub fn read_data(reader: impl Read) -> Vec<Entry> {
+    files::data_from_reader(&Disk, BufReader::new(reader))
+}
 
-    // The grammar expects (at least one) `data;` statement to separate model from data
-    // But GMPL allows it to be omitted from a .dat file, so insert it to be safe
-    let prefixed = format!("data;\n{text}");
-    let pairs = loader::parse(&prefixed);
-    loader::consume(pairs)
+/// Load a model and any number of data files from `files`, which can be `Disk`
+/// or an in-memory `HashMap<String, String>` of path to file contents.
+/// Data files are applied in order, so a later file overrides the param values
+/// (key by key) and set values of earlier ones.
+pub fn load_model_and_data_from(files: &impl Files, path: &str, data_paths: &[&str]) -> Vec<Entry> {
+    let mut entries = files::load_model(files, path);
+    for data_path in data_paths {
+        entries.extend(files::load_data(files, data_path));
+    }
+    entries
 }
 
-/// Load model and data, calling `load_model` and `load_data`.
-pub fn load_model_and_data(path: &str, data_path: Option<&str>) -> Vec<Entry> {
-    let model_entries = load_model(path);
-    let data_entries = match data_path {
-        Some(data_path) => load_data(data_path),
-        None => vec![],
-    };
-    model_entries.into_iter().chain(data_entries).collect()
+/// Load a model and any number of data files from disk, see `load_model_and_data_from`.
+pub fn load_model_and_data(path: &str, data_paths: &[&str]) -> Vec<Entry> {
+    load_model_and_data_from(&Disk, path, data_paths)
 }
 
 /// Merge raw model and data into a `ModelWithData`.
@@ -48,9 +96,68 @@ pub fn merge_model(entries: Vec<Entry>) -> ModelWithData {
     ModelWithData::from_entries(entries)
 }
 
+/// Build the graph of which declarations refer to which sets, params and vars.
+pub fn dependency_graph(model: &ModelWithData) -> Graph {
+    Graph::from_model(model)
+}
+
+/// Check the merged model for semantic errors (subscript counts, unbound indices,
+/// misplaced vars, non-linear terms) before generating anything.
+pub fn check_model(model: &ModelWithData) -> Vec<Diagnostic> {
+    check::check_model(model)
+}
+
+/// Check the data against the model: keys outside param domains, values breaking
+/// param conditions, types or `in`, and set members not `within` their parent set.
+/// Only meaningful once `check_model` reports no errors.
+pub fn validate_data(model: &ModelWithData) -> Vec<Diagnostic> {
+    validate::validate_data(model)
+}
+
+/// Like `validate_data`, but only for the data of the params in `names`, eg
+/// after `ModelWithData::set_param` has overridden them.
+pub fn validate_params(model: &ModelWithData, names: &[impl AsRef<str>]) -> Vec<Diagnostic> {
+    validate::validate_params(model, names)
+}
+
+/// Check that the row of an indexed objective picked by `select_objective` (eg
+/// `cost[RE1]`) is in the objective's domain, with the sets restricted by
+/// `options`. Like `validate_data` this needs the resolved sets, but it only
+/// builds them if a row was picked. Run it once `GenOptions::check` passes.
+pub fn validate_objective(model: &ModelWithData, options: &GenOptions) -> Vec<Diagnostic> {
+    validate::validate_objective(model, options)
+}
+
 /// Convert merged model to matrix.
 pub fn generate_matrix(model: ModelWithData) -> Compiled {
-    gen_matrix(model)
+    gen_matrix(model, &GenOptions::default())
+}
+
+/// Convert merged model to matrix with options: how computed param values are
+/// kept (evaluated lazily as they're used, or all up front), and which
+/// constraints and set members to generate. Check the options against the model
+/// with `GenOptions::check` first.
+pub fn generate_matrix_with(model: ModelWithData, options: &GenOptions) -> Compiled {
+    gen_matrix(model, options)
+}
+
+/// Generate the row named `row` (as in the MPS output, eg `supply_limit[P1]`)
+/// again, tracing the param values behind each coefficient and the RHS.
+pub fn explain_row(model: ModelWithData, row: &str) -> Result<Explanation, Diagnostic> {
+    matrix::explain::explain_row(model, row)
+}
+
+/// Remove empty rows, turn rows on a single var into bounds and fix vars that are
+/// in no constraint. The returned report says what was removed, and has errors
+/// for any rows or bounds that can't hold.
+pub fn reduce_matrix(compiled: &mut Compiled) -> Reduction {
+    compiled.reduce()
+}
+
+/// Row and column counts per constraint and var, nonzeros, and the ranges of
+/// the coefficients, RHS and bounds.
+pub fn matrix_stats(compiled: &Compiled) -> Stats {
+    compiled.stats()
 }
 
 /// Print matrix in MPS format to stdout.
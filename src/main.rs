@@ -5,9 +5,13 @@
 use std::process::ExitCode;
 use std::time::Instant;
 
-use clap::{Parser, Subcommand};
+use clap::{Parser, Subcommand, ValueEnum};
 
-use mosox::{generate_matrix, load_model_and_data, matrix_to_mps, merge_model, stem};
+use mosox::{
+    Diagnostic, GenOptions, ModelWithData, check_model, dependency_graph, explain_row,
+    generate_matrix, generate_matrix_with, load_model_and_data, matrix_stats, matrix_to_mps,
+    merge_model, reduce_matrix, stem, validate_data, validate_objective, validate_params,
+};
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
@@ -22,7 +26,8 @@ enum Commands {
     /// Check for errors and quit
     Check {
         path: String,
-        data_path: Option<String>,
+        /// Data files, applied in order (later files override earlier ones)
+        data_paths: Vec<String>,
         /// Display full Debug output instead of concise Display output
         #[arg(short, long)]
         verbose: bool,
@@ -30,45 +35,243 @@ enum Commands {
     /// Load and output to MPS
     Generate {
         path: String,
-        data_path: Option<String>,
+        /// Data files, applied in order (later files override earlier ones)
+        data_paths: Vec<String>,
+        /// Drop empty rows, turn rows on a single var into bounds and fix
+        /// vars that are in no constraint
+        #[arg(long)]
+        reduce: bool,
+        /// Write what --reduce removed to this file, one line per row or var,
+        /// then the constant the fixed vars add to the objective
+        #[arg(long, requires = "reduce")]
+        report: Option<String>,
+        /// Only build the constraints matching this glob, eg 'EBa11_*' (can be
+        /// repeated). Objectives are always built.
+        #[arg(long, value_name = "GLOB")]
+        only: Vec<String>,
+        /// Only use these members of a set wherever it appears, eg REGION=RE1,RE2
+        /// (can be repeated)
+        #[arg(long, value_name = "SET=MEMBERS", value_parser = parse_restrict)]
+        restrict: Vec<(String, Vec<String>)>,
+        /// Leave out the constraints matching this glob, eg 'E8_*' (can be repeated)
+        #[arg(long, value_name = "NAME")]
+        drop_constraint: Vec<String>,
+        /// Override a param value, eg 'Demand[RE1,2030]=5' (can be repeated)
+        #[arg(long, value_name = "NAME[INDEX]=VALUE", value_parser = parse_set_param)]
+        set_param: Vec<ParamOverride>,
+        /// The objective to write as the first N row (the first one by default),
+        /// or one row of an indexed objective, eg 'cost[RE1]'. The others are
+        /// written as free rows. No OBJSENSE is written, so solvers minimise it
+        /// even if it's declared maximize.
+        #[arg(long, value_name = "NAME")]
+        objective: Option<String>,
+    },
+    /// Show how a single row was generated: its indices, the param values behind
+    /// each coefficient, and the constants folded into the RHS
+    Explain {
+        path: String,
+        /// Data files, applied in order (later files override earlier ones)
+        data_paths: Vec<String>,
+        /// The row as named in the MPS output, eg 'EBa11_EnergyBalanceEachTS5[RE1,ID,ELC,2030]'
+        #[arg(long)]
+        row: String,
+    },
+    /// Print row and column counts and coefficient ranges of the generated matrix
+    Stats {
+        path: String,
+        /// Data files, applied in order (later files override earlier ones)
+        data_paths: Vec<String>,
+    },
+    /// Output the graph of references between sets, params, vars and constraints
+    Graph {
+        path: String,
+        /// Data files, applied in order (later files override earlier ones)
+        data_paths: Vec<String>,
+        #[arg(short, long, value_enum, default_value_t = GraphFormat::Dot)]
+        format: GraphFormat,
+        /// Only list the constraints that depend on this set or param
+        #[arg(long)]
+        uses: Option<String>,
     },
 }
 
+#[derive(Clone, Copy, ValueEnum)]
+enum GraphFormat {
+    Dot,
+    Json,
+}
+
+fn parse_restrict(arg: &str) -> Result<(String, Vec<String>), String> {
+    let Some((set, members)) = arg.split_once('=') else {
+        return Err("expected SET=MEMBER[,MEMBER...]".to_string());
+    };
+    let members = members.split(',').map(str::to_string).collect();
+    Ok((set.to_string(), members))
+}
+
+#[derive(Clone)]
+struct ParamOverride {
+    name: String,
+    index: Vec<String>,
+    value: f64,
+}
+
+fn parse_set_param(arg: &str) -> Result<ParamOverride, String> {
+    let Some((target, value)) = arg.rsplit_once('=') else {
+        return Err("expected NAME[INDEX,...]=VALUE".to_string());
+    };
+    let value = value
+        .trim()
+        .parse()
+        .map_err(|_| format!("'{value}' isn't a number"))?;
+    let (name, index) = match target.split_once('[') {
+        Some((name, index)) => {
+            let Some(index) = index.strip_suffix(']') else {
+                return Err(format!("missing ']' in '{target}'"));
+            };
+            (
+                name,
+                index.split(',').map(|val| val.trim().to_string()).collect(),
+            )
+        }
+        None => (target, vec![]),
+    };
+    Ok(ParamOverride {
+        name: name.trim().to_string(),
+        index,
+        value,
+    })
+}
+
+/// Apply the scenario switches from the command line to the model
+fn apply_overrides(
+    model: &mut ModelWithData,
+    drop_constraint: &[String],
+    set_param: &[ParamOverride],
+    objective: Option<&str>,
+) -> bool {
+    let mut diags = vec![];
+    for glob in drop_constraint {
+        if model.drop_constraints(glob) == 0 {
+            diags.push(Diagnostic::error(format!("no constraint matches {glob}")));
+        }
+    }
+    for over in set_param {
+        if let Err(diag) = model.set_param(&over.name, &over.index, over.value) {
+            diags.push(diag);
+        }
+    }
+    if let Some(objective) = objective
+        && let Err(diag) = model.select_objective(objective)
+    {
+        diags.push(diag);
+    }
+    report(diags)
+}
+
 fn set_exit() -> ExitCode {
     ExitCode::SUCCESS
 }
 
+fn load(path: &str, data_paths: &[String]) -> ModelWithData {
+    let data_paths: Vec<&str> = data_paths.iter().map(String::as_str).collect();
+    merge_model(load_model_and_data(path, &data_paths))
+}
+
+/// Print the diagnostics, returning false if there were any errors
+fn report(diags: Vec<Diagnostic>) -> bool {
+    for diag in &diags {
+        eprintln!("{diag}");
+    }
+    !diags.iter().any(|d| d.is_error())
+}
+
 fn main() -> ExitCode {
     env_logger::init();
     let cli = Cli::parse();
     match &cli.command {
         Commands::Check {
             path,
-            data_path,
+            data_paths,
             verbose,
         } => {
-            let entries = load_model_and_data(path, data_path.as_deref());
-            let model = merge_model(entries);
+            let model = load(path, data_paths);
 
             // Print the model
             if *verbose {
                 println!("{:#?}", model);
             }
+            if !report(check_model(&model)) || !report(validate_data(&model)) {
+                return ExitCode::FAILURE;
+            }
             set_exit()
         }
-        Commands::Generate { path, data_path } => {
+        Commands::Generate {
+            path,
+            data_paths,
+            reduce,
+            report: report_path,
+            only,
+            restrict,
+            drop_constraint,
+            set_param,
+            objective,
+        } => {
             let t_total = Instant::now();
 
             let t0 = Instant::now();
-            let entries = load_model_and_data(path, data_path.as_deref());
-            let model = merge_model(entries);
+            let mut model = load(path, data_paths);
+            if !apply_overrides(&mut model, drop_constraint, set_param, objective.as_deref()) {
+                return ExitCode::FAILURE;
+            }
+            // The overridden values are checked like data, eg for keys outside
+            // the param's domain
+            let overridden: Vec<&str> = set_param.iter().map(|over| over.name.as_str()).collect();
+            if !report(check_model(&model)) || !report(validate_params(&model, &overridden)) {
+                return ExitCode::FAILURE;
+            }
+            let options = GenOptions {
+                only: only.clone(),
+                restrict: restrict.clone(),
+                ..GenOptions::default()
+            };
+            if !report(options.check(&model)) || !report(validate_objective(&model, &options)) {
+                return ExitCode::FAILURE;
+            }
 
             eprintln!("load: {:?}", t0.elapsed());
 
             let t1 = Instant::now();
-            let compiled = generate_matrix(model);
+            let mut compiled = generate_matrix_with(model, &options);
             eprintln!("compile: {:?}", t1.elapsed());
 
+            if *reduce {
+                let t = Instant::now();
+                let reduction = reduce_matrix(&mut compiled);
+                eprintln!(
+                    "reduce: {:?} ({} removed)",
+                    t.elapsed(),
+                    reduction.reduced.len()
+                );
+                if let Some(report_path) = report_path {
+                    let mut lines: String = reduction
+                        .reduced
+                        .iter()
+                        .map(|reduced| format!("{reduced}\n"))
+                        .collect();
+                    if reduction.objective_constant != 0.0 {
+                        lines.push_str(&format!("constant {}\n", reduction.objective_constant));
+                    }
+                    if let Err(err) = std::fs::write(report_path, lines) {
+                        eprintln!("error: couldn't write {report_path}: {err}");
+                        return ExitCode::FAILURE;
+                    }
+                }
+                if !report(reduction.diagnostics) {
+                    return ExitCode::FAILURE;
+                }
+            }
+
             let t2 = Instant::now();
             matrix_to_mps(compiled, stem(path));
             eprintln!("print: {:?}", t2.elapsed());
@@ -76,5 +279,54 @@ fn main() -> ExitCode {
             eprintln!("total: {:?}", t_total.elapsed());
             set_exit()
         }
+        Commands::Explain {
+            path,
+            data_paths,
+            row,
+        } => {
+            let model = load(path, data_paths);
+            if !report(check_model(&model)) {
+                return ExitCode::FAILURE;
+            }
+            match explain_row(model, row) {
+                Ok(explanation) => print!("{explanation}"),
+                Err(diag) => {
+                    report(vec![diag]);
+                    return ExitCode::FAILURE;
+                }
+            }
+            set_exit()
+        }
+        Commands::Stats { path, data_paths } => {
+            let model = load(path, data_paths);
+            if !report(check_model(&model)) {
+                return ExitCode::FAILURE;
+            }
+            let stats = matrix_stats(&generate_matrix(model));
+            print!("{stats}");
+            report(stats.warnings());
+            set_exit()
+        }
+        Commands::Graph {
+            path,
+            data_paths,
+            format,
+            uses,
+        } => {
+            let model = load(path, data_paths);
+            let graph = dependency_graph(&model);
+
+            if let Some(name) = uses {
+                for user in graph.users_of(name) {
+                    println!("{user}");
+                }
+            } else {
+                match format {
+                    GraphFormat::Dot => print!("{}", graph.to_dot()),
+                    GraphFormat::Json => print!("{}", graph.to_json()),
+                }
+            }
+            set_exit()
+        }
     }
 }
@@ -0,0 +1,810 @@
+//! Constraint expressions compiled once into a flat list of stack instructions,
+//! then run for each index of the constraint's domain.
+//!
+//! Symbols are resolved to vars, params or indices at compile time, and params
+//! are bound to their data, so running a program doesn't look anything up by
+//! name. Values are linear expressions: a constant and a run of (var, coeff)
+//! pairs. The pairs of every value on the stack live in one shared buffer, in
+//! stack order, so adding two values is only a matter of popping the top one.
+//!
+//! The same programs are run with a trace to explain a row, which keeps the
+//! params and conditions behind each term alongside the values.
+
+use std::collections::HashMap;
+use std::ops::{Range, RangeBounds};
+
+use lasso::Spur;
+
+use crate::ir::interner::intern_resolve;
+use crate::ir::{
+    BoolOp, Domain, Expr, Index, LogicExpr, MathOp, RelOp, SetVal, Subscript, SubscriptShift,
+    index_name,
+};
+use crate::matrix::constraint::{
+    IdxValMap, Pair, domain_to_indexes, eval_func_minmax, get_index_map, idx_extend, idx_get,
+};
+use crate::matrix::explain::{Traced, logic_source};
+use crate::matrix::lookup::Lookups;
+use crate::matrix::param::{Param, ParamVal};
+
+/// A compiled expression
+pub struct Program<'a> {
+    ops: Vec<Op<'a>>,
+}
+
+enum Op<'a> {
+    Num(f64),
+    /// A scalar param, which is only a number but is named in a trace
+    Scalar(Spur, f64),
+    /// A var at the given subscript, with a coefficient of one
+    Var(Spur, Vec<Slot<'a>>),
+    /// A param value, or if the param has no value at the subscript (or no data
+    /// at all), the result of the next `fallback` ops (its default) which are
+    /// skipped otherwise
+    Param {
+        name: Spur,
+        table: Option<&'a HashMap<Index, f64>>,
+        subscript: Vec<Slot<'a>>,
+        fallback: usize,
+    },
+    /// A computed param, evaluated once per index and shared
+    Computed {
+        name: Spur,
+        param: &'a Param,
+        expr: &'a Expr,
+        subscript: Vec<Slot<'a>>,
+    },
+    /// The current value of an index, eg `y` in `y - yy`
+    Index(Spur),
+    /// A param without any value or default, which panics if it's ever used
+    Uninit,
+    Neg,
+    Math(MathOp),
+    /// Sum the results of running the next `body` ops for each index of the domain
+    Sum {
+        domain: &'a Domain,
+        body: usize,
+    },
+    MinMax {
+        domain: &'a Domain,
+        var: Spur,
+        is_min: bool,
+    },
+    Cmp(RelOp),
+    And,
+    Or,
+    /// Pop a condition and run either the next `then` ops or the `otherwise`
+    /// ops after them (or push zero if there are none)
+    Branch {
+        condition: &'a LogicExpr,
+        then: usize,
+        otherwise: usize,
+    },
+}
+
+/// One part of a subscript: the index it names, or the literal to use if there
+/// is no such index (eg `2020` or `RE1`)
+struct Slot<'a> {
+    name: Spur,
+    literal: SetVal,
+    shift: Option<&'a SubscriptShift>,
+}
+
+impl<'a> Program<'a> {
+    pub fn compile(expr: &'a Expr, lookups: &'a Lookups) -> Self {
+        let mut ops = Vec::new();
+        compile_expr(expr, lookups, &mut ops);
+        Program { ops }
+    }
+}
+
+fn compile_expr<'a>(expr: &'a Expr, lookups: &'a Lookups, ops: &mut Vec<Op<'a>>) {
+    match expr {
+        Expr::Number(num) => ops.push(Op::Num(*num)),
+        Expr::VarSubscripted(var_or_param) => {
+            let name = var_or_param.var;
+            let subscript = compile_subscript(&var_or_param.subscript);
+            if lookups.var_map.contains_key(&name) {
+                ops.push(Op::Var(name, subscript));
+            } else if let Some(param) = lookups.par_map.get(&name) {
+                let fallback = |table, subscript, ops: &mut Vec<Op<'a>>| {
+                    let at = ops.len();
+                    ops.push(Op::Num(0.0));
+                    match &param.default {
+                        Some(expr) => compile_expr(expr, lookups, ops),
+                        None => ops.push(Op::Uninit),
+                    }
+                    ops[at] = Op::Param {
+                        name,
+                        table,
+                        subscript,
+                        fallback: ops.len() - at - 1,
+                    };
+                };
+                match &param.data {
+                    ParamVal::Scalar(num) => ops.push(Op::Scalar(name, *num)),
+                    ParamVal::Arr(table) => fallback(Some(table), subscript, ops),
+                    ParamVal::Expr(expr) => ops.push(Op::Computed {
+                        name,
+                        param,
+                        expr,
+                        subscript,
+                    }),
+                    ParamVal::None => fallback(None, subscript, ops),
+                }
+            } else {
+                ops.push(Op::Index(name));
+            }
+        }
+        Expr::FuncSum(func) => {
+            let at = ops.len();
+            ops.push(Op::Num(0.0));
+            compile_expr(&func.operand, lookups, ops);
+            ops[at] = Op::Sum {
+                domain: &func.domain,
+                body: ops.len() - at - 1,
+            };
+        }
+        Expr::FuncMin(func) => ops.push(Op::MinMax {
+            domain: &func.domain,
+            var: func.var,
+            is_min: true,
+        }),
+        Expr::FuncMax(func) => ops.push(Op::MinMax {
+            domain: &func.domain,
+            var: func.var,
+            is_min: false,
+        }),
+        Expr::Conditional(conditional) => {
+            compile_logic(&conditional.condition, lookups, ops);
+            let at = ops.len();
+            ops.push(Op::Num(0.0));
+            compile_expr(&conditional.then_expr, lookups, ops);
+            let then = ops.len() - at - 1;
+            if let Some(otherwise) = &conditional.else_expr {
+                compile_expr(otherwise, lookups, ops);
+            }
+            ops[at] = Op::Branch {
+                condition: &conditional.condition,
+                then,
+                otherwise: ops.len() - at - 1 - then,
+            };
+        }
+        Expr::UnaryNeg(inner) => {
+            compile_expr(inner, lookups, ops);
+            ops.push(Op::Neg);
+        }
+        Expr::BinOp { lhs, op, rhs } => {
+            compile_expr(lhs, lookups, ops);
+            compile_expr(rhs, lookups, ops);
+            ops.push(Op::Math(*op));
+        }
+    }
+}
+
+fn compile_logic<'a>(logic: &'a LogicExpr, lookups: &'a Lookups, ops: &mut Vec<Op<'a>>) {
+    match logic {
+        LogicExpr::Comparison { lhs, op, rhs } => {
+            compile_expr(lhs, lookups, ops);
+            compile_expr(rhs, lookups, ops);
+            ops.push(Op::Cmp(*op));
+        }
+        LogicExpr::BoolOp { lhs, op, rhs } => {
+            compile_logic(lhs, lookups, ops);
+            compile_logic(rhs, lookups, ops);
+            ops.push(match op {
+                BoolOp::And => Op::And,
+                BoolOp::Or => Op::Or,
+            });
+        }
+    }
+}
+
+fn compile_subscript(subscript: &Subscript) -> Vec<Slot<'_>> {
+    subscript
+        .iter()
+        .map(|part| {
+            let literal = match intern_resolve(part.var).parse::<u32>() {
+                Ok(num) => SetVal::Int(num),
+                Err(_) => SetVal::Str(part.var),
+            };
+            Slot {
+                name: part.var,
+                literal,
+                shift: part.shift.as_ref(),
+            }
+        })
+        .collect()
+}
+
+#[derive(Clone, Copy)]
+enum Val {
+    /// A constant plus the pairs from `start` up to where the next value's begin
+    Lin {
+        num: f64,
+        start: usize,
+    },
+    Str(Spur),
+    Bool(bool),
+}
+
+/// Runs programs, keeping its stacks between runs so they're only allocated once
+#[derive(Default)]
+pub struct Machine {
+    vals: Vec<Val>,
+    pairs: Vec<Pair>,
+    trace: Option<Trace>,
+}
+
+/// What went into each value on the stack, kept while explaining a row. Like
+/// the pairs, the constant terms of every value live in one buffer in stack order.
+#[derive(Default)]
+struct Trace {
+    /// The factors of each of the machine's pairs
+    factors: Vec<Vec<String>>,
+    constants: Vec<Traced>,
+    /// Where each value's constants start
+    starts: Vec<usize>,
+}
+
+impl Machine {
+    /// Run `lhs` and `rhs` at one index and move everything onto the left:
+    /// the var pairs and the constant that's left on the right
+    pub fn solve(
+        &mut self,
+        lhs: &Program,
+        rhs: &Program,
+        lookups: &Lookups,
+        idx_val_map: &IdxValMap,
+    ) -> (Vec<Pair>, f64) {
+        let rhs = self.run_row(lhs, rhs, lookups, idx_val_map);
+        (self.pairs.drain(..).collect(), rhs)
+    }
+
+    /// `solve`, keeping the params and conditions behind each var term, and the
+    /// constants (with the signs they have on the right) that made up the RHS
+    pub fn explain(
+        &mut self,
+        lhs: &Program,
+        rhs: &Program,
+        lookups: &Lookups,
+        idx_val_map: &IdxValMap,
+    ) -> (Vec<Traced>, Vec<Traced>, f64) {
+        self.trace = Some(Trace::default());
+        let rhs = self.run_row(lhs, rhs, lookups, idx_val_map);
+        let trace = self.trace.take().unwrap();
+        let terms = self
+            .pairs
+            .drain(..)
+            .zip(trace.factors)
+            .map(|(pair, factors)| Traced {
+                var: Some((pair.var, pair.index)),
+                coeff: pair.coeff,
+                factors,
+            })
+            .collect();
+        let mut constants = trace.constants;
+        negate(&mut constants);
+        // Without the `-0` that moving a zero over leaves
+        (terms, constants, rhs + 0.0)
+    }
+
+    fn run_row(
+        &mut self,
+        lhs: &Program,
+        rhs: &Program,
+        lookups: &Lookups,
+        idx_val_map: &IdxValMap,
+    ) -> f64 {
+        self.vals.clear();
+        self.pairs.clear();
+        self.run(&lhs.ops, lookups, idx_val_map);
+        self.run(&rhs.ops, lookups, idx_val_map);
+        self.math(MathOp::Sub);
+        // Leaving its constants in the trace for `explain`
+        let (num, _) = self.vals.pop().unwrap().lin();
+        -num
+    }
+
+    /// Run `program` at one index for its value, or `None` if it has vars
+    pub fn constant(
+        &mut self,
+        program: &Program,
+        lookups: &Lookups,
+        idx_val_map: &IdxValMap,
+    ) -> Option<f64> {
+        self.vals.clear();
+        self.pairs.clear();
+        self.run(&program.ops, lookups, idx_val_map);
+        let (num, _) = self.pop_lin();
+        self.pairs.is_empty().then_some(num)
+    }
+
+    fn run(&mut self, ops: &[Op], lookups: &Lookups, idx_val_map: &IdxValMap) {
+        let mut pc = 0;
+        while pc < ops.len() {
+            match &ops[pc] {
+                Op::Num(num) => self.push_num(*num),
+                Op::Scalar(name, num) => {
+                    self.push_noted(*num, || format!("{} = {num}", intern_resolve(*name)));
+                }
+                Op::Var(var, subscript) => {
+                    self.push_zero();
+                    self.pairs.push(Pair {
+                        var: *var,
+                        index: concrete(subscript, idx_val_map),
+                        coeff: 1.0,
+                    });
+                    if let Some(trace) = &mut self.trace {
+                        trace.factors.push(vec![]);
+                    }
+                }
+                Op::Param {
+                    name,
+                    table,
+                    subscript,
+                    fallback,
+                } => {
+                    let found =
+                        table.and_then(|table| table.get(&concrete(subscript, idx_val_map)));
+                    match found {
+                        Some(num) => self.push_noted(*num, || {
+                            let index = concrete(subscript, idx_val_map);
+                            format!("{} = {num}", index_name(*name, &index))
+                        }),
+                        None => {
+                            self.run(&ops[pc + 1..][..*fallback], lookups, idx_val_map);
+                            if let Some(trace) = &mut self.trace {
+                                let (num, _) = self.vals.last().unwrap().lin();
+                                let index = concrete(subscript, idx_val_map);
+                                let note =
+                                    format!("{} = {num} (default)", index_name(*name, &index));
+                                trace.collapse(num, note);
+                            }
+                        }
+                    }
+                    pc += fallback;
+                }
+                Op::Computed {
+                    name,
+                    param,
+                    expr,
+                    subscript,
+                } => {
+                    let index = concrete(subscript, idx_val_map);
+                    let num = param.computed(expr, &index, lookups);
+                    self.push_noted(num, || {
+                        format!("{} = {num} (computed)", index_name(*name, &index))
+                    });
+                }
+                Op::Index(name) => match idx_get(idx_val_map, *name) {
+                    Some(SetVal::Str(val)) => self.push(Val::Str(*val)),
+                    Some(SetVal::Int(num)) => self.push_num(*num as f64),
+                    Some(SetVal::Tuple(_)) => panic!("tuple set not allowed in var subscript"),
+                    None => panic!(
+                        "symbol {} does not point to a valid var or param",
+                        intern_resolve(*name)
+                    ),
+                },
+                Op::Uninit => panic!("tried to get uninitialized param"),
+                Op::Neg => {
+                    let (num, start) = self.vals.pop().unwrap().lin();
+                    self.scale(start, -1.0);
+                    self.vals.push(Val::Lin { num: -num, start });
+                    if let Some(trace) = &mut self.trace {
+                        let at = trace.top();
+                        negate(&mut trace.constants[at..]);
+                    }
+                }
+                Op::Math(op) => self.math(*op),
+                Op::Sum { domain, body } => {
+                    let body_ops = &ops[pc + 1..][..*body];
+                    self.push_zero();
+                    for idx in domain_to_indexes(domain, lookups, idx_val_map).iter() {
+                        let mut idx_map = get_index_map(&domain.parts, idx);
+                        idx_extend(&mut idx_map, idx_val_map);
+                        self.run(body_ops, lookups, &idx_map);
+                        self.math(MathOp::Add);
+                    }
+                    pc += body;
+                }
+                Op::MinMax {
+                    domain,
+                    var,
+                    is_min,
+                } => {
+                    let num = eval_func_minmax(domain, *is_min, lookups, idx_val_map);
+                    self.push_noted(num, || {
+                        let func = if *is_min { "min" } else { "max" };
+                        format!("{func}{domain} {} = {num}", intern_resolve(*var))
+                    });
+                }
+                Op::Cmp(op) => {
+                    let rhs = self.pop_term();
+                    let lhs = self.pop_term();
+                    let result = match (lhs, rhs) {
+                        (Val::Lin { num: lhs, .. }, Val::Lin { num: rhs, .. }) => match op {
+                            RelOp::Eq | RelOp::EqEq => lhs == rhs,
+                            RelOp::Ne | RelOp::Ne2 => lhs != rhs,
+                            RelOp::Gt => lhs > rhs,
+                            RelOp::Ge => lhs >= rhs,
+                            RelOp::Lt => lhs < rhs,
+                            RelOp::Le => lhs <= rhs,
+                        },
+                        (Val::Str(lhs), Val::Str(rhs)) => match op {
+                            RelOp::Eq => lhs == rhs,
+                            RelOp::Ne => lhs != rhs,
+                            _ => panic!("unhandled string comparison: {op}"),
+                        },
+                        _ => panic!("vars or mixed terms in domain condition"),
+                    };
+                    self.push(Val::Bool(result));
+                }
+                Op::And | Op::Or => {
+                    let rhs = self.pop_bool();
+                    let lhs = self.pop_bool();
+                    let result = match ops[pc] {
+                        Op::And => lhs && rhs,
+                        _ => lhs || rhs,
+                    };
+                    self.push(Val::Bool(result));
+                }
+                Op::Branch {
+                    condition,
+                    then,
+                    otherwise,
+                } => {
+                    let then_ops = &ops[pc + 1..][..*then];
+                    let otherwise_ops = &ops[pc + 1 + then..][..*otherwise];
+                    let holds = self.pop_bool();
+                    if holds {
+                        self.run(then_ops, lookups, idx_val_map);
+                    } else if otherwise_ops.is_empty() {
+                        self.push_zero();
+                    } else {
+                        self.run(otherwise_ops, lookups, idx_val_map);
+                    }
+                    if let Some(trace) = &mut self.trace {
+                        let (_, start) = self.vals.last().unwrap().lin();
+                        let note = format!("{} is {holds}", logic_source(condition, idx_val_map));
+                        trace.note(start, note);
+                    }
+                    pc += then + otherwise;
+                }
+            }
+            pc += 1;
+        }
+    }
+
+    fn push(&mut self, val: Val) {
+        self.vals.push(val);
+        if let Some(trace) = &mut self.trace {
+            trace.starts.push(trace.constants.len());
+        }
+    }
+
+    fn push_num(&mut self, num: f64) {
+        self.push_noted_with(num, None::<fn() -> String>);
+    }
+
+    /// Push a number that came from a param or function, saying which in a trace
+    fn push_noted(&mut self, num: f64, note: impl FnOnce() -> String) {
+        self.push_noted_with(num, Some(note));
+    }
+
+    fn push_noted_with(&mut self, num: f64, note: Option<impl FnOnce() -> String>) {
+        self.push(Val::Lin {
+            num,
+            start: self.pairs.len(),
+        });
+        if let Some(trace) = &mut self.trace {
+            trace.constants.push(Traced {
+                var: None,
+                coeff: num,
+                factors: note.map(|note| note()).into_iter().collect(),
+            });
+        }
+    }
+
+    /// Push a zero that's only a starting point (eg for a sum), and so isn't a
+    /// term of its own in a trace
+    fn push_zero(&mut self) {
+        self.push(Val::Lin {
+            num: 0.0,
+            start: self.pairs.len(),
+        });
+    }
+
+    /// Combine the top two values. Their pairs are already next to each other,
+    /// so this only ever scales them in place.
+    fn math(&mut self, op: MathOp) {
+        // Not `pop_lin`: a trace keeps their constants to combine them too
+        let (rhs, rhs_start) = self.vals.pop().unwrap().lin();
+        let (lhs, lhs_start) = self.vals.pop().unwrap().lin();
+        let lhs_vars = lhs_start < rhs_start;
+        let rhs_vars = rhs_start < self.pairs.len();
+        let num = match op {
+            MathOp::Add => lhs + rhs,
+            MathOp::Sub => {
+                self.scale(rhs_start, -1.0);
+                lhs - rhs
+            }
+            MathOp::Mul => match (lhs_vars, rhs_vars) {
+                (false, _) => {
+                    self.scale(rhs_start, lhs);
+                    lhs * rhs
+                }
+                (true, false) => {
+                    self.scale(lhs_start, rhs);
+                    lhs * rhs
+                }
+                (true, true) => panic!("no vars allowed in expr mul"),
+            },
+            MathOp::Div => {
+                if rhs_vars {
+                    panic!("no vars allowed in expr div");
+                }
+                self.divide(lhs_start, rhs);
+                lhs / rhs
+            }
+            MathOp::Pow => {
+                if lhs_vars || rhs_vars {
+                    panic!("no vars allowed in expr pow");
+                }
+                lhs.powf(rhs)
+            }
+        };
+        self.vals.push(Val::Lin {
+            num,
+            start: lhs_start,
+        });
+        if let Some(trace) = &mut self.trace {
+            trace.math(op, lhs, rhs, lhs_start..rhs_start, rhs_start);
+        }
+    }
+
+    fn scale(&mut self, start: usize, by: f64) {
+        for pair in &mut self.pairs[start..] {
+            pair.coeff *= by;
+        }
+    }
+
+    /// Divide rather than scale by the reciprocal, which can round differently
+    fn divide(&mut self, start: usize, by: f64) {
+        for pair in &mut self.pairs[start..] {
+            pair.coeff /= by;
+        }
+    }
+
+    fn pop(&mut self) -> Val {
+        if let Some(trace) = &mut self.trace {
+            trace.pop();
+        }
+        self.vals.pop().unwrap()
+    }
+
+    /// Pop a number (or linear expression) and the start of its pairs
+    fn pop_lin(&mut self) -> (f64, usize) {
+        self.pop().lin()
+    }
+
+    /// Pop a number or string for a comparison, which can't have vars in it
+    fn pop_term(&mut self) -> Val {
+        let val = self.pop();
+        if let Val::Lin { start, .. } = val
+            && start < self.pairs.len()
+        {
+            panic!("Cannot have variables in final domain condition check");
+        }
+        val
+    }
+
+    fn pop_bool(&mut self) -> bool {
+        match self.pop() {
+            Val::Bool(b) => b,
+            _ => unreachable!(),
+        }
+    }
+}
+
+impl Val {
+    fn lin(self) -> (f64, usize) {
+        match self {
+            Val::Lin { num, start } => (num, start),
+            Val::Str(_) => panic!("Cannot do math on a string term"),
+            Val::Bool(_) => unreachable!(),
+        }
+    }
+}
+
+impl Trace {
+    /// Where the top value's constants start
+    fn top(&self) -> usize {
+        *self.starts.last().unwrap()
+    }
+
+    /// Drop the top value's constants
+    fn pop(&mut self) {
+        let at = self.starts.pop().unwrap();
+        self.constants.truncate(at);
+    }
+
+    /// Do to the top two values' constants (and the factors of their pairs)
+    /// what `Machine::math` did to their pairs. Both values have already been
+    /// popped from the machine, but not from the trace.
+    fn math(&mut self, op: MathOp, lhs: f64, rhs: f64, lhs_pairs: Range<usize>, rhs_pairs: usize) {
+        let rhs_at = self.starts.pop().unwrap();
+        let lhs_at = self.top();
+        let rhs_vars = rhs_pairs < self.factors.len();
+        match op {
+            MathOp::Add => {}
+            MathOp::Sub => negate(&mut self.constants[rhs_at..]),
+            MathOp::Mul | MathOp::Div if !rhs_vars => {
+                let by = self.factors_of(rhs_at..);
+                for term in &mut self.constants[lhs_at..] {
+                    match op {
+                        MathOp::Mul => term.coeff *= rhs,
+                        _ => term.coeff /= rhs,
+                    }
+                    term.factors.extend(by.iter().cloned());
+                }
+                for factors in &mut self.factors[lhs_pairs] {
+                    factors.extend(by.iter().cloned());
+                }
+            }
+            MathOp::Mul | MathOp::Div => {
+                let by = self.factors_of(lhs_at..rhs_at);
+                for term in &mut self.constants[lhs_at..] {
+                    term.coeff *= lhs;
+                    term.factors.extend(by.iter().cloned());
+                }
+                for factors in &mut self.factors[rhs_pairs..] {
+                    factors.extend(by.iter().cloned());
+                }
+            }
+            MathOp::Pow => {
+                let factors = self.factors_of(lhs_at..);
+                self.constants.push(Traced {
+                    var: None,
+                    coeff: lhs.powf(rhs),
+                    factors,
+                });
+            }
+        }
+    }
+
+    /// Take out some constants, keeping only their factors
+    fn factors_of(&mut self, range: impl RangeBounds<usize>) -> Vec<String> {
+        self.constants
+            .drain(range)
+            .flat_map(|term| term.factors)
+            .collect()
+    }
+
+    /// Replace the top value's constants (eg those of a param's default) with
+    /// one, noting where it came from
+    fn collapse(&mut self, num: f64, note: String) {
+        let at = self.top();
+        self.constants.truncate(at);
+        self.constants.push(Traced {
+            var: None,
+            coeff: num,
+            factors: vec![note],
+        });
+    }
+
+    /// Add a note to every term of the top value, whose pairs start at `pairs`
+    fn note(&mut self, pairs: usize, note: String) {
+        let at = self.top();
+        for term in &mut self.constants[at..] {
+            term.factors.push(note.clone());
+        }
+        for factors in &mut self.factors[pairs..] {
+            factors.push(note.clone());
+        }
+    }
+}
+
+fn negate(terms: &mut [Traced]) {
+    for term in terms {
+        term.coeff = -term.coeff;
+    }
+}
+
+/// Resolve a compiled subscript to the index it points at
+fn concrete(subscript: &[Slot], idx_val_map: &IdxValMap) -> Index {
+    subscript
+        .iter()
+        .map(|slot| {
+            let val = idx_get(idx_val_map, slot.name)
+                .copied()
+                .unwrap_or(slot.literal);
+            match (slot.shift, val) {
+                (None, val) => val,
+                (Some(shift), SetVal::Int(num)) => match shift {
+                    SubscriptShift::Plus => SetVal::Int(num + 1),
+                    SubscriptShift::Minus => SetVal::Int(num - 1),
+                },
+                (Some(_), SetVal::Str(_)) => panic!("tried to index shift on string index val"),
+                (Some(_), SetVal::Tuple(_)) => panic!("tuple set not allowed in var subscript"),
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ir::index_name;
+    use crate::{generate_matrix, merge_model, parse_model};
+
+    /// A row's name, (col, coeff) pairs and RHS
+    type Row = (String, Vec<(String, f64)>, f64);
+
+    /// Every row, sorted by name
+    fn rows(text: &str) -> Vec<Row> {
+        let compiled = generate_matrix(merge_model(parse_model(text)));
+        let mut rows: Vec<_> = compiled
+            .cons
+            .iter()
+            .map(|(name, idx, _, rhs)| (index_name(*name, idx), vec![], *rhs))
+            .collect();
+        for (j, (var, idx)) in compiled.vars.keys().enumerate() {
+            for (row, val) in compiled.col(j) {
+                rows[row].1.push((index_name(*var, idx), val));
+            }
+        }
+        for row in &mut rows {
+            row.1.sort_by(|a, b| a.0.cmp(&b.0));
+        }
+        rows.sort_by(|a, b| a.0.cmp(&b.0));
+        rows
+    }
+
+    #[test]
+    fn test_linear_algebra() {
+        let text = r#"
+            set Y;
+            param cost{y in Y} := if y = 1 then 5 else 2;
+            param scale{y in Y} default 2;
+            var x{y in Y} >= 0;
+            var z >= 0;
+            minimize obj: z;
+            s.t. c{y in Y}: x[y] - (z + 3) + (if y > 1 then -x[y-1] / 2) >= cost[y] * scale[y];
+            data;
+            set Y := 1 2;
+        "#;
+        let rows = rows(text);
+        assert_eq!(
+            rows[0],
+            (
+                "c[1]".into(),
+                vec![("x[1]".into(), 1.0), ("z".into(), -1.0)],
+                13.0
+            )
+        );
+        assert_eq!(
+            rows[1],
+            (
+                "c[2]".into(),
+                vec![
+                    ("x[1]".into(), -0.5),
+                    ("x[2]".into(), 1.0),
+                    ("z".into(), -1.0)
+                ],
+                7.0
+            )
+        );
+    }
+
+    #[test]
+    fn test_divide() {
+        // 3 * (1 / 10) isn't 0.3, so the coefficient has to be divided directly
+        let text = r#"
+            var x >= 0;
+            minimize obj: x;
+            s.t. c: 3 * x / 10 >= 1;
+        "#;
+        let rows = rows(text);
+        assert_eq!(rows[0].1, vec![("x".into(), 0.3)]);
+    }
+}
@@ -0,0 +1,328 @@
+//! Up-front pass that resolves sets and expands the domains of `sum`s once,
+//! so that generating each row only iterates over shared slices.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+use itertools::Itertools;
+use lasso::Spur;
+use smallvec::SmallVec;
+
+use crate::ir::model::ConstraintOrObjective;
+use crate::ir::{Domain, DomainPartVar, Expr, Index, LogicExpr};
+use crate::matrix::constraint::{IdxValMap, domain_product, filter_domain};
+use crate::matrix::lookup::Lookups;
+use crate::matrix::sparse::Sparse;
+
+/// A domain expanded without any enclosing indices
+pub struct Expansion {
+    pub indexes: Arc<[Index]>,
+    /// Whether the domain's condition has been applied already. If it refers to
+    /// an enclosing index it has to be checked again for each use.
+    pub filtered: bool,
+}
+
+/// Expansions are keyed by the domain's id, if it has one
+pub fn domain_key(domain: &Domain) -> Option<usize> {
+    (domain.id != 0).then_some(domain.id)
+}
+
+impl Lookups {
+    /// Resolve the unindexed sets and expand every `sum` domain in `constraints`
+    /// that doesn't index its sets by an enclosing index. Domains whose condition
+    /// depends on an enclosing index are indexed by param instead where possible.
+    ///
+    /// Must be run again after the set or param data changes. Only domains with
+    /// an id (see `Domain::id`) are expanded. Eager computed params are evaluated
+    /// last, once the sets are up to date.
+    pub fn prepare(&mut self, constraints: &[ConstraintOrObjective]) {
+        self.domains.clear();
+        self.sparse.clear();
+        for set in self.set_map.values_mut() {
+            set.reset();
+        }
+        self.expand_domains(constraints);
+        self.compute_params();
+    }
+
+    /// `prepare` again after a data change, resolving only the sets in `sets`
+    /// and expanding only the domains of `constraints`. The expansions of every
+    /// other constraint are kept, so they mustn't depend on what changed.
+    pub fn prepare_only<'c>(
+        &mut self,
+        constraints: impl IntoIterator<Item = &'c ConstraintOrObjective>,
+        sets: &HashSet<Spur>,
+    ) {
+        for (name, set) in self.set_map.iter_mut() {
+            if sets.contains(name) {
+                set.reset();
+            }
+        }
+        self.expand_domains(constraints);
+        self.compute_params();
+    }
+
+    fn expand_domains<'c>(
+        &mut self,
+        constraints: impl IntoIterator<Item = &'c ConstraintOrObjective>,
+    ) {
+        let lookups = &*self;
+        for set in lookups.set_map.values() {
+            set.resolve(&Index::new(), lookups);
+        }
+
+        let mut walker = Walker {
+            lookups,
+            outer: Vec::new(),
+            domains: HashMap::new(),
+            sparse: HashMap::new(),
+            visited: HashSet::new(),
+        };
+        for con in constraints {
+            match &con.domain {
+                Some(domain) => walker.scoped(domain, |walker| {
+                    // Each constraint's own domain is only expanded once, but can
+                    // still be expanded from a param rather than in full
+                    if let Some(key) = domain_key(domain) {
+                        walker.visited.insert(key);
+                        if let Some(sparse) = Sparse::new(domain, lookups) {
+                            walker.sparse.insert(key, sparse);
+                        }
+                    }
+                    walker.constraint(con);
+                }),
+                None => walker.constraint(con),
+            }
+        }
+        let Walker {
+            domains,
+            sparse,
+            visited,
+            ..
+        } = walker;
+        self.domains.retain(|key, _| !visited.contains(key));
+        self.sparse.retain(|key, _| !visited.contains(key));
+        self.domains.extend(domains);
+        self.sparse.extend(sparse);
+    }
+}
+
+struct Walker<'a> {
+    lookups: &'a Lookups,
+    /// Indices bound by the enclosing domains
+    outer: Vec<Spur>,
+    domains: HashMap<usize, Expansion>,
+    sparse: HashMap<usize, Sparse>,
+    /// Every domain walked, whether or not it was expanded
+    visited: HashSet<usize>,
+}
+
+impl Walker<'_> {
+    /// Visit the domain's condition and then run `inner`, with its indices bound
+    fn scoped(&mut self, domain: &Domain, inner: impl FnOnce(&mut Self)) {
+        let depth = self.outer.len();
+        self.outer.extend(dummies(domain));
+        if let Some(cond) = &domain.condition {
+            self.logic(cond);
+        }
+        inner(self);
+        self.outer.truncate(depth);
+    }
+
+    fn constraint(&mut self, con: &ConstraintOrObjective) {
+        self.expr(&con.lhs);
+        self.expr(&con.rhs);
+        if let Some(upper) = &con.upper {
+            self.expr(upper);
+        }
+    }
+
+    fn expr(&mut self, expr: &Expr) {
+        match expr {
+            Expr::Number(_) | Expr::VarSubscripted(_) => {}
+            Expr::FuncSum(func) => {
+                self.expand(&func.domain);
+                self.scoped(&func.domain, |walker| walker.expr(&func.operand));
+            }
+            Expr::FuncMin(_) | Expr::FuncMax(_) => {}
+            Expr::Conditional(cond) => {
+                self.logic(&cond.condition);
+                self.expr(&cond.then_expr);
+                if let Some(else_expr) = &cond.else_expr {
+                    self.expr(else_expr);
+                }
+            }
+            Expr::UnaryNeg(inner) => self.expr(inner),
+            Expr::BinOp { lhs, op: _, rhs } => {
+                self.expr(lhs);
+                self.expr(rhs);
+            }
+        }
+    }
+
+    fn logic(&mut self, logic: &LogicExpr) {
+        match logic {
+            LogicExpr::Comparison { lhs, op: _, rhs } => {
+                self.expr(lhs);
+                self.expr(rhs);
+            }
+            LogicExpr::BoolOp { lhs, op: _, rhs } => {
+                self.logic(lhs);
+                self.logic(rhs);
+            }
+        }
+    }
+
+    fn expand(&mut self, domain: &Domain) {
+        let Some(key) = domain_key(domain) else {
+            return;
+        };
+        self.visited.insert(key);
+        if self.domains.contains_key(&key)
+            || domain.parts.iter().any(|part| !part.subscript.is_empty())
+        {
+            return;
+        }
+        let filtered = match &domain.condition {
+            None => true,
+            Some(cond) => {
+                let mut bound = dummies(domain);
+                self.closed_logic(cond, &mut bound)
+            }
+        };
+        let sparse = Sparse::new(domain, self.lookups);
+        let empty = IdxValMap::new();
+        if filtered {
+            let indexes = match &sparse {
+                Some(sparse) => sparse.indexes(domain, self.lookups, &empty).into_iter(),
+                None => domain_product(domain, self.lookups, &empty)
+                    .collect_vec()
+                    .into_iter(),
+            };
+            let indexes = filter_domain(domain, indexes, self.lookups, &empty).collect();
+            self.domains.insert(key, Expansion { indexes, filtered });
+        } else if let Some(sparse) = sparse {
+            self.sparse.insert(key, sparse);
+        } else {
+            let indexes = domain_product(domain, self.lookups, &empty).collect();
+            self.domains.insert(key, Expansion { indexes, filtered });
+        }
+    }
+
+    /// Whether a condition gives the same answer wherever it's used, ie it
+    /// doesn't refer to an enclosing index, directly or through a param default
+    fn closed_logic(&self, logic: &LogicExpr, bound: &mut SmallVec<[Spur; 8]>) -> bool {
+        match logic {
+            LogicExpr::Comparison { lhs, op: _, rhs } => {
+                self.closed(lhs, bound) && self.closed(rhs, bound)
+            }
+            LogicExpr::BoolOp { lhs, op: _, rhs } => {
+                self.closed_logic(lhs, bound) && self.closed_logic(rhs, bound)
+            }
+        }
+    }
+
+    fn closed(&self, expr: &Expr, bound: &mut SmallVec<[Spur; 8]>) -> bool {
+        match expr {
+            Expr::Number(_) => true,
+            Expr::VarSubscripted(v) => {
+                // Defaults are evaluated where they're used, so can see enclosing indices
+                let computed =
+                    self.lookups.par_map.get(&v.var).is_some_and(|param| {
+                        !matches!(param.default, None | Some(Expr::Number(_)))
+                    });
+                !computed
+                    && !self.free(v.var, bound)
+                    && !v.subscript.iter().any(|sub| self.free(sub.var, bound))
+            }
+            Expr::FuncSum(func) => {
+                if !self.closed_parts(&func.domain, bound) {
+                    return false;
+                }
+                let depth = bound.len();
+                bound.extend(dummies(&func.domain));
+                let closed = func
+                    .domain
+                    .condition
+                    .as_ref()
+                    .is_none_or(|cond| self.closed_logic(cond, bound))
+                    && self.closed(&func.operand, bound);
+                bound.truncate(depth);
+                closed
+            }
+            Expr::FuncMin(func) => self.closed_parts(&func.domain, bound),
+            Expr::FuncMax(func) => self.closed_parts(&func.domain, bound),
+            Expr::Conditional(cond) => {
+                self.closed_logic(&cond.condition, bound)
+                    && self.closed(&cond.then_expr, bound)
+                    && cond
+                        .else_expr
+                        .as_ref()
+                        .is_none_or(|else_expr| self.closed(else_expr, bound))
+            }
+            Expr::UnaryNeg(inner) => self.closed(inner, bound),
+            Expr::BinOp { lhs, op: _, rhs } => self.closed(lhs, bound) && self.closed(rhs, bound),
+        }
+    }
+
+    /// Whether none of the domain's sets are indexed by an enclosing index
+    fn closed_parts(&self, domain: &Domain, bound: &[Spur]) -> bool {
+        domain
+            .parts
+            .iter()
+            .all(|part| !part.subscript.iter().any(|sub| self.free(sub.var, bound)))
+    }
+
+    fn free(&self, name: Spur, bound: &[Spur]) -> bool {
+        self.outer.contains(&name) && !bound.contains(&name)
+    }
+}
+
+/// The indices a domain binds, eg `y` and `l` in `{y in YEAR, l in TIMESLICE}`
+fn dummies(domain: &Domain) -> SmallVec<[Spur; 8]> {
+    domain
+        .parts
+        .iter()
+        .flat_map(|part| match &part.var {
+            DomainPartVar::Single(id) => SmallVec::<[Spur; 8]>::from_slice(&[*id]),
+            DomainPartVar::Tuple(ids) => ids.iter().copied().collect(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gmpl::loader::{consume, parse};
+    use crate::ir::model::ModelWithData;
+    use crate::matrix::constraint::domain_to_indexes;
+
+    #[test]
+    fn test_expansions_follow_clones() {
+        let text = r#"
+            set I := a b c;
+            param w{i in I} := 1;
+            var x{i in I} >= 0;
+            minimize cost: sum{i in I: w[i] > 0} x[i];
+        "#;
+        let ModelWithData {
+            sets,
+            vars,
+            pars,
+            constraints,
+        } = ModelWithData::from_entries(consume(parse(text)));
+        let mut lookups = Lookups::from_model(sets, vars, pars);
+        lookups.prepare(&constraints);
+
+        let moved = constraints.clone();
+        drop(constraints);
+        let Expr::FuncSum(func) = &moved[0].lhs else {
+            panic!("expected a sum");
+        };
+        let key = domain_key(&func.domain).unwrap();
+        let cached = lookups.domains[&key].indexes.clone();
+        let indexes = domain_to_indexes(&func.domain, &lookups, &IdxValMap::new());
+        assert!(Arc::ptr_eq(&indexes, &cached));
+        assert_eq!(indexes.len(), 3);
+    }
+}
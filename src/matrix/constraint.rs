@@ -1,15 +1,18 @@
+use std::sync::Arc;
+
 use crate::ir::LogicExpr;
 use crate::ir::{
     BoolOp, Domain, DomainPart, DomainPartVar, Expr, Index, MathOp, RelOp, SetVal, SetValTerminal,
     Subscript, SubscriptShift, interner::intern_resolve,
 };
+use crate::matrix::cache::domain_key;
 use crate::matrix::lookup::Lookups;
 use crate::matrix::param::ParamVal;
 use itertools::Itertools;
 use lasso::Spur;
 use smallvec::SmallVec;
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct Pair {
     pub var: Spur,
     pub index: Index,
@@ -34,7 +37,7 @@ pub fn idx_get(map: &IdxValMap, key: Spur) -> Option<&SetVal> {
 }
 
 // Helper to extend one IdxValMap with another
-fn idx_extend(map: &mut IdxValMap, other: &IdxValMap) {
+pub(crate) fn idx_extend(map: &mut IdxValMap, other: &IdxValMap) {
     for (k, v) in other.iter() {
         if !map.iter().any(|(mk, _)| *mk == *k) {
             map.push((*k, *v));
@@ -70,7 +73,7 @@ pub fn recurse(expr: &Expr, lookups: &Lookups, idx_val_map: &IdxValMap) -> Vec<T
                             }
                         }
                     }
-                    ParamVal::Expr(expr) => recurse(expr, lookups, idx_val_map),
+                    ParamVal::Expr(expr) => vec![Term::Num(param.computed(expr, &index, lookups))],
                     ParamVal::None => match &param.default {
                         Some(expr) => recurse(expr, lookups, idx_val_map),
                         None => panic!("tried to get uninitialized param"),
@@ -203,83 +206,132 @@ pub fn recurse(expr: &Expr, lookups: &Lookups, idx_val_map: &IdxValMap) -> Vec<T
     }
 }
 
+/// All the indexes of `domain` that satisfy its condition, with any enclosing
+/// indices taken from `idx_val_map`. Uses the expansion (or sparse param
+/// index) precomputed by `Lookups::prepare` where there is one.
 pub fn domain_to_indexes(
     domain: &Domain,
     lookups: &Lookups,
     idx_val_map: &IdxValMap,
-) -> Vec<Index> {
-    let Domain { parts, condition } = domain;
-    let cartesian: Box<dyn Iterator<Item = Vec<SetVal>>> =
-        if parts.iter().all(|part| part.subscript.is_empty()) {
-            Box::new(
-                parts
-                    .iter()
-                    .map(|part| {
-                        let concrete_idx: Index = vec![].into();
-                        lookups
-                            .set_map
-                            .get(&part.set)
-                            .unwrap()
-                            .resolve(&concrete_idx, lookups)
-                            .0
-                    })
-                    .multi_cartesian_product(),
-            )
-        } else {
-            // GMPL has a degenerate feature where in a domain expression like
-            // { a in A, b in B[a] }
-            // a later indexed set can refer to a set value from another one
-            // Plausibly this could go twice like
-            // { a in A, b in B[a], c in C[b] }
-            // but I'm hoping not to support that
-
-            // The Box dyn is just to keep the variable as an iterator so we can
-            // reassign to it but not have to collect it until we're done iterating
-            let mut cartesian: Box<dyn Iterator<Item = Vec<SetVal>>> =
-                Box::new(vec![vec![]].into_iter());
-            for part in parts {
-                cartesian = Box::new(cartesian.flat_map(|existing| {
-                    let mut idx_map = get_index_map(parts, &existing);
-                    idx_extend(&mut idx_map, idx_val_map);
-                    let concrete_idx = concrete_index(&part.subscript, &idx_map);
+) -> Arc<[Index]> {
+    if let Some(key) = domain_key(domain) {
+        if let Some(expansion) = lookups.domains.get(&key) {
+            if expansion.filtered {
+                return expansion.indexes.clone();
+            }
+            let indexes = expansion.indexes.iter().cloned();
+            return filter_domain(domain, indexes, lookups, idx_val_map).collect();
+        }
+        if let Some(sparse) = lookups.sparse.get(&key) {
+            let indexes = sparse.indexes(domain, lookups, idx_val_map).into_iter();
+            return filter_domain(domain, indexes, lookups, idx_val_map).collect();
+        }
+    }
+    let indexes = domain_product(domain, lookups, idx_val_map);
+    filter_domain(domain, indexes, lookups, idx_val_map).collect()
+}
 
+/// The cartesian product of the domain's sets, before its condition is applied
+pub(crate) fn domain_product<'a>(
+    domain: &'a Domain,
+    lookups: &'a Lookups,
+    idx_val_map: &'a IdxValMap,
+) -> Box<dyn Iterator<Item = Index> + 'a> {
+    let parts = &domain.parts;
+    if parts.iter().all(|part| part.subscript.is_empty()) {
+        Box::new(
+            parts
+                .iter()
+                .map(|part| {
+                    let concrete_idx: Index = vec![].into();
                     lookups
                         .set_map
                         .get(&part.set)
                         .unwrap()
                         .resolve(&concrete_idx, lookups)
-                        .iter()
-                        .map(|val| {
-                            let mut new_idx = existing.clone();
-                            new_idx.push(*val);
-                            new_idx
-                        })
-                        .collect::<Vec<_>>()
-                }));
-            }
-            cartesian
-        };
+                        .to_vec()
+                })
+                .multi_cartesian_product()
+                .map(Index::from),
+        )
+    } else {
+        // GMPL has a degenerate feature where in a domain expression like
+        // { a in A, b in B[a] }
+        // a later indexed set can refer to a set value from another one
+        // Plausibly this could go twice like
+        // { a in A, b in B[a], c in C[b] }
+        // but I'm hoping not to support that
+
+        // The Box dyn is just to keep the variable as an iterator so we can
+        // reassign to it but not have to collect it until we're done iterating
+        let mut cartesian: Box<dyn Iterator<Item = Vec<SetVal>>> =
+            Box::new(vec![vec![]].into_iter());
+        for part in parts {
+            cartesian = Box::new(cartesian.flat_map(move |existing| {
+                let mut idx_map = get_index_map(parts, &existing);
+                idx_extend(&mut idx_map, idx_val_map);
+                let concrete_idx = concrete_index(&part.subscript, &idx_map);
+
+                lookups
+                    .set_map
+                    .get(&part.set)
+                    .unwrap()
+                    .resolve(&concrete_idx, lookups)
+                    .iter()
+                    .map(|val| {
+                        let mut new_idx = existing.clone();
+                        new_idx.push(*val);
+                        new_idx
+                    })
+                    .collect::<Vec<_>>()
+            }));
+        }
+        Box::new(cartesian.map(Index::from))
+    }
+}
 
-    cartesian
-        .filter_map(|idx| {
-            let idx = Index::from(idx);
-            match &condition {
-                None => Some(idx),
-                Some(logic) => {
-                    let mut idx_map = get_index_map(parts, &idx);
-                    idx_extend(&mut idx_map, idx_val_map);
-                    if check_domain_condition(logic, lookups, &idx_map) {
-                        Some(idx)
-                    } else {
-                        None
-                    }
-                }
-            }
+/// Whether `idx` is in the cartesian product of the domain's sets, without
+/// going through the rest of the product
+pub(crate) fn in_domain_product(domain: &Domain, idx: &Index, lookups: &Lookups) -> bool {
+    let parts = &domain.parts;
+    parts.len() == idx.len()
+        && parts.iter().enumerate().all(|(i, part)| {
+            let idx_map = get_index_map(&parts[..i], &idx[..i]);
+            let concrete_idx = concrete_index(&part.subscript, &idx_map);
+            lookups
+                .set_map
+                .get(&part.set)
+                .unwrap()
+                .resolve(&concrete_idx, lookups)
+                .contains(&idx[i])
         })
-        .collect::<Vec<Index>>()
 }
 
-fn check_domain_condition(logic: &LogicExpr, lookups: &Lookups, idx_val_map: &IdxValMap) -> bool {
+/// Keep the indexes that satisfy the domain's condition (if it has one)
+pub(crate) fn filter_domain<'a>(
+    domain: &'a Domain,
+    indexes: impl Iterator<Item = Index> + 'a,
+    lookups: &'a Lookups,
+    idx_val_map: &'a IdxValMap,
+) -> impl Iterator<Item = Index> + 'a {
+    let Domain {
+        parts, condition, ..
+    } = domain;
+    indexes.filter(move |idx| match &condition {
+        None => true,
+        Some(logic) => {
+            let mut idx_map = get_index_map(parts, idx);
+            idx_extend(&mut idx_map, idx_val_map);
+            check_domain_condition(logic, lookups, &idx_map)
+        }
+    })
+}
+
+pub fn check_domain_condition(
+    logic: &LogicExpr,
+    lookups: &Lookups,
+    idx_val_map: &IdxValMap,
+) -> bool {
     match logic {
         LogicExpr::Comparison { lhs, op, rhs } => {
             let lhs = recurse(lhs, lookups, idx_val_map);
@@ -326,9 +378,9 @@ fn expand_sum(
     idx_val_map: &IdxValMap,
 ) -> Vec<Term> {
     domain_to_indexes(sum_domain, lookups, idx_val_map)
-        .into_iter()
+        .iter()
         .flat_map(|idx| {
-            let mut idx_map = get_index_map(&sum_domain.parts, &idx);
+            let mut idx_map = get_index_map(&sum_domain.parts, idx);
             idx_extend(&mut idx_map, idx_val_map);
             recurse(operand, lookups, &idx_map)
         })
@@ -362,41 +414,6 @@ fn resolve_terms_to_term(terms: &[Term]) -> Term {
     }
 }
 
-pub fn algebra(lhs: Vec<Term>, rhs: Vec<Term>) -> (Vec<Pair>, f64) {
-    let lhs_nums: Vec<f64> = lhs
-        .iter()
-        .filter_map(|p| if let Term::Num(n) = p { Some(*n) } else { None })
-        .collect();
-    let rhs_nums: Vec<f64> = rhs
-        .iter()
-        .filter_map(|p| if let Term::Num(n) = p { Some(*n) } else { None })
-        .collect();
-
-    let lhs_pairs: Vec<Pair> = lhs
-        .into_iter()
-        .filter_map(|p| if let Term::Pair(n) = p { Some(n) } else { None })
-        .collect();
-    let rhs_pairs: Vec<Pair> = rhs
-        .into_iter()
-        .filter_map(|p| if let Term::Pair(n) = p { Some(n) } else { None })
-        .collect();
-
-    let rhs_pairs_neg: Vec<Pair> = rhs_pairs
-        .into_iter()
-        .map(|pair| Pair {
-            var: pair.var,
-            index: pair.index,
-            coeff: -pair.coeff,
-        })
-        .collect();
-
-    let lhs_nums_neg: Vec<f64> = lhs_nums.into_iter().map(|n| -n).collect();
-
-    let rhs_total: f64 = [rhs_nums, lhs_nums_neg].into_iter().flatten().sum();
-    let pairs = lhs_pairs.into_iter().chain(rhs_pairs_neg).collect();
-    (pairs, rhs_total)
-}
-
 // I'd prefer this function to accept an Index only, but then I have to clone for the Vec->SmallVec
 // conversion
 pub fn get_index_map(parts: &[DomainPart], idx: &[SetVal]) -> IdxValMap {
@@ -433,7 +450,7 @@ pub fn get_index_map(parts: &[DomainPart], idx: &[SetVal]) -> IdxValMap {
         .collect()
 }
 
-fn eval_func_minmax(
+pub(crate) fn eval_func_minmax(
     domain: &Domain,
     is_min: bool,
     lookups: &Lookups,
@@ -486,7 +503,7 @@ fn negate_terms(terms: Vec<Term>) -> Vec<Term> {
         .collect()
 }
 
-fn concrete_index(susbcript: &Subscript, idx_val_map: &IdxValMap) -> Index {
+pub fn concrete_index(susbcript: &Subscript, idx_val_map: &IdxValMap) -> Index {
     susbcript
         .iter()
         .map(|i| {
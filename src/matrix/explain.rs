@@ -0,0 +1,371 @@
+//! Generate a single row again, keeping track of the param values that went into
+//! each coefficient, to see why a row in the MPS came out the way it did.
+
+use std::fmt::{self, Write};
+
+use lasso::Spur;
+
+use crate::analysis::Diagnostic;
+use crate::ir::interner::intern_resolve;
+use crate::ir::model::{ConstraintOrObjective, ModelWithData};
+use crate::ir::op::RowType;
+use crate::ir::{Expr, Index, LogicExpr, SetVal, SubscriptShift, index_name};
+use crate::matrix::bytecode::{Machine, Program};
+use crate::matrix::constraint::{
+    IdxValMap, filter_domain, get_index_map, idx_get, in_domain_product,
+};
+use crate::matrix::lookup::Lookups;
+
+/// How a row was built: the constraint's indices, each var term and constant
+/// with the values multiplied into it, and the resulting RHS
+pub struct Explanation {
+    pub row: String,
+    /// The constraint as written, eg `(x[i] + y[i]) >= Demand[i]`
+    pub source: String,
+    pub bindings: IdxValMap,
+    pub terms: Vec<Traced>,
+    /// The constants of both sides, with the signs they have once they're moved
+    /// over to the RHS
+    pub constants: Vec<Traced>,
+    pub row_type: RowType,
+    pub rhs: f64,
+}
+
+/// One var term (or constant) of a row
+#[derive(Clone, Debug)]
+pub struct Traced {
+    pub var: Option<(Spur, Index)>,
+    pub coeff: f64,
+    /// The params, `min`/`max`s and conditions that made up the coefficient,
+    /// eg `OutputActivityRatio[RE1,T1,ELC,1,2030] = 0.5`
+    pub factors: Vec<String>,
+}
+
+/// Find the constraint and index named by `row` (as in the MPS output, eg
+/// `EBa11_EnergyBalanceEachTS5[RE1,ID,ELC,2030]`) and generate it with a trace
+pub fn explain_row(model: ModelWithData, row: &str) -> Result<Explanation, Diagnostic> {
+    let row: String = row.chars().filter(|c| !c.is_whitespace()).collect();
+    let name = row.split('[').next().unwrap();
+    let ModelWithData {
+        sets,
+        vars,
+        pars,
+        constraints,
+    } = model;
+    let Some(con) = constraints
+        .iter()
+        .find(|con| intern_resolve(con.name) == name)
+    else {
+        return Err(Diagnostic::error(format!(
+            "there's no constraint named {name}"
+        )));
+    };
+    let lookups = Lookups::from_model(sets, vars, pars);
+
+    let ConstraintOrObjective {
+        name: _,
+        domain,
+        row_type,
+        lhs,
+        rhs,
+        upper,
+        ops: _,
+        active: _,
+        sense: _,
+    } = con;
+    if let Some(message) = con.relation_error() {
+        return Err(Diagnostic::error(format!("{name}: {message}")));
+    }
+    let bindings = match domain {
+        None if row == name => IdxValMap::new(),
+        None => {
+            return Err(Diagnostic::error(format!("{name} has no indices")));
+        }
+        Some(domain) => {
+            let idx = row
+                .strip_prefix(name)
+                .and_then(|rest| rest.strip_prefix('['))
+                .and_then(|rest| rest.strip_suffix(']'))
+                .and_then(|raw| domain.parse_index(raw))
+                .filter(|idx| in_domain_product(domain, idx, &lookups));
+            let Some(idx) = idx else {
+                return Err(Diagnostic::error(format!(
+                    "{row} isn't in the domain of {name}"
+                )));
+            };
+            let empty = IdxValMap::new();
+            if filter_domain(domain, std::iter::once(idx.clone()), &lookups, &empty)
+                .next()
+                .is_none()
+            {
+                return Err(Diagnostic::error(format!(
+                    "{row} is left out by the condition of {name}'s domain"
+                )));
+            }
+            get_index_map(&domain.parts, &idx)
+        }
+    };
+
+    // The row is run through the same programs the generator uses, with a trace
+    let mut machine = Machine::default();
+    let lhs_program = Program::compile(lhs, &lookups);
+    let rhs_program = Program::compile(rhs, &lookups);
+    let (terms, constants, rhs) = machine.explain(&lhs_program, &rhs_program, &lookups, &bindings);
+
+    let empty = IdxValMap::new();
+    let (row_type, source) = match upper {
+        Some(upper) => {
+            let upper_program = Program::compile(upper, &lookups);
+            let lower = machine.constant(&rhs_program, &lookups, &bindings);
+            let upper_num = machine.constant(&upper_program, &lookups, &bindings);
+            let (Some(lower), Some(upper_num)) = (lower, upper_num) else {
+                return Err(Diagnostic::error(format!(
+                    "the bounds of {name}'s double inequality can't have vars"
+                )));
+            };
+            let source = format!(
+                "{} <= {} <= {}",
+                source(&con.rhs, &empty),
+                source(&con.lhs, &empty),
+                source(upper, &empty)
+            );
+            (RowType::Ranged(upper_num - lower), source)
+        }
+        None => {
+            let source = format!(
+                "{} {} {}",
+                source(&con.lhs, &empty),
+                row_type.symbol(),
+                source(&con.rhs, &empty)
+            );
+            (*row_type, source)
+        }
+    };
+
+    Ok(Explanation {
+        row,
+        source,
+        bindings,
+        terms,
+        constants,
+        row_type,
+        rhs,
+    })
+}
+
+/// An expression as written, but with its subscripts (which the model's own
+/// `Display` leaves out), and the values of any indices bound in `idx_val_map`
+fn source(expr: &Expr, idx_val_map: &IdxValMap) -> String {
+    match expr {
+        Expr::Number(num) => num.to_string(),
+        Expr::VarSubscripted(v) => {
+            let mut out = intern_resolve(v.var).to_string();
+            if !v.subscript.is_empty() {
+                let parts: Vec<String> = v
+                    .subscript
+                    .iter()
+                    .map(|part| match (idx_get(idx_val_map, part.var), &part.shift) {
+                        (Some(SetVal::Int(num)), Some(SubscriptShift::Plus)) => {
+                            (num + 1).to_string()
+                        }
+                        (Some(SetVal::Int(num)), Some(SubscriptShift::Minus)) => {
+                            (num - 1).to_string()
+                        }
+                        (Some(val), _) => val.to_string(),
+                        (None, _) => part.to_string(),
+                    })
+                    .collect();
+                write!(out, "[{}]", parts.join(",")).unwrap();
+            }
+            out
+        }
+        Expr::FuncSum(func) => format!("sum{} {}", func.domain, source(&func.operand, idx_val_map)),
+        Expr::FuncMin(func) => format!("min{} {}", func.domain, intern_resolve(func.var)),
+        Expr::FuncMax(func) => format!("max{} {}", func.domain, intern_resolve(func.var)),
+        Expr::Conditional(cond) => {
+            let mut out = format!(
+                "if {} then {}",
+                logic_source(&cond.condition, idx_val_map),
+                source(&cond.then_expr, idx_val_map)
+            );
+            if let Some(otherwise) = &cond.else_expr {
+                write!(out, " else {}", source(otherwise, idx_val_map)).unwrap();
+            }
+            out
+        }
+        Expr::UnaryNeg(inner) => format!("-{}", source(inner, idx_val_map)),
+        Expr::BinOp { lhs, op, rhs } => format!(
+            "({} {op} {})",
+            source(lhs, idx_val_map),
+            source(rhs, idx_val_map)
+        ),
+    }
+}
+
+pub(crate) fn logic_source(logic: &LogicExpr, idx_val_map: &IdxValMap) -> String {
+    match logic {
+        LogicExpr::Comparison { lhs, op, rhs } => format!(
+            "({} {op} {})",
+            source(lhs, idx_val_map),
+            source(rhs, idx_val_map)
+        ),
+        LogicExpr::BoolOp { lhs, op, rhs } => format!(
+            "({} {op} {})",
+            logic_source(lhs, idx_val_map),
+            logic_source(rhs, idx_val_map)
+        ),
+    }
+}
+
+impl fmt::Display for Traced {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // Without a `-0` where a zero param cancelled a negative term
+        write!(f, "{:+}", self.coeff + 0.0)?;
+        if let Some((var, index)) = &self.var {
+            write!(f, " {}", index_name(*var, index))?;
+        }
+        if !self.factors.is_empty() {
+            write!(f, "    ({})", self.factors.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Explanation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{}", self.row)?;
+        writeln!(f, "  {}", self.source)?;
+        if !self.bindings.is_empty() {
+            writeln!(f)?;
+            writeln!(f, "indices")?;
+            for (name, val) in &self.bindings {
+                writeln!(f, "  {} = {val}", intern_resolve(*name))?;
+            }
+        }
+        writeln!(f)?;
+        writeln!(f, "terms")?;
+        for term in &self.terms {
+            writeln!(f, "  {term}")?;
+        }
+        if !self.constants.is_empty() {
+            writeln!(f)?;
+            writeln!(f, "constants (moved to the rhs)")?;
+            for term in &self.constants {
+                writeln!(f, "  {term}")?;
+            }
+        }
+        writeln!(f)?;
+        match self.row_type {
+            RowType::Unconstrained => writeln!(f, "objective constant {}", -self.rhs),
+            RowType::Ranged(width) => {
+                writeln!(f, "range {} <= row <= {}", self.rhs, self.rhs + width)
+            }
+            row_type => writeln!(f, "rhs {} {}", row_type.symbol(), self.rhs),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{merge_model, parse_model};
+
+    const MODEL: &str = r#"
+        set I;
+        param w{i in I} default 1;
+        param cap{i in I} default 10;
+        var x{i in I} >= 0;
+        var y >= 0;
+        minimize cost: sum{i in I} w[i] * x[i];
+        s.t. c{i in I: cap[i] > 5}: 2 * w[i] * x[i] + y - 3 >= cap[i] - (if w[i] > 2 then y);
+        data;
+        set I := a b c;
+        param w := b 4;
+        param cap := c 1;
+    "#;
+
+    fn explain(row: &str) -> Result<Explanation, Diagnostic> {
+        explain_row(merge_model(parse_model(MODEL)), row)
+    }
+
+    #[test]
+    fn test_explain_row() {
+        let explanation = explain("c[b]").unwrap();
+        let terms: Vec<String> = explanation
+            .terms
+            .iter()
+            .map(|term| term.to_string())
+            .collect();
+        assert_eq!(
+            terms,
+            vec![
+                "+8 x[b]    (w[b] = 4)",
+                "+1 y",
+                "+1 y    ((w[b] > 2) is true)",
+            ]
+        );
+        let constants: Vec<String> = explanation
+            .constants
+            .iter()
+            .map(|term| term.to_string())
+            .collect();
+        assert_eq!(constants, vec!["+3", "+10    (cap[b] = 10 (default))"]);
+        assert_eq!(explanation.rhs, 13.0);
+        assert_eq!(explanation.row_type, RowType::GreaterThanOrEqual);
+
+        let text = explanation.to_string();
+        assert!(text.contains("indices\n  i = b\n"));
+        assert!(text.ends_with("rhs >= 13\n"));
+    }
+
+    #[test]
+    fn test_explain_range() {
+        let text = r#"
+            param lo := 2;
+            var x >= 0;
+            minimize cost: x;
+            s.t. band: lo <= x + 1 <= lo * 3;
+        "#;
+        let explanation = explain_row(merge_model(parse_model(text)), "band").unwrap();
+        assert_eq!(explanation.source, "lo <= (x + 1) <= (lo * 3)");
+        assert_eq!(explanation.row_type, RowType::Ranged(4.0));
+        assert!(explanation.to_string().ends_with("range 1 <= row <= 5\n"));
+    }
+
+    #[test]
+    fn test_explain_tuple_row() {
+        let text = r#"
+            set P dimen 2;
+            set T;
+            param f{(i, j) in P, t in T} default 0;
+            var x{(i, j) in P} >= 0;
+            minimize cost: sum{(i, j) in P} x[i, j];
+            s.t. flow{(i, j) in P, t in T}: f[i, j, t] * x[i, j] <= 5;
+            data;
+            set P := (a, b) (b, c);
+            set T := 1 2;
+            param f := a b 2 3;
+        "#;
+        let model = || merge_model(parse_model(text));
+        let explanation = explain_row(model(), "flow[a,b,2]").unwrap();
+        assert_eq!(
+            explanation.terms[0].to_string(),
+            "+3 x[a,b]    (f[a,b,2] = 3)"
+        );
+        let message = explain_row(model(), "flow[a,c,2]").err().unwrap().message;
+        assert_eq!(message, "flow[a,c,2] isn't in the domain of flow");
+        let message = explain_row(model(), "flow[a,b]").err().unwrap().message;
+        assert_eq!(message, "flow[a,b] isn't in the domain of flow");
+    }
+
+    #[test]
+    fn test_explain_missing_row() {
+        let message = |row: &str| explain(row).err().unwrap().message;
+        assert_eq!(message("d[a]"), "there's no constraint named d");
+        assert_eq!(message("c[z]"), "c[z] isn't in the domain of c");
+        assert_eq!(
+            message("c[c]"),
+            "c[c] is left out by the condition of c's domain"
+        );
+    }
+}
@@ -0,0 +1,279 @@
+//! Regenerate the matrix after a data change, rebuilding only the constraint blocks
+//! that depend on the changed params or sets.
+//!
+//! ```no_run
+//! use mosox::{Generator, load_data, load_model_and_data, merge_model};
+//! let model = merge_model(load_model_and_data("osemosys.mod", &["base.dat"]));
+//! let mut generator = Generator::new(model);
+//! let update = generator.update(load_data("scenario.dat")).unwrap();
+//! let compiled = generator.compiled();
+//! ```
+
+use std::collections::{HashMap, HashSet};
+
+use lasso::Spur;
+use rayon::iter::{IntoParallelIterator, IntoParallelRefIterator, ParallelIterator};
+
+use crate::analysis::Diagnostic;
+use crate::ir::interner::intern_resolve;
+use crate::ir::model::{ConstraintOrObjective, ModelWithData, regroup_set_values};
+use crate::ir::refs::Refs;
+use crate::ir::{self, Domain, Entry, Index, index_name};
+use crate::matrix::lookup::Lookups;
+use crate::matrix::param::resolve_param_data;
+use crate::matrix::{Compiled, MatrixBuilder, SolvedConstraint, build_constraint, objective_sense};
+
+/// Keeps a model's lookups and solved constraint blocks around so that data
+/// changes only regenerate what they affect
+pub struct Generator {
+    lookups: Lookups,
+    param_decls: HashMap<Spur, ir::Param>,
+    set_dimens: HashMap<Spur, u32>,
+    constraints: Vec<ConstraintOrObjective>,
+    /// Every set and param each constraint depends on, directly or through
+    /// other sets and params
+    deps: Vec<HashSet<Spur>>,
+    /// The same for each set and param
+    data_deps: HashMap<Spur, HashSet<Spur>>,
+    blocks: Vec<Vec<SolvedConstraint>>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChangeKind {
+    Added,
+    Removed,
+    Modified,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RowChange {
+    /// Row name as in the MPS output, eg `supply_limit[P1]`
+    pub row: String,
+    pub kind: ChangeKind,
+}
+
+/// What an `update` touched
+#[derive(Debug, Default)]
+pub struct Update {
+    /// Names of the constraints (and objective) that were regenerated
+    pub regenerated: Vec<String>,
+    /// Rows that were added, removed or whose coefficients or RHS changed
+    pub rows: Vec<RowChange>,
+}
+
+impl Generator {
+    pub fn new(model: ModelWithData) -> Self {
+        let ModelWithData {
+            sets,
+            vars,
+            pars,
+            constraints,
+        } = model;
+
+        let mut direct: HashMap<Spur, HashSet<Spur>> = HashMap::new();
+        for set in &sets {
+            direct.insert(set.decl.name, Refs::of_set(&set.decl));
+        }
+        for par in &pars {
+            direct.insert(par.decl.name, Refs::of_param(&par.decl));
+        }
+        let deps = constraints
+            .iter()
+            .map(|con| transitive(Refs::of_constraint(con), &direct))
+            .collect();
+        let data_deps = direct
+            .iter()
+            .map(|(name, refs)| (*name, transitive(refs.clone(), &direct)))
+            .collect();
+
+        let param_decls = pars
+            .iter()
+            .map(|par| (par.decl.name, par.decl.clone()))
+            .collect();
+        let set_dimens = sets
+            .iter()
+            .filter_map(|set| set.decl.dimen.map(|dimen| (set.decl.name, dimen)))
+            .collect();
+
+        let mut lookups = Lookups::from_model(sets, vars, pars);
+        lookups.prepare(&constraints);
+        let blocks = constraints
+            .par_iter()
+            .map(|con| build_constraint(con, &lookups))
+            .collect();
+
+        Generator {
+            lookups,
+            param_decls,
+            set_dimens,
+            constraints,
+            deps,
+            data_deps,
+            blocks,
+        }
+    }
+
+    /// Apply a data delta (set and param data statements, eg from `load_data`).
+    /// Param values replace existing values key by key, set data replaces the
+    /// set (or the given index of an indexed set), the same as a later data file.
+    /// Nothing is applied if the delta has anything else, or data for a name the
+    /// model doesn't declare.
+    pub fn update(&mut self, delta: Vec<Entry>) -> Result<Update, Diagnostic> {
+        let mut entries = Vec::with_capacity(delta.len());
+        for entry in delta {
+            match entry {
+                Entry::DataTabbing(tabbing) => {
+                    let dimen = self
+                        .param_decls
+                        .get(&tabbing.params[0])
+                        .and_then(|decl| decl.domain.as_ref())
+                        .map_or(0, Domain::dimen);
+                    let (data, set) = tabbing.split(dimen);
+                    entries.extend(set.map(Entry::DataSet));
+                    entries.extend(data.into_iter().map(Entry::DataParam));
+                }
+                other => entries.push(other),
+            }
+        }
+        for entry in &entries {
+            let (kind, name, declared) = match entry {
+                Entry::DataParam(data) => (
+                    "param",
+                    data.name,
+                    self.param_decls.contains_key(&data.name),
+                ),
+                Entry::DataSet(data) => (
+                    "set",
+                    data.name,
+                    self.lookups.set_map.contains_key(&data.name),
+                ),
+                other => {
+                    return Err(Diagnostic::error(format!(
+                        "only data statements can be applied as an update, got: {other}"
+                    )));
+                }
+            };
+            if !declared {
+                return Err(Diagnostic::error(format!(
+                    "data {kind} {} has no matching model declaration",
+                    intern_resolve(name)
+                )));
+            }
+        }
+
+        let mut changed: HashSet<Spur> = HashSet::new();
+        for entry in entries {
+            match entry {
+                Entry::DataParam(data) => {
+                    let name = data.name;
+                    let dimen = self.param_decls[&name]
+                        .domain
+                        .as_ref()
+                        .map_or(0, Domain::dimen);
+                    let param = resolve_param_data(data, dimen);
+                    self.lookups
+                        .par_map
+                        .get_mut(&name)
+                        .unwrap()
+                        .override_with(param);
+                    changed.insert(name);
+                }
+                Entry::DataSet(mut data) => {
+                    let name = data.name;
+                    if let Some(&dimen) = self.set_dimens.get(&name)
+                        && dimen > 1
+                    {
+                        data.values = regroup_set_values(&data.values, dimen as usize);
+                    }
+                    self.lookups.set_map[&name].insert(data);
+                    changed.insert(name);
+                }
+                _ => unreachable!("checked above"),
+            }
+        }
+
+        // Only the sets, computed params and constraints that depend on the
+        // changed data are worked out again
+        let affected: HashSet<Spur> = self
+            .data_deps
+            .iter()
+            .filter(|(_, deps)| !deps.is_disjoint(&changed))
+            .map(|(name, _)| *name)
+            .chain(changed.iter().copied())
+            .collect();
+        let stale: Vec<usize> = self
+            .deps
+            .iter()
+            .enumerate()
+            .filter(|(_, deps)| !deps.is_disjoint(&changed))
+            .map(|(i, _)| i)
+            .collect();
+        self.lookups.forget_computed(&affected);
+        self.lookups
+            .prepare_only(stale.iter().map(|i| &self.constraints[*i]), &affected);
+
+        let rebuilt: Vec<Vec<SolvedConstraint>> = stale
+            .clone()
+            .into_par_iter()
+            .map(|i| build_constraint(&self.constraints[i], &self.lookups))
+            .collect();
+
+        let mut update = Update::default();
+        for (i, block) in stale.into_iter().zip(rebuilt) {
+            update
+                .regenerated
+                .push(intern_resolve(self.constraints[i].name).to_string());
+            diff_block(&self.blocks[i], &block, &mut update.rows);
+            self.blocks[i] = block;
+        }
+        Ok(update)
+    }
+
+    /// Assemble the current matrix from the cached blocks
+    pub fn compiled(&self) -> Compiled {
+        let mut builder = MatrixBuilder::new(&self.lookups);
+        for row in self.blocks.iter().flatten() {
+            builder.push(row);
+        }
+        builder.finish(objective_sense(&self.constraints))
+    }
+}
+
+/// Follow references through params and sets until nothing new is found
+fn transitive(mut deps: HashSet<Spur>, direct: &HashMap<Spur, HashSet<Spur>>) -> HashSet<Spur> {
+    let mut todo: Vec<Spur> = deps.iter().copied().collect();
+    while let Some(name) = todo.pop() {
+        for dep in direct.get(&name).into_iter().flatten() {
+            if deps.insert(*dep) {
+                todo.push(*dep);
+            }
+        }
+    }
+    deps
+}
+
+fn diff_block(old: &[SolvedConstraint], new: &[SolvedConstraint], changes: &mut Vec<RowChange>) {
+    let old_rows: HashMap<&Index, &SolvedConstraint> =
+        old.iter().map(|row| (row.idx.as_ref(), row)).collect();
+    let new_idxs: HashSet<&Index> = new.iter().map(|row| row.idx.as_ref()).collect();
+
+    for row in new {
+        let kind = match old_rows.get(row.idx.as_ref()) {
+            None => ChangeKind::Added,
+            Some(old_row) if *old_row != row => ChangeKind::Modified,
+            Some(_) => continue,
+        };
+        changes.push(RowChange {
+            row: index_name(row.name, &row.idx),
+            kind,
+        });
+    }
+    for row in old {
+        if !new_idxs.contains(row.idx.as_ref()) {
+            changes.push(RowChange {
+                row: index_name(row.name, &row.idx),
+                kind: ChangeKind::Removed,
+            });
+        }
+    }
+}
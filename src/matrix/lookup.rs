@@ -1,17 +1,21 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
 use indexmap::IndexMap;
 use lasso::Spur;
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
 
 use crate::{
     ir::{
-        self,
+        self, Index,
         model::{ParamWithData, SetWithData},
         op::Bounds,
     },
     matrix::{
-        param::{Param, resolve_param},
+        cache::Expansion,
+        constraint::{IdxValMap, domain_to_indexes, get_index_map},
+        param::{Param, ParamMemo, ParamVal, resolve_param},
         set::SetCont,
+        sparse::Sparse,
     },
 };
 
@@ -19,6 +23,12 @@ pub struct Lookups {
     pub set_map: IndexMap<Spur, SetCont>,
     pub var_map: HashMap<Spur, Bounds>,
     pub par_map: HashMap<Spur, Param>,
+    /// Domains expanded by `prepare`, keyed by `cache::domain_key`
+    pub domains: HashMap<usize, Expansion>,
+    /// Domains to expand from a param's keys, also from `prepare`
+    pub sparse: HashMap<usize, Sparse>,
+    /// How computed params keep their values
+    pub memo: ParamMemo,
 }
 
 impl Lookups {
@@ -26,6 +36,15 @@ impl Lookups {
         sets: Vec<SetWithData>,
         vars: Vec<ir::Var>,
         pars: Vec<ParamWithData>,
+    ) -> Self {
+        Self::from_model_with(sets, vars, pars, ParamMemo::default())
+    }
+
+    pub fn from_model_with(
+        sets: Vec<SetWithData>,
+        vars: Vec<ir::Var>,
+        pars: Vec<ParamWithData>,
+        memo: ParamMemo,
     ) -> Self {
         Lookups {
             set_map: sets
@@ -40,6 +59,45 @@ impl Lookups {
                 .into_iter()
                 .map(|param| (param.decl.name, resolve_param(param)))
                 .collect(),
+            domains: HashMap::new(),
+            sparse: HashMap::new(),
+            memo,
+        }
+    }
+
+    /// Forget the values of the computed params in `params` after a data change.
+    /// Eager params are evaluated again by `prepare_only`.
+    pub fn forget_computed(&mut self, params: &HashSet<Spur>) {
+        for (name, param) in self.par_map.iter_mut() {
+            if params.contains(name) {
+                param.forget();
+            }
+        }
+    }
+
+    /// Evaluate every computed param over its whole domain, if they're eager.
+    /// Run once the sets have been resolved, so it sees their current members.
+    pub(crate) fn compute_params(&self) {
+        if self.memo != ParamMemo::Eager {
+            return;
+        }
+        for param in self.par_map.values() {
+            let ParamVal::Expr(expr) = &param.data else {
+                continue;
+            };
+            let Some(domain) = &param.domain else {
+                param.computed(expr, &Index::new(), self);
+                continue;
+            };
+            domain_to_indexes(domain, self, &IdxValMap::new())
+                .par_iter()
+                .for_each(|idx| {
+                    let index = get_index_map(&domain.parts, idx)
+                        .into_iter()
+                        .map(|(_, val)| val)
+                        .collect();
+                    param.computed(expr, &index, self);
+                });
         }
     }
 }
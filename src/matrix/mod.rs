@@ -1,83 +1,257 @@
-mod constraint;
-mod lookup;
-mod param;
+mod bytecode;
+mod cache;
+pub(crate) mod constraint;
+pub(crate) mod explain;
+pub(crate) mod incremental;
+pub(crate) mod lookup;
+pub(crate) mod param;
+pub(crate) mod reduce;
 mod set;
+mod sparse;
+pub(crate) mod stats;
 
 use std::sync::Arc;
 
-use indexmap::IndexMap;
+use indexmap::{Equivalent, IndexMap};
 use lasso::Spur;
-use rayon::iter::{IntoParallelIterator, ParallelIterator};
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
 use smallvec::SmallVec;
 
-use crate::ir::Index;
+use crate::analysis::Diagnostic;
+use crate::ir::interner::{intern, intern_resolve};
 use crate::ir::model::{ConstraintOrObjective, ModelWithData};
 use crate::ir::op::{Bounds, RowType};
-use crate::matrix::constraint::{Pair, algebra, domain_to_indexes, get_index_map, recurse};
+use crate::ir::{Index, ObjSense, SetVal, glob_match, index_name};
+use crate::matrix::bytecode::{Machine, Program};
+use crate::matrix::constraint::{Pair, domain_to_indexes, get_index_map};
 use crate::matrix::lookup::Lookups;
+use crate::matrix::param::ParamMemo;
 
-pub struct VarWithCoefficients {
-    pub bounds: Bounds,
-    /// coeffs is a map of (constraint_name, constraint_index) -> coefficient
-    pub coeffs: IndexMap<(Spur, Arc<Index>), f64>,
-}
-
-/// VarsMap is a map of (var_name, var_index) -> var bounds & coefficients
-pub(crate) type VarsMap = IndexMap<(Spur, Arc<Index>), VarWithCoefficients>;
+/// VarsMap is a map of (var_name, var_index) -> var bounds, in column order
+pub(crate) type VarsMap = IndexMap<(Spur, Index), Bounds>;
 /// ConsMap is an array of (constraint_name, constraint_index, row_type, rhs)
 pub(crate) type ConsMap = Vec<(Spur, Arc<Index>, RowType, f64)>;
 
 /// The compiled matrix with vars (cols) and cons (rows).
+/// The nonzeros are stored column-major: column `j` has the entries
+/// `col_starts[j]..col_starts[j + 1]` of `row_ids` and `vals`, in row order.
 pub struct Compiled {
     pub vars: VarsMap,
     pub cons: ConsMap,
+    pub col_starts: Vec<usize>,
+    pub row_ids: Vec<u32>,
+    pub vals: Vec<f64>,
+    /// The sense of the first `N` row. The MPS output doesn't record it, so
+    /// that row is minimised whatever it is.
+    pub sense: ObjSense,
+}
+
+impl Compiled {
+    /// The (row id, coefficient) pairs of column `j`
+    pub fn col(&self, j: usize) -> impl Iterator<Item = (usize, f64)> + '_ {
+        let range = self.col_starts[j]..self.col_starts[j + 1];
+        self.row_ids[range.clone()]
+            .iter()
+            .map(|row| *row as usize)
+            .zip(self.vals[range].iter().copied())
+    }
+}
+
+/// What to generate, and how
+#[derive(Clone, Debug, Default)]
+pub struct GenOptions {
+    pub memo: ParamMemo,
+    /// Only build the constraints whose names match one of these globs (eg
+    /// `EBa11_*`), or all of them if there are none. Objectives are always built.
+    pub only: Vec<String>,
+    /// Sets to restrict to some of their members, eg `REGION` to `[RE1]`.
+    /// This applies wherever the set is used, objective included.
+    pub restrict: Vec<(String, Vec<String>)>,
+}
+
+impl GenOptions {
+    /// Check the options against the model, before `gen_matrix` relies on them
+    pub fn check(&self, model: &ModelWithData) -> Vec<Diagnostic> {
+        self.restrict
+            .iter()
+            .filter(|(set, _)| {
+                !model
+                    .sets
+                    .iter()
+                    .any(|s| intern_resolve(s.decl.name) == set)
+            })
+            .map(|(set, _)| Diagnostic::error(format!("there's no set named {set} to restrict")))
+            .collect()
+    }
+
+    /// Restrict the sets in `lookups`, which `check` has found are all declared
+    pub(crate) fn restrict_sets(&self, lookups: &mut Lookups) {
+        for (set, members) in &self.restrict {
+            let Some(set_cont) = lookups.set_map.get_mut(&intern(set)) else {
+                panic!("Restricted set '{set}' has no matching model declaration");
+            };
+            set_cont.restrict(members.iter().map(|member| SetVal::parse(member)).collect());
+        }
+    }
 }
 
-pub fn gen_matrix(model: ModelWithData) -> Compiled {
+pub fn gen_matrix(model: ModelWithData, options: &GenOptions) -> Compiled {
     let ModelWithData {
         sets,
         pars,
         vars,
-        constraints,
+        mut constraints,
     } = model;
-    let lookups = Lookups::from_model(sets, vars, pars);
-    let cons = build_constraints(constraints, &lookups);
-    build_cols_and_rows(cons, &lookups)
+    if !options.only.is_empty() {
+        constraints.retain(|con| {
+            let name = intern_resolve(con.name);
+            con.row_type == RowType::Unconstrained
+                || options.only.iter().any(|glob| glob_match(glob, name))
+        });
+    }
+    let mut lookups = Lookups::from_model_with(sets, vars, pars, options.memo);
+    options.restrict_sets(&mut lookups);
+    lookups.prepare(&constraints);
+    let mut builder = MatrixBuilder::new(&lookups);
+    // One constraint at a time, so only its rows (rather than every row) are
+    // held as solved pairs before they're flattened
+    for con in &constraints {
+        for row in build_constraint(con, &lookups) {
+            builder.push(&row);
+        }
+    }
+    builder.finish(objective_sense(&constraints))
 }
 
-fn build_cols_and_rows(cons: Vec<SolvedConstraint>, lookups: &Lookups) -> Compiled {
-    let mut rows: ConsMap = vec![];
-    let mut cols: VarsMap = IndexMap::new();
-    for SolvedConstraint {
-        name,
-        idx,
-        row_type,
-        rhs,
-        pairs,
-    } in cons
-    {
-        rows.push((name, idx.clone(), row_type, rhs));
-        for pair in pairs {
-            cols.entry((pair.var, Arc::new(pair.index)))
-                .or_insert_with(|| VarWithCoefficients {
-                    bounds: *lookups.var_map.get(&pair.var).unwrap(),
-                    coeffs: IndexMap::new(),
-                })
-                .coeffs
-                .entry((name, idx.clone()))
-                // With big sums, the same Var can appear multiple times, so we must accumulate the
-                // coefficients
-                .and_modify(|v| *v += pair.coeff)
-                .or_insert(pair.coeff);
+/// The sense of the first objective, which is the one generated as the first `N` row
+fn objective_sense(constraints: &[ConstraintOrObjective]) -> ObjSense {
+    constraints
+        .iter()
+        .find_map(|con| con.sense)
+        .unwrap_or(ObjSense::Minimize)
+}
+
+/// Looks up a column without cloning its index
+#[derive(Hash, PartialEq, Eq)]
+struct ColKey<'a>(Spur, &'a Index);
+
+impl Equivalent<(Spur, Index)> for ColKey<'_> {
+    fn equivalent(&self, key: &(Spur, Index)) -> bool {
+        self.0 == key.0 && *self.1 == key.1
+    }
+}
+
+/// Assigns row and column ids as solved rows come in, and keeps the
+/// coefficients as flat (col, row, value) triplets until they're all in
+struct MatrixBuilder<'a> {
+    lookups: &'a Lookups,
+    vars: VarsMap,
+    cons: ConsMap,
+    cols: Vec<u32>,
+    rows: Vec<u32>,
+    vals: Vec<f64>,
+}
+
+impl<'a> MatrixBuilder<'a> {
+    fn new(lookups: &'a Lookups) -> Self {
+        MatrixBuilder {
+            lookups,
+            vars: IndexMap::new(),
+            cons: vec![],
+            cols: vec![],
+            rows: vec![],
+            vals: vec![],
+        }
+    }
+
+    fn push(&mut self, con: &SolvedConstraint) {
+        let row = self.cons.len() as u32;
+        self.cons
+            .push((con.name, con.idx.clone(), con.row_type, con.rhs));
+        for pair in &con.pairs {
+            let col = match self.vars.get_index_of(&ColKey(pair.var, &pair.index)) {
+                Some(col) => col,
+                None => {
+                    let bounds = *self.lookups.var_map.get(&pair.var).unwrap();
+                    self.vars
+                        .insert_full((pair.var, pair.index.clone()), bounds)
+                        .0
+                }
+            };
+            self.cols.push(col as u32);
+            self.rows.push(row);
+            self.vals.push(pair.coeff);
         }
     }
 
-    Compiled {
-        vars: cols,
-        cons: rows,
+    /// Bucket the triplets by column (keeping them in row order within each)
+    /// and sum the coefficients of any var that appears more than once in a row
+    fn finish(self, sense: ObjSense) -> Compiled {
+        let MatrixBuilder {
+            vars,
+            cons,
+            cols,
+            rows,
+            vals,
+            ..
+        } = self;
+
+        let mut col_starts = vec![0; vars.len() + 1];
+        for col in &cols {
+            col_starts[*col as usize + 1] += 1;
+        }
+        for j in 0..vars.len() {
+            col_starts[j + 1] += col_starts[j];
+        }
+        let mut next = col_starts.clone();
+        let mut row_ids = vec![0; cols.len()];
+        let mut sorted_vals = vec![0.0; cols.len()];
+        for ((col, row), val) in cols.into_iter().zip(rows).zip(vals) {
+            let at = &mut next[col as usize];
+            row_ids[*at] = row;
+            sorted_vals[*at] = val;
+            *at += 1;
+        }
+
+        // With big sums, the same var can appear in a row multiple times, so the
+        // coefficients must be accumulated (those that cancel out are dropped)
+        let mut out = 0;
+        let mut start = 0;
+        for j in 0..vars.len() {
+            let end = col_starts[j + 1];
+            let mut i = start;
+            while i < end {
+                let row = row_ids[i];
+                let mut val = sorted_vals[i];
+                i += 1;
+                while i < end && row_ids[i] == row {
+                    val += sorted_vals[i];
+                    i += 1;
+                }
+                if val != 0.0 {
+                    row_ids[out] = row;
+                    sorted_vals[out] = val;
+                    out += 1;
+                }
+            }
+            start = end;
+            col_starts[j + 1] = out;
+        }
+        row_ids.truncate(out);
+        sorted_vals.truncate(out);
+
+        Compiled {
+            vars,
+            cons,
+            col_starts,
+            row_ids,
+            vals: sorted_vals,
+            sense,
+        }
     }
 }
 
+#[derive(Clone, PartialEq)]
 struct SolvedConstraint {
     name: Spur,
     idx: Arc<Index>,
@@ -86,44 +260,274 @@ struct SolvedConstraint {
     pairs: Vec<Pair>,
 }
 
-fn build_constraints(
-    constraints: Vec<ConstraintOrObjective>,
-    lookups: &Lookups,
-) -> Vec<SolvedConstraint> {
-    constraints
-        .into_par_iter()
-        .flat_map(
-            |ConstraintOrObjective {
-                 name,
-                 domain,
-                 row_type,
-                 lhs,
-                 rhs,
-             }| {
-                // let row_type = Arc::new(row_type);
-
-                let (indexes, parts) = domain
-                    .map(|d| (domain_to_indexes(&d, lookups, &SmallVec::new()), d.parts))
-                    .unwrap_or_else(|| (vec![vec![].into()], vec![]));
-
-                indexes
-                    .into_par_iter()
-                    .map(|con_index| {
-                        let con_index = Arc::new(con_index);
-                        let idx_val_map = get_index_map(&parts, &con_index);
-                        let lhs = recurse(&lhs, lookups, &idx_val_map);
-                        let rhs = recurse(&rhs, lookups, &idx_val_map);
-                        let (pairs, rhs_total) = algebra(lhs, rhs);
-                        SolvedConstraint {
-                            name,
-                            idx: con_index,
-                            row_type,
-                            rhs: rhs_total,
-                            pairs,
-                        }
-                    })
-                    .collect::<Vec<_>>()
-            },
-        )
-        .collect()
+/// Expand a single constraint (or objective) over its domain into solved rows
+fn build_constraint(con: &ConstraintOrObjective, lookups: &Lookups) -> Vec<SolvedConstraint> {
+    let ConstraintOrObjective {
+        name,
+        domain,
+        row_type,
+        lhs,
+        rhs,
+        upper,
+        ops: _,
+        active,
+        sense: _,
+    } = con;
+    if let Some(message) = con.relation_error() {
+        panic!("{}: {message}", intern_resolve(*name));
+    }
+
+    let (indexes, parts) = domain
+        .as_ref()
+        .map(|d| {
+            (
+                domain_to_indexes(d, lookups, &SmallVec::new()),
+                d.parts.as_slice(),
+            )
+        })
+        .unwrap_or_else(|| (vec![Index::new()].into(), &[]));
+
+    let lhs = Program::compile(lhs, lookups);
+    let rhs = Program::compile(rhs, lookups);
+    let upper = upper.as_ref().map(|upper| Program::compile(upper, lookups));
+    let mut rows: Vec<SolvedConstraint> = indexes
+        .par_iter()
+        .map_init(Machine::default, |machine, con_index| {
+            let con_index = Arc::new(con_index.clone());
+            let idx_val_map = get_index_map(parts, &con_index);
+            let row_type = match &upper {
+                Some(upper) => {
+                    let bound = |machine: &mut Machine, program| {
+                        machine
+                            .constant(program, lookups, &idx_val_map)
+                            .unwrap_or_else(|| {
+                                panic!(
+                                    "{}: the bounds of a double inequality can't have vars",
+                                    index_name(*name, &con_index)
+                                )
+                            })
+                    };
+                    let width = bound(machine, upper) - bound(machine, &rhs);
+                    if width < 0.0 {
+                        panic!(
+                            "{}: the lower bound of a double inequality is above its upper bound",
+                            index_name(*name, &con_index)
+                        );
+                    }
+                    RowType::Ranged(width)
+                }
+                None => *row_type,
+            };
+            let (pairs, rhs_total) = machine.solve(&lhs, &rhs, lookups, &idx_val_map);
+            SolvedConstraint {
+                name: *name,
+                idx: con_index,
+                row_type,
+                rhs: rhs_total,
+                pairs,
+            }
+        })
+        .collect();
+    // The first N row is the one that's minimised
+    if let Some(active) = active {
+        let Some(at) = rows.iter().position(|row| *row.idx == *active) else {
+            panic!(
+                "{} isn't in the domain of {}",
+                index_name(*name, active),
+                intern_resolve(*name)
+            );
+        };
+        let row = rows.remove(at);
+        rows.insert(0, row);
+    }
+    rows
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::*;
+    use crate::{load_model_and_data, merge_model, parse_model};
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("EBa11_*", "EBa11_EnergyBalanceEachTS5"));
+        assert!(glob_match("*Balance*TS?", "EBa11_EnergyBalanceEachTS5"));
+        assert!(glob_match("cost", "cost"));
+        assert!(!glob_match("EBa1_*", "EBa11_EnergyBalanceEachTS5"));
+        assert!(!glob_match("*TS", "EBa11_EnergyBalanceEachTS5"));
+    }
+
+    #[test]
+    fn test_only_and_restrict() {
+        let text = r#"
+            set I;
+            set J;
+            var x{i in I, j in J} >= 0;
+            minimize cost: sum{i in I, j in J} x[i, j];
+            s.t. supply{i in I}: sum{j in J} x[i, j] <= 10;
+            s.t. demand{j in J}: sum{i in I} x[i, j] >= 1;
+            data;
+            set I := a b;
+            set J := u v w;
+        "#;
+        let options = GenOptions {
+            only: vec!["dem*".to_string()],
+            restrict: vec![("J".to_string(), vec!["u".to_string(), "w".to_string()])],
+            ..GenOptions::default()
+        };
+        let model = merge_model(parse_model(text));
+        assert!(options.check(&model).is_empty());
+        let compiled = gen_matrix(model.clone(), &options);
+        let rows: Vec<String> = compiled
+            .cons
+            .iter()
+            .map(|(name, idx, _, _)| index_name(*name, idx))
+            .collect();
+        assert_eq!(rows, vec!["demand[u]", "demand[w]", "cost"]);
+        let cols: Vec<String> = compiled
+            .vars
+            .keys()
+            .map(|(name, idx)| index_name(*name, idx))
+            .collect();
+        assert_eq!(cols, vec!["x[a,u]", "x[b,u]", "x[a,w]", "x[b,w]"]);
+
+        let options = GenOptions {
+            restrict: vec![("K".to_string(), vec!["u".to_string()])],
+            ..GenOptions::default()
+        };
+        let diags = options.check(&model);
+        assert_eq!(diags.len(), 1);
+        assert_eq!(diags[0].message, "there's no set named K to restrict");
+    }
+
+    #[test]
+    fn test_restrict_eager() {
+        // Eager params are evaluated after the sets are restricted
+        let text = r#"
+            set J;
+            param n := sum{j in J} 1;
+            var y >= 0;
+            minimize cost: y;
+            s.t. c: y >= n;
+            data;
+            set J := u v w;
+        "#;
+        let options = GenOptions {
+            memo: ParamMemo::Eager,
+            restrict: vec![("J".to_string(), vec!["u".to_string()])],
+            ..GenOptions::default()
+        };
+        let compiled = gen_matrix(merge_model(parse_model(text)), &options);
+        assert_eq!(compiled.cons[0].3, 1.0);
+    }
+
+    #[test]
+    fn test_range_constraint() {
+        let text = r#"
+            set I;
+            param lo{i in I};
+            var x{i in I} >= 0;
+            minimize cost: sum{i in I} x[i];
+            s.t. margin{i in I}: lo[i] <= 2 * x[i] + 1 <= lo[i] + 4;
+            s.t. flipped: 10 >= sum{i in I} x[i] >= 3;
+            data;
+            set I := a b;
+            param lo := a 1 b 5;
+        "#;
+        let compiled = gen_matrix(merge_model(parse_model(text)), &GenOptions::default());
+        let rows: Vec<(String, RowType, f64)> = compiled
+            .cons
+            .iter()
+            .map(|(name, idx, row_type, rhs)| (index_name(*name, idx), *row_type, *rhs))
+            .collect();
+        assert_eq!(
+            rows,
+            vec![
+                ("margin[a]".to_string(), RowType::Ranged(4.0), 0.0),
+                ("margin[b]".to_string(), RowType::Ranged(4.0), 4.0),
+                ("flipped".to_string(), RowType::Ranged(7.0), 3.0),
+                ("cost".to_string(), RowType::Unconstrained, 0.0),
+            ]
+        );
+    }
+
+    /// Where generation spends its time on osemosys, with and without the
+    /// up-front set and domain pass:
+    /// `cargo test --release bench_gen_matrix -- --ignored --nocapture`
+    #[test]
+    #[ignore]
+    fn bench_gen_matrix() {
+        let ModelWithData {
+            sets,
+            vars,
+            pars,
+            constraints,
+        } = merge_model(load_model_and_data(
+            "examples/osemosys.mod",
+            &["examples/ose_atl.dat"],
+        ));
+
+        let t = Instant::now();
+        let mut lookups = Lookups::from_model(sets, vars, pars);
+        eprintln!("lookups: {:?}", t.elapsed());
+
+        // (total, domain only, name, rows) for each constraint, slowest first
+        let time_constraints = |lookups: &Lookups| {
+            let mut times: Vec<(Duration, Duration, Spur, usize)> = constraints
+                .iter()
+                .map(|con| {
+                    let t = Instant::now();
+                    if let Some(domain) = &con.domain {
+                        domain_to_indexes(domain, lookups, &SmallVec::new());
+                    }
+                    let domain = t.elapsed();
+                    let t = Instant::now();
+                    let rows = build_constraint(con, lookups).len();
+                    (t.elapsed(), domain, con.name, rows)
+                })
+                .collect();
+            times.sort_by_key(|t| std::cmp::Reverse(t.0));
+            times
+        };
+
+        let unprepared = time_constraints(&lookups);
+        let t = Instant::now();
+        lookups.prepare(&constraints);
+        eprintln!("prepare: {:?}", t.elapsed());
+        let prepared = time_constraints(&lookups);
+
+        let total = |times: &[(Duration, Duration, Spur, usize)]| {
+            times.iter().map(|t| t.0).sum::<Duration>()
+        };
+        eprintln!(
+            "constraints: {:?} unprepared, {:?} prepared",
+            total(&unprepared),
+            total(&prepared)
+        );
+        for (elapsed, domain, name, rows) in prepared.iter().take(15) {
+            let before = unprepared.iter().find(|t| t.2 == *name).unwrap().0;
+            eprintln!(
+                "  {:<40} {rows:>7} rows {elapsed:>10.2?} (domain {domain:.2?}, unprepared {before:.2?})",
+                intern_resolve(*name)
+            );
+        }
+
+        let t = Instant::now();
+        let mut builder = MatrixBuilder::new(&lookups);
+        for con in &constraints {
+            for row in build_constraint(con, &lookups) {
+                builder.push(&row);
+            }
+        }
+        let compiled = builder.finish(objective_sense(&constraints));
+        eprintln!(
+            "gen_matrix (parallel): {:?} for {} rows, {} cols, {} nonzeros",
+            t.elapsed(),
+            compiled.cons.len(),
+            compiled.vars.len(),
+            compiled.vals.len()
+        );
+    }
 }
@@ -1,11 +1,48 @@
 use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
 
+use dashmap::DashMap;
+use dashmap::mapref::entry::Entry;
+use lasso::Spur;
+
+use crate::ir::interner::intern_resolve;
 use crate::ir::model::ParamWithData;
-use crate::ir::{Expr, Index, ParamAssign, ParamDataBody, ParamDataTarget, SetVal};
+use crate::ir::{
+    Domain, DomainPartVar, Expr, Index, ParamAssign, ParamData, ParamDataBody, ParamDataRecord,
+    ParamDataTarget, SetVal,
+};
+use crate::matrix::constraint::{IdxValMap, Term, recurse};
+use crate::matrix::lookup::Lookups;
+
+/// How the values of computed params are kept once they've been evaluated
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ParamMemo {
+    /// Evaluate each index the first time it's used, keeping at most this many
+    /// values per param (any more are evaluated every time)
+    Lazy(usize),
+    /// Evaluate every index of every computed param when the lookups are prepared
+    Eager,
+}
+
+impl Default for ParamMemo {
+    fn default() -> Self {
+        ParamMemo::Lazy(1 << 20)
+    }
+}
+
+/// The values of a computed param by index, shared by every thread generating rows
+#[derive(Default)]
+pub struct Memo {
+    values: DashMap<Index, f64>,
+    len: AtomicUsize,
+}
 
 pub struct Param {
     pub data: ParamVal,
     pub default: Option<Expr>,
+    /// The param's own indices, which a computed value is evaluated over
+    pub domain: Option<Domain>,
+    pub memo: Memo,
 }
 pub enum ParamVal {
     Arr(HashMap<Index, f64>),
@@ -14,80 +51,292 @@ pub enum ParamVal {
     None,
 }
 
+impl Param {
+    /// Overlay `other` on this param: its values replace existing ones key by key,
+    /// and its default (if any) replaces the current default
+    pub fn override_with(&mut self, other: Param) {
+        if other.default.is_some() {
+            self.default = other.default;
+        }
+        match (&mut self.data, other.data) {
+            (_, ParamVal::None) => {}
+            (ParamVal::Arr(arr), ParamVal::Arr(other)) => arr.extend(other),
+            (data, other) => *data = other,
+        }
+    }
+
+    /// The value of a computed param at `index`, with the param's own indices
+    /// bound to it. Each value is only evaluated once, up to the memo's limit.
+    pub fn computed(&self, expr: &Expr, index: &Index, lookups: &Lookups) -> f64 {
+        if let Some(val) = self.memo.values.get(index) {
+            return *val;
+        }
+
+        let dummies = self.domain.iter().flat_map(|domain| &domain.parts);
+        let idx_val_map: IdxValMap = dummies
+            .flat_map(|part| match &part.var {
+                DomainPartVar::Single(id) => vec![*id],
+                DomainPartVar::Tuple(ids) => ids.clone(),
+            })
+            .zip(index.iter().copied())
+            .collect();
+        let val = recurse(expr, lookups, &idx_val_map)
+            .iter()
+            .map(|term| match term {
+                Term::Num(num) => *num,
+                _ => panic!("computed param must evaluate to a number"),
+            })
+            .sum();
+
+        let limit = match lookups.memo {
+            ParamMemo::Lazy(limit) => limit,
+            ParamMemo::Eager => usize::MAX,
+        };
+        // Take a slot before inserting, and give it back if there's no room or
+        // another thread got there first, so each value is only counted once
+        if self.memo.len.fetch_add(1, Ordering::Relaxed) < limit {
+            match self.memo.values.entry(index.clone()) {
+                Entry::Vacant(entry) => {
+                    entry.insert(val);
+                }
+                Entry::Occupied(_) => {
+                    self.memo.len.fetch_sub(1, Ordering::Relaxed);
+                }
+            }
+        } else {
+            self.memo.len.fetch_sub(1, Ordering::Relaxed);
+        }
+        val
+    }
+
+    /// Forget the computed values, as the data they came from may have changed
+    pub fn forget(&mut self) {
+        self.memo = Memo::default();
+    }
+}
+
 pub fn resolve_param(param: ParamWithData) -> Param {
-    let default = resolve_param_default(&param);
-    if let Some(data) = param.data
-        && let Some(body) = data.body
-    {
-        match body {
-            ParamDataBody::Num(num) => Param {
-                data: ParamVal::Scalar(num),
-                default,
-            },
-            ParamDataBody::List(pairs) => {
-                let mut arr: HashMap<Index, f64> = HashMap::new();
-                for pair in pairs {
-                    arr.insert(vec![pair.key].into(), pair.value);
+    let ParamWithData { decl, data } = param;
+    let dimen = decl.domain.as_ref().map_or(0, Domain::dimen);
+    let mut resolved = Param {
+        data: match decl.assign {
+            Some(ParamAssign::Expr(expr)) => ParamVal::Expr(expr),
+            _ => ParamVal::None,
+        },
+        default: decl.default,
+        domain: decl.domain,
+        memo: Memo::default(),
+    };
+    // Data statements are applied in the order they were given,
+    // so later data files override earlier ones key by key
+    for data in data {
+        resolved.override_with(resolve_param_data(data, dimen));
+    }
+    resolved
+}
+
+/// Resolve a single data statement for a param with `dimen` keys,
+/// without anything from the declaration
+pub fn resolve_param_data(data: ParamData, dimen: usize) -> Param {
+    let values = match data.body {
+        Some(ParamDataBody::Num(num)) => ParamVal::Scalar(num),
+        Some(ParamDataBody::Records(records)) => {
+            ParamVal::Arr(resolve_records(data.name, records, dimen))
+        }
+        None => ParamVal::None,
+    };
+    Param {
+        data: values,
+        default: data.default.map(Expr::Number),
+        domain: None,
+        memo: Memo::default(),
+    }
+}
+
+/// Expand data records into values keyed by the full index of the param.
+/// A slice like `[Atlantis_00A,NGCC,*,*]` fixes the key positions that aren't
+/// `*` for the records after it, and the free positions are filled in order
+/// by the keys in lists, or the row and column of tables.
+fn resolve_records(name: Spur, records: Vec<ParamDataRecord>, dimen: usize) -> HashMap<Index, f64> {
+    let mut arr: HashMap<Index, f64> = HashMap::new();
+    let mut slice: Vec<Option<SetVal>> = vec![None; dimen];
+    let mut free: Vec<usize> = (0..dimen).collect();
+
+    let key = |slice: &[Option<SetVal>]| -> Index { slice.iter().map(|v| v.unwrap()).collect() };
+
+    for record in records {
+        match record {
+            ParamDataRecord::Slice(targets) => {
+                if targets.len() != dimen {
+                    panic!(
+                        "slice for param '{}' has {} position(s) but the param has {dimen}",
+                        intern_resolve(name),
+                        targets.len()
+                    );
+                }
+                slice = targets
+                    .into_iter()
+                    .map(|t| match t {
+                        ParamDataTarget::IndexVar(idx) => Some(idx),
+                        ParamDataTarget::Any => None,
+                    })
+                    .collect();
+                free = (0..dimen).filter(|i| slice[*i].is_none()).collect();
+            }
+            ParamDataRecord::List(tokens) => {
+                let width = free.len() + 1;
+                if !tokens.len().is_multiple_of(width) {
+                    panic!(
+                        "list data for param '{}' doesn't split into records of {} key(s) and a value",
+                        intern_resolve(name),
+                        free.len()
+                    );
                 }
-                Param {
-                    data: ParamVal::Arr(arr),
-                    default,
+                for record in tokens.chunks(width) {
+                    for (pos, token) in free.iter().zip(record) {
+                        slice[*pos] = Some(token.key());
+                    }
+                    if let Some(value) = record[width - 1].value() {
+                        arr.insert(key(&slice), value);
+                    }
                 }
             }
-            ParamDataBody::Tables(tables) => {
-                let mut arr: HashMap<Index, f64> = HashMap::new();
-                for table in tables {
-                    // Expressions like:
-                    // [Atlantis_00A,NGCC,NOx,*,*]:
-                    // Become prefixes for the indexes down below
-                    // NOTE: Current implementation ONLY supports having exactly two * (Any)
-                    // targets, and they must be the last two
-                    let target_idxs: Vec<SetVal> = match table.target {
-                        Some(targets) => targets
-                            .into_iter()
-                            .filter_map(|t| match t {
-                                ParamDataTarget::IndexVar(idx) => Some(idx),
-                                ParamDataTarget::Any => None,
-                            })
-                            .collect(),
-                        None => vec![],
-                    };
-                    for row in table.rows {
-                        for (col, value) in table.cols.iter().zip(row.values.iter()) {
-                            arr.insert(
-                                [target_idxs.clone(), vec![row.label, *col]].concat().into(),
-                                *value,
-                            );
+            ParamDataRecord::Table(table) => {
+                let [first, second] = free[..] else {
+                    panic!(
+                        "table data for param '{}' needs exactly two free key positions, got {}",
+                        intern_resolve(name),
+                        free.len()
+                    );
+                };
+                let (row_pos, col_pos) = match table.transposed {
+                    false => (first, second),
+                    true => (second, first),
+                };
+                for row in table.rows {
+                    slice[row_pos] = Some(row.label);
+                    for (col, value) in table.cols.iter().zip(row.values) {
+                        if let Some(value) = value {
+                            slice[col_pos] = Some(*col);
+                            arr.insert(key(&slice), value);
                         }
                     }
                 }
-                Param {
-                    data: ParamVal::Arr(arr),
-                    default,
-                }
             }
         }
-    } else if let Some(ParamAssign::Expr(expr)) = param.decl.assign {
-        Param {
-            data: ParamVal::Expr(expr),
-            default,
-        }
-    } else {
-        Param {
-            data: ParamVal::None,
-            default,
-        }
     }
+    arr
 }
 
-fn resolve_param_default(param: &ParamWithData) -> Option<Expr> {
-    if let Some(data) = &param.data {
-        if let Some(default) = data.default {
-            return Some(Expr::Number(default));
-        };
-    } else if let Some(default) = &param.decl.default {
-        return Some(default.clone());
-    };
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gmpl::loader::{consume, parse};
+    use crate::ir::index_name;
+    use crate::ir::interner::intern;
+    use crate::ir::model::ModelWithData;
+    use crate::matrix::build_constraint;
+
+    #[test]
+    fn test_resolve_data_formats() {
+        let text = r#"
+            set I; set J; set K;
+            param p{i in I, j in J, k in K};
+            param q{i in I, j in J};
+            param r{i in I, j in J};
+            param s{i in I};
+            param u{i in I};
+            var x{i in I};
+            minimize cost: sum{i in I} x[i];
+            data;
+            set J := b c;
+            set K := x y;
+            param p := a b y 5 [a,*,x] b 1 c 2.5 [*,c,*] (tr) : a b := y 3 4;
+            param q : b c := a 1 . b 3 4;
+            param r (tr) : a b := b 1 2 c 3 4;
+            param : I : s u := a 1 2 b 3 .;
+        "#;
+        let model = ModelWithData::from_entries(consume(parse(text)));
+        let mut values: Vec<(String, f64)> = model
+            .pars
+            .into_iter()
+            .flat_map(|param| {
+                let name = param.decl.name;
+                match resolve_param(param).data {
+                    ParamVal::Arr(arr) => arr
+                        .into_iter()
+                        .map(|(idx, val)| (index_name(name, &idx), val))
+                        .collect::<Vec<_>>(),
+                    _ => vec![],
+                }
+            })
+            .collect();
+        values.sort_by(|a, b| a.0.cmp(&b.0));
 
-    None
+        let expected = [
+            ("p[a,b,x]", 1.0),
+            ("p[a,b,y]", 5.0),
+            ("p[a,c,x]", 2.5),
+            ("p[a,c,y]", 3.0),
+            ("p[b,c,y]", 4.0),
+            ("q[a,b]", 1.0),
+            ("q[b,b]", 3.0),
+            ("q[b,c]", 4.0),
+            ("r[a,b]", 1.0),
+            ("r[a,c]", 3.0),
+            ("r[b,b]", 2.0),
+            ("r[b,c]", 4.0),
+            ("s[a]", 1.0),
+            ("s[b]", 3.0),
+            ("u[a]", 2.0),
+        ];
+        let expected: Vec<(String, f64)> =
+            expected.iter().map(|(k, v)| (k.to_string(), *v)).collect();
+        assert_eq!(values, expected);
+
+        let set_i = model
+            .sets
+            .iter()
+            .find(|s| intern_resolve(s.decl.name) == "I");
+        assert_eq!(set_i.unwrap().data[0].values.len(), 2);
+    }
+
+    #[test]
+    fn test_computed_memo() {
+        let text = r#"
+            set Y;
+            param w{y in Y} default 1;
+            param d{y in Y} := w[y] * 2;
+            var x{y in Y} >= 0;
+            minimize cost: sum{y in Y} d[y] * x[y];
+            s.t. c{y in Y}: d[y] * x[y] >= 1;
+            data;
+            set Y := a b c;
+            param w := b 3;
+        "#;
+        for memo in [ParamMemo::Lazy(2), ParamMemo::Eager] {
+            let ModelWithData {
+                sets,
+                vars,
+                pars,
+                constraints,
+            } = ModelWithData::from_entries(consume(parse(text)));
+            let mut lookups = Lookups::from_model_with(sets, vars, pars, memo);
+            lookups.prepare(&constraints);
+            let rows: Vec<f64> = constraints
+                .iter()
+                .flat_map(|con| build_constraint(con, &lookups))
+                .filter(|row| intern_resolve(row.name) == "c")
+                .map(|row| row.pairs[0].coeff)
+                .collect();
+            assert_eq!(rows, vec![2.0, 6.0, 2.0]);
+
+            let d = &lookups.par_map[&intern("d")];
+            let kept = match memo {
+                ParamMemo::Lazy(limit) => limit,
+                ParamMemo::Eager => 3,
+            };
+            assert_eq!(d.memo.values.len(), kept);
+        }
+    }
 }
@@ -0,0 +1,359 @@
+//! An optional pass over the generated matrix that does the first, cheap part of
+//! a solver's presolve: empty rows are dropped, rows on a single var become
+//! bounds on it, and vars that no constraint uses are fixed.
+
+use std::fmt;
+
+use indexmap::IndexMap;
+
+use crate::analysis::Diagnostic;
+use crate::ir::op::{Bounds, RowType};
+use crate::ir::{ObjSense, index_name};
+use crate::matrix::Compiled;
+
+/// How far a row or bound can be violated before it counts as infeasible
+const TOL: f64 = 1e-9;
+
+/// What `Compiled::reduce` removed, to map a solution back to the full matrix
+#[derive(Debug, Default)]
+pub struct Reduction {
+    pub reduced: Vec<Reduced>,
+    /// What the fixed vars add to the objective, which the reduced matrix
+    /// leaves out
+    pub objective_constant: f64,
+    /// Rows and bounds that can't hold, and vars the objective is unbounded in
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Reduced {
+    /// A row whose coefficients all cancelled out
+    EmptyRow { row: String },
+    /// A row on a single var, now part of its bounds. The row's dual is the
+    /// var's reduced cost divided by `coeff`.
+    BoundRow {
+        row: String,
+        col: String,
+        coeff: f64,
+        bounds: Bounds,
+    },
+    /// A var in no constraint, fixed at the bound that's best for the
+    /// objective (or nearest zero if it's not in the objective)
+    FixedCol { col: String, value: f64 },
+}
+
+/// One line per reduction, eg `bound c[a] x[a] 2 -inf 5`
+impl fmt::Display for Reduced {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Reduced::EmptyRow { row } => write!(f, "drop {row}"),
+            Reduced::BoundRow {
+                row,
+                col,
+                coeff,
+                bounds,
+            } => write!(
+                f,
+                "bound {row} {col} {coeff} {} {}",
+                bounds.lower, bounds.upper
+            ),
+            Reduced::FixedCol { col, value } => write!(f, "fix {col} {value}"),
+        }
+    }
+}
+
+impl Compiled {
+    /// Drop empty rows, fold singleton rows into bounds and fix the vars that are
+    /// then in no constraint. The objective is taken to be the first `N` row,
+    /// minimised or maximised as declared.
+    pub fn reduce(&mut self) -> Reduction {
+        let mut reduction = Reduction::default();
+        let objective = self
+            .cons
+            .iter()
+            .position(|(_, _, row_type, _)| *row_type == RowType::Unconstrained);
+
+        // The number of coefficients in each row, and the last one seen
+        let mut counts = vec![0usize; self.cons.len()];
+        let mut last = vec![(0, 0.0); self.cons.len()];
+        for j in 0..self.vars.len() {
+            for (row, val) in self.col(j) {
+                counts[row] += 1;
+                last[row] = (j, val);
+            }
+        }
+
+        let mut bounds: Vec<Bounds> = self.vars.values().copied().collect();
+        let mut keep_row = vec![true; self.cons.len()];
+        for (i, (name, idx, row_type, rhs)) in self.cons.iter().enumerate() {
+            if *row_type == RowType::Unconstrained || counts[i] > 1 {
+                continue;
+            }
+            keep_row[i] = false;
+            let row = index_name(*name, idx);
+            let (lower, upper) = row_type.bounds(*rhs);
+            if counts[i] == 0 {
+                if !holds(0.0, *row_type, *rhs) {
+                    let relation = match row_type {
+                        RowType::Ranged(_) => format!("{lower} <= 0 <= {upper}"),
+                        _ => format!("0 {} {rhs}", row_type.symbol()),
+                    };
+                    reduction.diagnostics.push(Diagnostic::error(format!(
+                        "{row} is empty and can't hold: {relation}"
+                    )));
+                }
+                reduction.reduced.push(Reduced::EmptyRow { row });
+                continue;
+            }
+
+            let (j, coeff) = last[i];
+            let (lower, upper) = (lower / coeff, upper / coeff);
+            let row_bounds = if coeff > 0.0 {
+                Bounds { lower, upper }
+            } else {
+                Bounds {
+                    lower: upper,
+                    upper: lower,
+                }
+            };
+            bounds[j].lower = bounds[j].lower.max(row_bounds.lower);
+            bounds[j].upper = bounds[j].upper.min(row_bounds.upper);
+            let (var, var_idx) = self.vars.get_index(j).unwrap().0;
+            reduction.reduced.push(Reduced::BoundRow {
+                row,
+                col: index_name(*var, var_idx),
+                coeff,
+                bounds: row_bounds,
+            });
+        }
+
+        let mut keep_col = vec![true; self.vars.len()];
+        for (j, (var, var_idx)) in self.vars.keys().enumerate() {
+            let col_bounds = &mut bounds[j];
+            if col_bounds.lower > col_bounds.upper {
+                if col_bounds.lower > col_bounds.upper + TOL {
+                    reduction.diagnostics.push(Diagnostic::error(format!(
+                        "bounds on {} can't hold: {} > {}",
+                        index_name(*var, var_idx),
+                        col_bounds.lower,
+                        col_bounds.upper
+                    )));
+                    continue;
+                }
+                col_bounds.upper = col_bounds.lower;
+            }
+
+            let mut cost = 0.0;
+            let mut used = false;
+            for (row, val) in self.col(j) {
+                if Some(row) == objective {
+                    cost = val;
+                } else if keep_row[row] {
+                    used = true;
+                }
+            }
+            if used {
+                continue;
+            }
+            // The cost to minimise, whichever way the objective goes
+            let toward = match self.sense {
+                ObjSense::Minimize => cost,
+                ObjSense::Maximize => -cost,
+            };
+            let value = match toward {
+                0.0 => 0.0_f64.clamp(col_bounds.lower, col_bounds.upper),
+                toward if toward > 0.0 => col_bounds.lower,
+                _ => col_bounds.upper,
+            };
+            let col = index_name(*var, var_idx);
+            if value.is_infinite() {
+                reduction.diagnostics.push(Diagnostic::warning(format!(
+                    "the objective is unbounded in {col}, which is in no constraint"
+                )));
+                continue;
+            }
+            keep_col[j] = false;
+            reduction.objective_constant += cost * value;
+            reduction.reduced.push(Reduced::FixedCol { col, value });
+        }
+
+        self.retain(&keep_row, &keep_col, bounds);
+        reduction
+    }
+
+    /// Keep only the given rows and columns, setting the new column bounds
+    fn retain(&mut self, keep_row: &[bool], keep_col: &[bool], bounds: Vec<Bounds>) {
+        let mut row_ids = Vec::with_capacity(keep_row.len());
+        let mut next = 0;
+        for keep in keep_row {
+            row_ids.push(next);
+            next += *keep as u32;
+        }
+
+        let mut col_starts = vec![0];
+        let mut out = 0;
+        for (j, keep) in keep_col.iter().enumerate() {
+            if !keep {
+                continue;
+            }
+            for i in self.col_starts[j]..self.col_starts[j + 1] {
+                let row = self.row_ids[i] as usize;
+                if keep_row[row] {
+                    self.row_ids[out] = row_ids[row];
+                    self.vals[out] = self.vals[i];
+                    out += 1;
+                }
+            }
+            col_starts.push(out);
+        }
+        self.row_ids.truncate(out);
+        self.vals.truncate(out);
+        self.col_starts = col_starts;
+
+        let vars = std::mem::take(&mut self.vars);
+        self.vars = vars
+            .into_iter()
+            .zip(bounds)
+            .zip(keep_col)
+            .filter(|(_, keep)| **keep)
+            .map(|(((key, _), bounds), _)| (key, bounds))
+            .collect::<IndexMap<_, _>>();
+        let mut keep = keep_row.iter();
+        self.cons.retain(|_| *keep.next().unwrap());
+    }
+}
+
+fn holds(lhs: f64, row_type: RowType, rhs: f64) -> bool {
+    let (lower, upper) = row_type.bounds(rhs);
+    lhs >= lower - TOL && lhs <= upper + TOL
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{generate_matrix, merge_model, parse_model};
+
+    #[test]
+    fn test_reduce() {
+        let text = r#"
+            set I;
+            var x{i in I} >= 0;
+            var y{i in I} >= 0;
+            var z <= 4;
+            minimize cost: sum{i in I} (x[i] + y[i]) - z;
+            s.t. both{i in I}: x[i] + y[i] >= 1;
+            s.t. upper{i in I}: 2 * x[i] <= 3;
+            s.t. lower{i in I}: -x[i] <= -1;
+            s.t. cancel{i in I}: x[i] - x[i] <= 1;
+            s.t. band{i in I}: 1 <= -2 * y[i] + 4 <= 3;
+            data;
+            set I := a b;
+        "#;
+        let mut compiled = generate_matrix(merge_model(parse_model(text)));
+        let reduction = compiled.reduce();
+        assert!(reduction.diagnostics.is_empty());
+
+        let reduced: Vec<String> = reduction.reduced.iter().map(|r| r.to_string()).collect();
+        assert_eq!(
+            reduced,
+            vec![
+                "bound upper[a] x[a] 2 -inf 1.5",
+                "bound upper[b] x[b] 2 -inf 1.5",
+                "bound lower[a] x[a] -1 1 inf",
+                "bound lower[b] x[b] -1 1 inf",
+                "drop cancel[a]",
+                "drop cancel[b]",
+                "bound band[a] y[a] -2 0.5 1.5",
+                "bound band[b] y[b] -2 0.5 1.5",
+                "fix z 4",
+            ]
+        );
+        assert_eq!(reduction.objective_constant, -4.0);
+
+        let rows: Vec<String> = compiled
+            .cons
+            .iter()
+            .map(|(name, idx, _, _)| index_name(*name, idx))
+            .collect();
+        assert_eq!(rows, vec!["both[a]", "both[b]", "cost"]);
+        let cols: Vec<(String, Bounds)> = compiled
+            .vars
+            .iter()
+            .map(|((var, idx), bounds)| (index_name(*var, idx), *bounds))
+            .collect();
+        let x = Bounds {
+            lower: 1.0,
+            upper: 1.5,
+        };
+        let y = Bounds {
+            lower: 0.5,
+            upper: 1.5,
+        };
+        assert_eq!(
+            cols,
+            vec![
+                ("x[a]".into(), x),
+                ("y[a]".into(), y),
+                ("x[b]".into(), x),
+                ("y[b]".into(), y),
+            ]
+        );
+        // Each remaining var is in its `both` row and the objective
+        for j in 0..compiled.vars.len() {
+            let rows: Vec<usize> = compiled.col(j).map(|(row, _)| row).collect();
+            assert_eq!(rows, vec![j / 2, 2]);
+        }
+    }
+
+    #[test]
+    fn test_reduce_infeasible() {
+        let text = r#"
+            var x >= 0;
+            minimize cost: x;
+            s.t. empty: x - x >= 1;
+            s.t. above: x >= 3;
+            s.t. below: x <= 2;
+        "#;
+        let mut compiled = generate_matrix(merge_model(parse_model(text)));
+        let messages: Vec<String> = compiled
+            .reduce()
+            .diagnostics
+            .iter()
+            .map(|diag| diag.to_string())
+            .collect();
+        assert_eq!(
+            messages,
+            vec![
+                "error: empty is empty and can't hold: 0 >= 1",
+                "error: bounds on x can't hold: 3 > 2",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_reduce_maximize() {
+        let text = r#"
+            var x >= 0;
+            var z >= 0;
+            maximize profit: x + z;
+            s.t. c: x <= 10;
+            s.t. zc: z <= 5;
+            s.t. e: x + 0 * z >= 1;
+        "#;
+        let mut compiled = generate_matrix(merge_model(parse_model(text)));
+        let reduction = compiled.reduce();
+        assert!(reduction.diagnostics.is_empty());
+        let reduced: Vec<String> = reduction.reduced.iter().map(|r| r.to_string()).collect();
+        assert_eq!(
+            reduced,
+            vec![
+                "bound c x 1 -inf 10",
+                "bound zc z 1 -inf 5",
+                "bound e x 1 1 inf",
+                "fix x 10",
+                "fix z 5"
+            ]
+        );
+        assert_eq!(reduction.objective_constant, 15.0);
+    }
+}
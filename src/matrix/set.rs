@@ -1,4 +1,5 @@
 use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, OnceLock};
 
 use crate::{
     ir::model::SetWithData,
@@ -14,7 +15,12 @@ use crate::{
 
 pub struct SetCont {
     decl: ir::Set,
-    data: HashMap<Index, SetVals>,
+    data: HashMap<Index, Arc<[SetVal]>>,
+    /// The members of an unindexed set built from its expression or default,
+    /// filled on first use (or up front by `Lookups::prepare`)
+    computed: OnceLock<Arc<[SetVal]>>,
+    /// The only members to keep, if the set has been restricted
+    keep: Option<HashSet<SetVal>>,
 }
 
 impl From<SetWithData> for SetCont {
@@ -28,25 +34,77 @@ impl From<SetWithData> for SetCont {
                      name: _,
                      index,
                      values,
-                 }| (index, values),
+                 }| (index, values.0.into()),
             )
             .collect();
 
-        SetCont { decl, data }
+        SetCont {
+            decl,
+            data,
+            computed: OnceLock::new(),
+            keep: None,
+        }
     }
 }
 
 impl SetCont {
-    pub fn resolve(&self, index: &Index, lookups: &Lookups) -> SetVals {
+    /// Replace (or add) the data for one index of this set
+    pub fn insert(&mut self, data: SetData) {
+        let values = self.kept(data.values);
+        self.data.insert(data.index, values);
+    }
+
+    /// Drop every member not in `keep`, from the data and from whatever the set
+    /// is computed from
+    pub fn restrict(&mut self, keep: HashSet<SetVal>) {
+        for values in self.data.values_mut() {
+            *values = values
+                .iter()
+                .filter(|val| keep.contains(val))
+                .copied()
+                .collect();
+        }
+        self.keep = Some(keep);
+        self.reset();
+    }
+
+    fn kept(&self, values: SetVals) -> Arc<[SetVal]> {
+        match &self.keep {
+            Some(keep) => values
+                .0
+                .into_iter()
+                .filter(|val| keep.contains(val))
+                .collect(),
+            None => values.0.into(),
+        }
+    }
+
+    /// Forget the computed members, as the sets or params they came from may have changed
+    pub fn reset(&mut self) {
+        self.computed = OnceLock::new();
+    }
+
+    /// The members of this set at `index`. These are shared rather than copied,
+    /// so resolving a set in the middle of generation is only a lookup.
+    pub fn resolve(&self, index: &Index, lookups: &Lookups) -> Arc<[SetVal]> {
         // Data takes preference over expressions (probably)
         if let Some(set_data) = self.data.get(index) {
-            // Should also check that the within/cross conditions are met!
+            // within/cross conditions are checked up front by `analysis::validate`
             return set_data.clone();
         }
 
-        // I tried add a cache check here with a RwLock<HashMap<...>> but
-        // there wasn't any speed up. Possibly because of cloning and expensive hashkeys
+        // Indexed sets are rarely computed, and then from their index,
+        // so only the unindexed ones are kept
+        if index.is_empty() {
+            return self
+                .computed
+                .get_or_init(|| self.kept(self.compute(index, lookups)))
+                .clone();
+        }
+        self.kept(self.compute(index, lookups))
+    }
 
+    fn compute(&self, index: &Index, lookups: &Lookups) -> SetVals {
         let (dims, expr) = (&self.decl.dims, &self.decl.expr);
 
         // Try to resolve from expression
@@ -124,7 +182,7 @@ impl SetCont {
                             .get(&v.var)
                             .unwrap()
                             .resolve(&index_concrete, lookups)
-                            .0
+                            .to_vec()
                     })
                     .collect();
 
@@ -158,7 +216,7 @@ fn resolve_set_of(
 
     // Extract the integrand values for each domain element
     let mut result = Vec::new();
-    for idx in domain_indexes {
+    for idx in domain_indexes.iter() {
         // Build a map from domain vars to their values for this iteration
         let iter_map: IdxValMap = set_of
             .domain
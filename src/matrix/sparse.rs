@@ -0,0 +1,330 @@
+//! Domains with a condition like `OutputActivityRatio[r,t,f,m,y] <> 0` are
+//! expanded from the param's stored keys rather than the full product of their
+//! sets, as most combinations would be thrown away by the condition anyway.
+
+use std::collections::HashMap;
+
+use itertools::Itertools;
+use lasso::Spur;
+use smallvec::SmallVec;
+
+use crate::ir::interner::intern_resolve;
+use crate::ir::{BoolOp, Domain, DomainPartVar, Expr, Index, LogicExpr, RelOp, SetVal, Subscript};
+use crate::matrix::constraint::{IdxValMap, idx_get};
+use crate::matrix::lookup::Lookups;
+use crate::matrix::param::ParamVal;
+
+/// The keys of a param, grouped by the subscripts that don't come from the domain
+pub struct Sparse {
+    slots: Vec<Slot>,
+    /// The values of the parts covered by the param (in part order), sorted as
+    /// the sets are, keyed by the values of the fixed slots
+    groups: HashMap<Index, Vec<Index>>,
+    /// Whether each part is covered by the param
+    covered: Vec<bool>,
+    /// Each set member's position in the set, per part
+    ranks: Vec<HashMap<SetVal, usize>>,
+}
+
+/// Where a param subscript gets its value from
+enum Slot {
+    /// The domain part at this position
+    Part(usize),
+    /// An enclosing index, or the literal if there's none by that name
+    Fixed { name: Spur, literal: SetVal },
+    /// An index summed over in the condition
+    Any,
+}
+
+impl Sparse {
+    /// Index the param in the domain's condition, if the domain is over
+    /// unindexed sets and its condition requires a param with a default
+    /// of zero to be nonzero
+    pub fn new(domain: &Domain, lookups: &Lookups) -> Option<Self> {
+        if domain.parts.iter().any(|part| !part.subscript.is_empty()) {
+            return None;
+        }
+        let dummies: Vec<Spur> = domain
+            .parts
+            .iter()
+            .map(|part| match &part.var {
+                DomainPartVar::Single(id) => Some(*id),
+                DomainPartVar::Tuple(_) => None,
+            })
+            .collect::<Option<_>>()?;
+
+        let Nonzero {
+            name,
+            subscript,
+            wildcards,
+        } = nonzero_param(domain.condition.as_ref()?)?;
+        let param = lookups.par_map.get(&name)?;
+        let ParamVal::Arr(table) = &param.data else {
+            return None;
+        };
+        if !matches!(param.default, Some(Expr::Number(num)) if num == 0.0) {
+            return None;
+        }
+
+        let mut covered = vec![false; dummies.len()];
+        let mut slots = Vec::with_capacity(subscript.0.len());
+        for sub in subscript.iter() {
+            if sub.shift.is_some() {
+                return None;
+            }
+            if wildcards.contains(&sub.var) {
+                slots.push(Slot::Any);
+                continue;
+            }
+            match dummies.iter().position(|dummy| *dummy == sub.var) {
+                // A part that's used twice would need its values to agree
+                Some(part) if covered[part] => return None,
+                Some(part) => {
+                    covered[part] = true;
+                    slots.push(Slot::Part(part));
+                }
+                None => {
+                    let literal = match intern_resolve(sub.var).parse::<u32>() {
+                        Ok(num) => SetVal::Int(num),
+                        Err(_) => SetVal::Str(sub.var),
+                    };
+                    slots.push(Slot::Fixed {
+                        name: sub.var,
+                        literal,
+                    });
+                }
+            }
+        }
+
+        let ranks: Vec<HashMap<SetVal, usize>> = domain
+            .parts
+            .iter()
+            .map(|part| {
+                lookups.set_map[&part.set]
+                    .resolve(&Index::new(), lookups)
+                    .iter()
+                    .enumerate()
+                    .map(|(rank, val)| (*val, rank))
+                    .collect()
+            })
+            .collect();
+
+        let mut groups: HashMap<Index, Vec<Index>> = HashMap::new();
+        'keys: for key in table.keys() {
+            let mut fixed = Index::new();
+            let mut vals: Vec<Option<SetVal>> = vec![None; dummies.len()];
+            for (slot, val) in slots.iter().zip(key) {
+                match slot {
+                    Slot::Part(part) if ranks[*part].contains_key(val) => vals[*part] = Some(*val),
+                    // Not a member of the set, so outside the domain
+                    Slot::Part(_) => continue 'keys,
+                    Slot::Fixed { .. } => fixed.push(*val),
+                    Slot::Any => {}
+                }
+            }
+            groups
+                .entry(fixed)
+                .or_default()
+                .push(vals.into_iter().flatten().collect());
+        }
+        for group in groups.values_mut() {
+            group.sort_by_cached_key(|vals| rank(&ranks, &covered, vals, true));
+            // Keys that only differ in a wildcard give the same values
+            group.dedup();
+        }
+        Some(Sparse {
+            slots,
+            groups,
+            covered,
+            ranks,
+        })
+    }
+
+    /// The domain's indexes that could satisfy its condition, in the order the
+    /// full product would give them. The condition itself still has to be checked.
+    pub fn indexes(
+        &self,
+        domain: &Domain,
+        lookups: &Lookups,
+        idx_val_map: &IdxValMap,
+    ) -> Vec<Index> {
+        let fixed: Index = self
+            .slots
+            .iter()
+            .filter_map(|slot| match slot {
+                Slot::Part(_) | Slot::Any => None,
+                Slot::Fixed { name, literal } => {
+                    Some(idx_get(idx_val_map, *name).copied().unwrap_or(*literal))
+                }
+            })
+            .collect();
+        let Some(group) = self.groups.get(&fixed) else {
+            return vec![];
+        };
+        if self.covered.iter().all(|covered| *covered) {
+            return group.clone();
+        }
+
+        // The parts the param doesn't cover are iterated in full
+        let free = domain
+            .parts
+            .iter()
+            .zip(&self.covered)
+            .filter(|(_, covered)| !**covered)
+            .map(|(part, _)| {
+                lookups.set_map[&part.set]
+                    .resolve(&Index::new(), lookups)
+                    .to_vec()
+            })
+            .multi_cartesian_product()
+            .collect_vec();
+        let mut indexes = Vec::with_capacity(group.len() * free.len());
+        for vals in group {
+            for free_vals in &free {
+                let (mut vals, mut free_vals) = (vals.iter(), free_vals.iter());
+                indexes.push(
+                    self.covered
+                        .iter()
+                        .map(|covered| match covered {
+                            true => *vals.next().unwrap(),
+                            false => *free_vals.next().unwrap(),
+                        })
+                        .collect(),
+                );
+            }
+        }
+        indexes.sort_by_cached_key(|idx| rank(&self.ranks, &self.covered, idx, false));
+        indexes
+    }
+}
+
+/// The position of each value in its set, for either the covered parts or all of them
+fn rank(
+    ranks: &[HashMap<SetVal, usize>],
+    covered: &[bool],
+    vals: &Index,
+    covered_only: bool,
+) -> Vec<usize> {
+    ranks
+        .iter()
+        .zip(covered)
+        .filter(|(_, covered)| !covered_only || **covered)
+        .zip(vals)
+        .map(|((ranks, _), val)| ranks[val])
+        .collect()
+}
+
+/// A param that has to be nonzero somewhere for the condition to hold
+struct Nonzero<'a> {
+    name: Spur,
+    subscript: &'a Subscript,
+    /// Indices bound by a `sum` around the param, which can take any value
+    wildcards: SmallVec<[Spur; 4]>,
+}
+
+/// Find `Param[...] <> 0` (or `sum{...} Param[...] <> 0`, which can only hold if
+/// one of the summed values is nonzero) in a condition, alone or in an `and`
+fn nonzero_param(logic: &LogicExpr) -> Option<Nonzero<'_>> {
+    match logic {
+        LogicExpr::Comparison {
+            lhs,
+            op: RelOp::Ne | RelOp::Ne2,
+            rhs,
+        } => match (lhs, rhs) {
+            (expr, Expr::Number(num)) | (Expr::Number(num), expr) if *num == 0.0 => {
+                nonzero_expr(expr)
+            }
+            _ => None,
+        },
+        LogicExpr::Comparison { .. } => None,
+        LogicExpr::BoolOp {
+            lhs,
+            op: BoolOp::And,
+            rhs,
+        } => nonzero_param(lhs).or_else(|| nonzero_param(rhs)),
+        LogicExpr::BoolOp { .. } => None,
+    }
+}
+
+fn nonzero_expr(expr: &Expr) -> Option<Nonzero<'_>> {
+    match expr {
+        Expr::VarSubscripted(v) => Some(Nonzero {
+            name: v.var,
+            subscript: &v.subscript,
+            wildcards: SmallVec::new(),
+        }),
+        Expr::FuncSum(func) => {
+            let mut nonzero = nonzero_expr(&func.operand)?;
+            for part in &func.domain.parts {
+                match &part.var {
+                    DomainPartVar::Single(id) => nonzero.wildcards.push(*id),
+                    DomainPartVar::Tuple(ids) => nonzero.wildcards.extend(ids.iter().copied()),
+                }
+            }
+            Some(nonzero)
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ir::index_name;
+    use crate::{generate_matrix, merge_model, parse_model};
+
+    #[test]
+    fn test_sparse_domains() {
+        let text = r#"
+            set I;
+            set J;
+            set K;
+            param p{i in I, j in J, k in K} default 0;
+            var x{j in J, k in K} >= 0;
+            minimize cost: sum{j in J, k in K} x[j, k];
+            s.t. own{k in K, i in I, j in J: p[i, j, k] <> 0}: x[j, k] >= 1;
+            s.t. summed{i in I, k in K: (sum{j in J} p[i, j, k]) <> 0}:
+                sum{j in J: p[i, j, k] <> 0 && p[i, j, k] < 3} x[j, k] >= 1;
+            data;
+            set I := u v;
+            set J := a b c;
+            set K := k1 k2;
+            param p := u c k1 1  u a k1 2  u b k2 3  v a k2 0;
+        "#;
+        let compiled = generate_matrix(merge_model(parse_model(text)));
+        let rows: Vec<String> = compiled
+            .cons
+            .iter()
+            .map(|(name, idx, _, _)| index_name(*name, idx))
+            .collect();
+        // In the order of the sets, not of the data, and without the zero
+        assert_eq!(
+            rows,
+            vec![
+                "own[k1,u,a]",
+                "own[k1,u,c]",
+                "own[k2,u,b]",
+                "summed[u,k1]",
+                "summed[u,k2]",
+                "cost",
+            ]
+        );
+
+        let mut cols: Vec<(String, String)> = vec![];
+        for (j, (var, idx)) in compiled.vars.keys().enumerate() {
+            for (row, _) in compiled.col(j) {
+                let (name, con_idx, _, _) = &compiled.cons[row];
+                if index_name(*name, con_idx).starts_with("summed") {
+                    cols.push((index_name(*name, con_idx), index_name(*var, idx)));
+                }
+            }
+        }
+        cols.sort();
+        assert_eq!(
+            cols,
+            vec![
+                ("summed[u,k1]".into(), "x[a,k1]".into()),
+                ("summed[u,k1]".into(), "x[c,k1]".into()),
+            ]
+        );
+    }
+}
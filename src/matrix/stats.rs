@@ -0,0 +1,296 @@
+//! Sizes and numeric ranges of the generated matrix, per constraint and var, to
+//! check a model over before handing it to a solver.
+
+use std::fmt;
+
+use indexmap::IndexMap;
+use lasso::Spur;
+
+use crate::analysis::Diagnostic;
+use crate::ir::interner::intern_resolve;
+use crate::ir::op::RowType;
+use crate::matrix::Compiled;
+
+/// Ranges wider than this (max / min) tend to give solvers numerical trouble
+const WIDE: f64 = 1e9;
+
+pub struct Stats {
+    /// Per constraint (and objective), in model order
+    pub rows: Vec<Family>,
+    /// Per var, in the order they're first used
+    pub cols: Vec<Family>,
+    pub nonzeros: usize,
+    pub coeffs: Range,
+    pub rhs: Range,
+    pub bounds: Range,
+}
+
+/// The rows of one constraint or the columns of one var
+pub struct Family {
+    pub name: Spur,
+    pub count: usize,
+    pub nonzeros: usize,
+    pub coeffs: Range,
+}
+
+/// The smallest and largest absolute value, ignoring zeros and infinities
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Range {
+    pub min: f64,
+    pub max: f64,
+}
+
+impl Default for Range {
+    fn default() -> Self {
+        Range {
+            min: f64::INFINITY,
+            max: 0.0,
+        }
+    }
+}
+
+impl Range {
+    fn add(&mut self, val: f64) {
+        let val = val.abs();
+        if val != 0.0 && val.is_finite() {
+            self.min = self.min.min(val);
+            self.max = self.max.max(val);
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.max == 0.0
+    }
+
+    /// How many times bigger the largest value is than the smallest
+    pub fn ratio(&self) -> f64 {
+        if self.is_empty() {
+            1.0
+        } else {
+            self.max / self.min
+        }
+    }
+
+    pub fn is_wide(&self) -> bool {
+        self.ratio() > WIDE
+    }
+}
+
+impl fmt::Display for Range {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_empty() {
+            write!(f, "{:>10} {:>10} {:>10}", "-", "-", "-")
+        } else {
+            write!(
+                f,
+                "{:>10.3e} {:>10.3e} {:>10.3e}",
+                self.min,
+                self.max,
+                self.ratio()
+            )
+        }
+    }
+}
+
+impl Compiled {
+    pub fn stats(&self) -> Stats {
+        let mut rows: IndexMap<Spur, Family> = IndexMap::new();
+        let mut row_family = Vec::with_capacity(self.cons.len());
+        let mut rhs = Range::default();
+        for (name, _, row_type, val) in &self.cons {
+            let entry = rows.entry(*name);
+            row_family.push(entry.index());
+            entry.or_insert_with(|| Family::new(*name)).count += 1;
+            match row_type {
+                RowType::Unconstrained => {}
+                RowType::Ranged(width) => {
+                    rhs.add(*val);
+                    rhs.add(val + width);
+                }
+                _ => rhs.add(*val),
+            }
+        }
+
+        let mut cols: IndexMap<Spur, Family> = IndexMap::new();
+        let mut coeffs = Range::default();
+        let mut bounds = Range::default();
+        for (j, ((var, _), var_bounds)) in self.vars.iter().enumerate() {
+            let col = cols.entry(*var).or_insert_with(|| Family::new(*var));
+            col.count += 1;
+            bounds.add(var_bounds.lower);
+            bounds.add(var_bounds.upper);
+            for (row, val) in self.col(j) {
+                col.nonzeros += 1;
+                col.coeffs.add(val);
+                let row = &mut rows[row_family[row]];
+                row.nonzeros += 1;
+                row.coeffs.add(val);
+                coeffs.add(val);
+            }
+        }
+
+        Stats {
+            rows: rows.into_values().collect(),
+            cols: cols.into_values().collect(),
+            nonzeros: self.vals.len(),
+            coeffs,
+            rhs,
+            bounds,
+        }
+    }
+}
+
+impl Family {
+    fn new(name: Spur) -> Self {
+        Family {
+            name,
+            count: 0,
+            nonzeros: 0,
+            coeffs: Range::default(),
+        }
+    }
+}
+
+impl Stats {
+    /// A warning for each range that's wide enough to cause numerical trouble
+    pub fn warnings(&self) -> Vec<Diagnostic> {
+        let mut warnings = vec![];
+        let mut check = |what: String, range: &Range| {
+            if range.is_wide() {
+                warnings.push(Diagnostic::warning(format!(
+                    "{what} range from {:e} to {:e} (ratio {:.1e})",
+                    range.min,
+                    range.max,
+                    range.ratio()
+                )));
+            }
+        };
+        check("coefficients".to_string(), &self.coeffs);
+        check("RHS values".to_string(), &self.rhs);
+        check("bounds".to_string(), &self.bounds);
+        for row in &self.rows {
+            check(
+                format!("coefficients of {}", intern_resolve(row.name)),
+                &row.coeffs,
+            );
+        }
+        for col in &self.cols {
+            check(
+                format!("coefficients of {}", intern_resolve(col.name)),
+                &col.coeffs,
+            );
+        }
+        warnings
+    }
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let total = |families: &[Family]| families.iter().map(|fam| fam.count).sum::<usize>();
+        writeln!(
+            f,
+            "rows: {}, columns: {}, nonzeros: {}",
+            total(&self.rows),
+            total(&self.cols),
+            self.nonzeros
+        )?;
+
+        let width = self
+            .rows
+            .iter()
+            .chain(&self.cols)
+            .map(|fam| intern_resolve(fam.name).len())
+            .max()
+            .unwrap_or(0)
+            .max(12);
+        for (title, families) in [("constraint", &self.rows), ("var", &self.cols)] {
+            writeln!(f)?;
+            writeln!(
+                f,
+                "{title:<width$} {:>9} {:>9} {:>10} {:>10} {:>10}",
+                "count", "nonzeros", "min |a|", "max |a|", "ratio"
+            )?;
+            for fam in families.iter() {
+                writeln!(
+                    f,
+                    "{:<width$} {:>9} {:>9} {}",
+                    intern_resolve(fam.name),
+                    fam.count,
+                    fam.nonzeros,
+                    fam.coeffs
+                )?;
+            }
+        }
+
+        writeln!(f)?;
+        writeln!(
+            f,
+            "{:<width$} {:>10} {:>10} {:>10}",
+            "range", "min", "max", "ratio"
+        )?;
+        for (name, range) in [
+            ("coefficients", &self.coeffs),
+            ("rhs", &self.rhs),
+            ("bounds", &self.bounds),
+        ] {
+            writeln!(f, "{name:<width$} {range}")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{generate_matrix, merge_model, parse_model};
+
+    #[test]
+    fn test_stats() {
+        let text = r#"
+            set I;
+            var x{i in I} <= 1e6;
+            var y >= 2;
+            minimize cost: sum{i in I} x[i] + y;
+            s.t. tiny{i in I}: 1e-6 * x[i] + 1e6 * y >= 5;
+            s.t. plain: sum{i in I} x[i] <= 0;
+            data;
+            set I := a b c;
+        "#;
+        let stats = generate_matrix(merge_model(parse_model(text))).stats();
+        let counts: Vec<(&str, usize, usize)> = stats
+            .rows
+            .iter()
+            .chain(&stats.cols)
+            .map(|fam| (intern_resolve(fam.name), fam.count, fam.nonzeros))
+            .collect();
+        assert_eq!(
+            counts,
+            vec![
+                ("tiny", 3, 6),
+                ("plain", 1, 3),
+                ("cost", 1, 4),
+                ("x", 3, 9),
+                ("y", 1, 4),
+            ]
+        );
+        assert_eq!(stats.nonzeros, 13);
+        assert_eq!(
+            stats.coeffs,
+            Range {
+                min: 1e-6,
+                max: 1e6
+            }
+        );
+        assert_eq!(stats.rhs, Range { min: 5.0, max: 5.0 });
+        assert_eq!(stats.bounds, Range { min: 2.0, max: 1e6 });
+
+        let warnings: Vec<String> = stats.warnings().iter().map(|w| w.to_string()).collect();
+        assert_eq!(
+            warnings,
+            vec![
+                "warning: coefficients range from 1e-6 to 1e6 (ratio 1.0e12)",
+                "warning: coefficients of tiny range from 1e-6 to 1e6 (ratio 1.0e12)",
+            ]
+        );
+    }
+}
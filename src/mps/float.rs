@@ -0,0 +1,123 @@
+//! Fast float formatting for MPS output.
+//!
+//! The output has always used `Display` for `f64`, which never uses an exponent
+//! (`1e-7` is written `0.0000001`). `ryu` finds the same shortest digits much
+//! faster, but writes them differently, so its output is rearranged to match.
+//! The two can disagree on ties between two equally short candidates, which
+//! need at least 16 significant digits, so those values still go via `Display`.
+
+use std::io::Write;
+
+/// Append `val` to `buf`, byte for byte as `write!(buf, "{val}")` would
+pub fn write_f64(buf: &mut Vec<u8>, val: f64) {
+    // Most coefficients are small integers, which don't need the float machinery
+    // (-0.0 does, as it's written with its sign)
+    if val.fract() == 0.0 && val.abs() < 1e15 && !(val == 0.0 && val.is_sign_negative()) {
+        buf.extend_from_slice(itoa::Buffer::new().format(val as i64).as_bytes());
+        return;
+    }
+    if !val.is_finite() {
+        write!(buf, "{val}").unwrap();
+        return;
+    }
+
+    let mut ryu = ryu::Buffer::new();
+    let text = ryu.format_finite(val).as_bytes();
+    let (neg, text) = match text.split_first() {
+        Some((b'-', rest)) => (true, rest),
+        _ => (false, text),
+    };
+    let (mantissa, exp) = match text.iter().position(|c| *c == b'e') {
+        Some(e) => {
+            // ryu only writes ASCII digits and a sign here
+            let exp = std::str::from_utf8(&text[e + 1..]).unwrap();
+            (&text[..e], exp.parse::<i32>().unwrap())
+        }
+        None => (text, 0),
+    };
+    let (int, frac) = match mantissa.iter().position(|c| *c == b'.') {
+        Some(dot) => (&mantissa[..dot], &mantissa[dot + 1..]),
+        None => (mantissa, &[][..]),
+    };
+
+    // All the significant digits, and where the decimal point goes among them
+    // (ryu writes at most 17 digits)
+    let mut store = [0u8; 24];
+    let len = int.len() + frac.len();
+    store[..int.len()].copy_from_slice(int);
+    store[int.len()..len].copy_from_slice(frac);
+    let mut digits = &store[..len];
+    let leading = digits.iter().take_while(|c| **c == b'0').count();
+    digits = &digits[leading..];
+    let point = int.len() as i32 + exp - leading as i32;
+    let trailing = digits.iter().rev().take_while(|c| **c == b'0').count();
+    digits = &digits[..digits.len() - trailing];
+    if digits.len() >= 16 {
+        write!(buf, "{val}").unwrap();
+        return;
+    }
+
+    if neg {
+        buf.push(b'-');
+    }
+    if digits.is_empty() {
+        buf.push(b'0');
+    } else if point <= 0 {
+        buf.extend_from_slice(b"0.");
+        buf.extend(std::iter::repeat_n(b'0', -point as usize));
+        buf.extend_from_slice(digits);
+    } else if point as usize >= digits.len() {
+        buf.extend_from_slice(digits);
+        buf.extend(std::iter::repeat_n(b'0', point as usize - digits.len()));
+    } else {
+        let (whole, fraction) = digits.split_at(point as usize);
+        buf.extend_from_slice(whole);
+        buf.push(b'.');
+        buf.extend_from_slice(fraction);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_matches_display() {
+        let mut vals = vec![
+            0.0,
+            -0.0,
+            1.0,
+            -1.0,
+            0.5,
+            0.1,
+            1e-7,
+            1.5e-7,
+            123.456,
+            1e15,
+            1e16,
+            1.5e16,
+            1e20,
+            -2.5e22,
+            1e300,
+            5e-324,
+            f64::MAX,
+            f64::MIN_POSITIVE,
+            f64::INFINITY,
+            f64::NAN,
+        ];
+        // Random bit patterns cover every exponent, random small ratios cover typical data
+        let mut state: u64 = 0x2545_f491_4f6c_dd1d;
+        for _ in 0..200_000 {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            vals.push(f64::from_bits(state));
+            vals.push((state % 100_000) as f64 / ((state >> 40) % 1000 + 1) as f64);
+        }
+        for val in vals {
+            let mut buf = Vec::new();
+            write_f64(&mut buf, val);
+            assert_eq!(String::from_utf8(buf).unwrap(), val.to_string());
+        }
+    }
+}
@@ -1 +1,2 @@
+mod float;
 pub mod output;
@@ -1,57 +1,156 @@
 use std::io::{BufWriter, Write};
 
+use lasso::{Key, Spur};
+use rayon::prelude::*;
+
 use crate::{
     ir::{
-        Index,
-        interner::intern_resolve,
-        op::{BoundsOp, RowType},
+        Index, SetVal, SetValTerminal,
+        interner::{intern_resolve, intern_snapshot},
+        op::RowType,
     },
     matrix::{Compiled, ConsMap, VarsMap},
+    mps::float::write_f64,
 };
 
+/// Vars per chunk of COLUMNS formatted on one thread
+const CHUNK_VARS: usize = 1024;
+/// Chunks formatted before they're written out, to bound memory
+const BATCH_CHUNKS: usize = 256;
+
 pub fn print_mps(compiled: Compiled, model_name: &str) {
     let stdout = std::io::stdout();
     let mut w = BufWriter::with_capacity(256 * 1024, stdout.lock());
+    write_mps(&mut w, &compiled, model_name);
+    // BufWriter flushes on drop
+}
+
+/// Write the matrix in MPS format.
+/// COLUMNS (by far the biggest section) is formatted in parallel chunks, which
+/// are then written in order, so the output is the same as a sequential writer.
+pub fn write_mps(w: &mut impl Write, compiled: &Compiled, model_name: &str) {
+    let namer = Namer::new();
+    // Each row name is used once in ROWS and once per coefficient, so format them up front
+    let row_names: Vec<String> = compiled
+        .cons
+        .par_iter()
+        .map(|(name, idx, _, _)| namer.name(*name, idx))
+        .collect();
 
     writeln!(w, "NAME {model_name}").unwrap();
-    write_con_rows(&mut w, &compiled.cons);
-    write_var_cols(&mut w, &compiled.vars);
-    write_con_rhs(&mut w, &compiled.cons);
-    write_var_bounds(&mut w, &compiled.vars);
+    write_con_rows(w, &compiled.cons, &row_names);
+    write_var_cols(w, compiled, &row_names, &namer);
+    write_con_rhs(w, &compiled.cons, &row_names);
+    write_con_ranges(w, &compiled.cons, &row_names);
+    write_var_bounds(w, &compiled.vars, &namer);
     writeln!(w, "ENDATA").unwrap();
-    // BufWriter flushes on drop
 }
 
-fn write_con_rows(w: &mut impl Write, rows: &ConsMap) {
+/// Formats symbols with their index, eg `Cost[RE1,2030]`, the same as `ir::index_name`
+/// but resolving strings from a snapshot of the interner rather than the interner itself
+struct Namer {
+    strings: Vec<&'static str>,
+}
+
+impl Namer {
+    fn new() -> Self {
+        Namer {
+            strings: intern_snapshot(),
+        }
+    }
+
+    fn resolve(&self, spur: Spur) -> &str {
+        match self.strings.get(spur.into_usize()) {
+            Some(s) => s,
+            // Interned since the snapshot
+            None => intern_resolve(spur),
+        }
+    }
+
+    fn write_terminal(&self, buf: &mut Vec<u8>, val: &SetValTerminal) {
+        match val {
+            SetValTerminal::Str(s) => buf.extend_from_slice(self.resolve(*s).as_bytes()),
+            SetValTerminal::Int(n) => {
+                buf.extend_from_slice(itoa::Buffer::new().format(*n).as_bytes())
+            }
+        }
+    }
+
+    fn write(&self, buf: &mut Vec<u8>, name: Spur, idx: &Index) {
+        buf.extend_from_slice(self.resolve(name).as_bytes());
+        if idx.is_empty() {
+            return;
+        }
+        buf.push(b'[');
+        for (i, val) in idx.iter().enumerate() {
+            if i > 0 {
+                buf.push(b',');
+            }
+            match val {
+                SetVal::Str(s) => self.write_terminal(buf, &SetValTerminal::Str(*s)),
+                SetVal::Int(n) => self.write_terminal(buf, &SetValTerminal::Int(*n)),
+                SetVal::Tuple([a, b]) => {
+                    self.write_terminal(buf, a);
+                    buf.push(b',');
+                    self.write_terminal(buf, b);
+                }
+            }
+        }
+        buf.push(b']');
+    }
+
+    fn name(&self, name: Spur, idx: &Index) -> String {
+        let mut buf = Vec::new();
+        self.write(&mut buf, name, idx);
+        // Only ever made of whole interned strings, digits and ASCII punctuation
+        String::from_utf8(buf).unwrap()
+    }
+}
+
+fn write_con_rows(w: &mut impl Write, rows: &ConsMap, row_names: &[String]) {
     writeln!(w, "ROWS").unwrap();
-    for (name, idx, dir, _) in rows {
-        let name = intern_resolve(*name);
-        write!(w, " {dir}  {name}").unwrap();
-        write_index_vals(w, idx);
-        writeln!(w).unwrap();
+    for ((_, _, dir, _), name) in rows.iter().zip(row_names) {
+        writeln!(w, " {dir}  {name}").unwrap();
     }
 }
 
-fn write_var_cols(w: &mut impl Write, cols: &VarsMap) {
+fn write_var_cols(w: &mut impl Write, compiled: &Compiled, row_names: &[String], namer: &Namer) {
     writeln!(w, "COLUMNS").unwrap();
-    for ((var_name, var_index), con_map) in cols {
-        let var_name = intern_resolve(*var_name);
-        for ((con_name, con_index), val) in &con_map.coeffs {
-            if *val != 0.0 {
-                let con_name = intern_resolve(*con_name);
-                write!(w, " {var_name}").unwrap();
-                write_index_vals(w, var_index);
-                write!(w, " {con_name}").unwrap();
-                write_index_vals(w, con_index);
-                writeln!(w, " {val}").unwrap();
-            }
+
+    let cols: Vec<_> = compiled.vars.keys().enumerate().collect();
+    // Reused for each batch, so big models don't keep allocating fresh buffers
+    let mut bufs: Vec<Vec<u8>> = vec![Vec::new(); BATCH_CHUNKS];
+    for batch in cols.chunks(CHUNK_VARS * BATCH_CHUNKS) {
+        batch
+            .par_chunks(CHUNK_VARS)
+            .zip(bufs.par_iter_mut())
+            .for_each(|(chunk, buf)| {
+                buf.clear();
+                let mut col = Vec::new();
+                for (j, (var_name, var_index)) in chunk {
+                    col.clear();
+                    namer.write(&mut col, *var_name, var_index);
+                    for (row, val) in compiled.col(*j) {
+                        buf.push(b' ');
+                        buf.extend_from_slice(&col);
+                        buf.push(b' ');
+                        buf.extend_from_slice(row_names[row].as_bytes());
+                        buf.push(b' ');
+                        write_f64(buf, val);
+                        buf.push(b'\n');
+                    }
+                }
+            });
+        for buf in &bufs[..batch.len().div_ceil(CHUNK_VARS)] {
+            w.write_all(buf).unwrap();
         }
     }
 }
 
-fn write_con_rhs(w: &mut impl Write, rows: &ConsMap) {
+fn write_con_rhs(w: &mut impl Write, rows: &ConsMap, row_names: &[String]) {
     writeln!(w, "RHS").unwrap();
-    for (name, idx, row_type, val) in rows {
+    let mut buf = Vec::new();
+    for ((_, _, row_type, val), name) in rows.iter().zip(row_names) {
         // Skip N-type rows (objective function) - they should never have RHS
         if *row_type == RowType::Unconstrained {
             continue;
@@ -59,47 +158,240 @@ fn write_con_rhs(w: &mut impl Write, rows: &ConsMap) {
         // MPS format assumes RHS is 0 if not provided
         // NB: -0 and +0 are different values
         if *val != 0.0 {
-            let name = intern_resolve(*name);
-            write!(w, " RHS1 {name}").unwrap();
-            write_index_vals(w, idx);
-            writeln!(w, " {val}").unwrap();
+            buf.clear();
+            write!(buf, " RHS1 {name} ").unwrap();
+            write_f64(&mut buf, *val);
+            buf.push(b'\n');
+            w.write_all(&buf).unwrap();
         }
     }
 }
 
-fn write_var_bounds(w: &mut impl Write, vars: &VarsMap) {
+/// Only written if there are ranged rows, as most models have none
+fn write_con_ranges(w: &mut impl Write, rows: &ConsMap, row_names: &[String]) {
+    let mut ranges = rows
+        .iter()
+        .zip(row_names)
+        .filter_map(|((_, _, row_type, _), name)| match row_type {
+            RowType::Ranged(width) => Some((name, *width)),
+            _ => None,
+        })
+        .peekable();
+    if ranges.peek().is_none() {
+        return;
+    }
+    writeln!(w, "RANGES").unwrap();
+    let mut buf = Vec::new();
+    for (name, width) in ranges {
+        buf.clear();
+        write!(buf, " RNG1 {name} ").unwrap();
+        write_f64(&mut buf, width);
+        buf.push(b'\n');
+        w.write_all(&buf).unwrap();
+    }
+}
+
+fn write_var_bounds(w: &mut impl Write, vars: &VarsMap, namer: &Namer) {
     writeln!(w, "BOUNDS").unwrap();
 
-    for ((var_name, var_idx), var) in vars {
-        if var.bounds.op == BoundsOp::Lower && var.bounds.val == Some(0.0) {
-            // exclude vars with >= 0, as that is default in MPS
-            continue;
+    let mut buf = Vec::new();
+    for ((var_name, var_idx), bounds) in vars {
+        for (op, val) in bounds.mps_entries() {
+            buf.clear();
+            write!(buf, " {op} BND1 ").unwrap();
+            namer.write(&mut buf, *var_name, var_idx);
+            if let Some(val) = val {
+                buf.push(b' ');
+                write_f64(&mut buf, val);
+            }
+            buf.push(b'\n');
+            w.write_all(&buf).unwrap();
         }
+    }
+}
 
-        let var_name = intern_resolve(*var_name);
-        write!(w, " {} BND1 {var_name}", var.bounds.op).unwrap();
-        write_index_vals(w, var_idx);
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
 
-        match var.bounds.val {
-            Some(val) => writeln!(w, " {val}").unwrap(),
-            None => writeln!(w).unwrap(),
-        };
+    use super::*;
+    use crate::{generate_matrix, load_model_and_data, merge_model, parse_model};
+
+    /// The original single-threaded writer, ported to the current `Compiled`
+    /// but otherwise as it was: `Display` for floats and indices, and one bound
+    /// entry per var. It shares no formatting code with `write_mps`.
+    fn write_mps_sequential(w: &mut impl Write, compiled: &Compiled, model_name: &str) {
+        writeln!(w, "NAME {model_name}").unwrap();
+        seq_con_rows(w, &compiled.cons);
+        seq_var_cols(w, compiled);
+        seq_con_rhs(w, &compiled.cons);
+        seq_var_bounds(w, &compiled.vars);
+        writeln!(w, "ENDATA").unwrap();
     }
-}
 
-/// Write index values directly to the buffer, avoiding String allocation
-#[inline]
-fn write_index_vals(w: &mut impl Write, v: &Index) {
-    if !v.is_empty() {
-        write!(w, "[").unwrap();
-        let mut first = true;
-        for item in v.iter() {
-            if !first {
-                write!(w, ",").unwrap();
+    fn seq_con_rows(w: &mut impl Write, rows: &ConsMap) {
+        writeln!(w, "ROWS").unwrap();
+        for (name, idx, dir, _) in rows {
+            let name = intern_resolve(*name);
+            write!(w, " {dir}  {name}").unwrap();
+            seq_index_vals(w, idx);
+            writeln!(w).unwrap();
+        }
+    }
+
+    fn seq_var_cols(w: &mut impl Write, compiled: &Compiled) {
+        writeln!(w, "COLUMNS").unwrap();
+        for (j, (var_name, var_index)) in compiled.vars.keys().enumerate() {
+            let var_name = intern_resolve(*var_name);
+            for (row, val) in compiled.col(j) {
+                let (con_name, con_index, _, _) = &compiled.cons[row];
+                let con_name = intern_resolve(*con_name);
+                write!(w, " {var_name}").unwrap();
+                seq_index_vals(w, var_index);
+                write!(w, " {con_name}").unwrap();
+                seq_index_vals(w, con_index);
+                writeln!(w, " {val}").unwrap();
+            }
+        }
+    }
+
+    fn seq_con_rhs(w: &mut impl Write, rows: &ConsMap) {
+        writeln!(w, "RHS").unwrap();
+        for (name, idx, row_type, val) in rows {
+            if *row_type == RowType::Unconstrained {
+                continue;
+            }
+            if *val != 0.0 {
+                let name = intern_resolve(*name);
+                write!(w, " RHS1 {name}").unwrap();
+                seq_index_vals(w, idx);
+                writeln!(w, " {val}").unwrap();
             }
-            first = false;
-            write!(w, "{item}").unwrap();
         }
-        write!(w, "]").unwrap();
+    }
+
+    fn seq_var_bounds(w: &mut impl Write, vars: &VarsMap) {
+        writeln!(w, "BOUNDS").unwrap();
+        for ((var_name, var_idx), bounds) in vars {
+            // Vars had a single bound, from their declaration
+            let (op, val) = match (bounds.lower, bounds.upper) {
+                (lower, upper) if lower == upper => ("FX", Some(lower)),
+                (f64::NEG_INFINITY, f64::INFINITY) => ("FR", None),
+                (_, upper) if upper < f64::INFINITY => ("UP", Some(upper)),
+                // exclude vars with >= 0, as that is default in MPS
+                (0.0, _) => continue,
+                (lower, _) => ("LO", Some(lower)),
+            };
+            let var_name = intern_resolve(*var_name);
+            write!(w, " {op} BND1 {var_name}").unwrap();
+            seq_index_vals(w, var_idx);
+            match val {
+                Some(val) => writeln!(w, " {val}").unwrap(),
+                None => writeln!(w).unwrap(),
+            };
+        }
+    }
+
+    fn seq_index_vals(w: &mut impl Write, v: &Index) {
+        if !v.is_empty() {
+            write!(w, "[").unwrap();
+            let mut first = true;
+            for item in v.iter() {
+                if !first {
+                    write!(w, ",").unwrap();
+                }
+                first = false;
+                write!(w, "{item}").unwrap();
+            }
+            write!(w, "]").unwrap();
+        }
+    }
+
+    fn osemosys(data: &str) -> Compiled {
+        let entries = load_model_and_data("examples/osemosys.mod", &[data]);
+        generate_matrix(merge_model(entries))
+    }
+
+    #[test]
+    fn test_same_as_sequential() {
+        let compiled = osemosys("examples/ose_small.dat");
+        let mut parallel = Vec::new();
+        write_mps(&mut parallel, &compiled, "osemosys");
+        let mut sequential = Vec::new();
+        write_mps_sequential(&mut sequential, &compiled, "osemosys");
+        assert!(parallel == sequential);
+    }
+
+    #[test]
+    fn test_same_as_golden() {
+        // Written by the original writer, so it also catches changes made to
+        // both writers at once
+        let entries = load_model_and_data("tests/fixtures/golden.mod", &[]);
+        let compiled = generate_matrix(merge_model(entries));
+        let mut out = Vec::new();
+        write_mps(&mut out, &compiled, "golden");
+        let golden = std::fs::read_to_string("tests/fixtures/golden.mps").unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), golden);
+    }
+
+    #[test]
+    fn test_ranges() {
+        let text = r#"
+            var x >= 0;
+            var y >= 0;
+            minimize cost: x + y;
+            s.t. plain: x + y >= 1;
+            s.t. margin: 2 <= x - y <= 5.5;
+        "#;
+        let compiled = generate_matrix(merge_model(parse_model(text)));
+        let mut out = Vec::new();
+        write_mps(&mut out, &compiled, "ranges");
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains("ROWS\n G  plain\n G  margin\n N  cost\n"));
+        assert!(
+            out.contains("RHS\n RHS1 plain 1\n RHS1 margin 2\nRANGES\n RNG1 margin 3.5\nBOUNDS\n")
+        );
+    }
+
+    #[test]
+    fn test_bounds() {
+        let text = r#"
+            var v <= 5;
+            var w >= -2;
+            var y;
+            var u = 3;
+            minimize cost: v + w + y + u;
+            s.t. c: v + w + y + u >= 1;
+        "#;
+        let compiled = generate_matrix(merge_model(parse_model(text)));
+        let mut out = Vec::new();
+        write_mps(&mut out, &compiled, "bounds");
+        let out = String::from_utf8(out).unwrap();
+        assert!(
+            out.ends_with(
+                "BOUNDS\n UP BND1 v 5\n LO BND1 w -2\n FR BND1 y\n FX BND1 u 3\nENDATA\n"
+            )
+        );
+
+        let mut sequential = Vec::new();
+        write_mps_sequential(&mut sequential, &compiled, "bounds");
+        assert_eq!(out.as_bytes(), sequential);
+    }
+
+    /// `cargo test --release bench_write_mps -- --ignored --nocapture`
+    #[test]
+    #[ignore]
+    fn bench_write_mps() {
+        let compiled = osemosys("examples/ose_atl.dat");
+        let time = |f: &dyn Fn(&mut std::io::Sink)| {
+            let t = Instant::now();
+            for _ in 0..10 {
+                f(&mut std::io::sink());
+            }
+            t.elapsed() / 10
+        };
+        let sequential = time(&|w| write_mps_sequential(w, &compiled, "osemosys"));
+        let parallel = time(&|w| write_mps(w, &compiled, "osemosys"));
+        eprintln!("sequential: {sequential:?}, parallel: {parallel:?}");
     }
 }
@@ -0,0 +1,332 @@
+//! Python bindings, enabled with the `python` feature and built with maturin.
+//!
+//! ```python
+//! import mosox
+//! model = mosox.load("osemosys.mod", "atlantis.dat")
+//! matrix = model.generate()
+//! A = matrix.to_scipy()
+//! ```
+
+use std::panic::{self, AssertUnwindSafe};
+use std::path::Path;
+
+use numpy::PyArray1;
+use pyo3::create_exception;
+use pyo3::exceptions::{PyException, PyFileNotFoundError};
+use pyo3::prelude::*;
+use pyo3::types::{IntoPyDict, PyAny};
+
+use crate::ir::index_name;
+use crate::ir::model::ModelWithData;
+use crate::ir::op::RowType;
+use crate::matrix::{Compiled, GenOptions, gen_matrix};
+use crate::{load_model_and_data, merge_model};
+
+create_exception!(
+    mosox,
+    MosoxError,
+    PyException,
+    "Raised when a model cannot be parsed, merged or generated."
+);
+
+/// Run `f`, turning a panic anywhere in the parser or generator into a `MosoxError`
+fn guard<T>(f: impl FnOnce() -> T) -> PyResult<T> {
+    panic::catch_unwind(AssertUnwindSafe(f)).map_err(|err| {
+        let msg = err
+            .downcast_ref::<String>()
+            .cloned()
+            .or_else(|| err.downcast_ref::<&str>().map(|s| s.to_string()))
+            .unwrap_or_else(|| "unknown error".to_string());
+        MosoxError::new_err(msg)
+    })
+}
+
+fn check_path(path: &str) -> PyResult<()> {
+    if Path::new(path).is_file() {
+        Ok(())
+    } else {
+        Err(PyFileNotFoundError::new_err(path.to_string()))
+    }
+}
+
+/// A parsed model merged with its data
+#[pyclass(name = "Model", module = "mosox")]
+struct PyModel {
+    inner: ModelWithData,
+}
+
+#[pymethods]
+impl PyModel {
+    /// Generate the constraint matrix for this model.
+    fn generate(&self, py: Python<'_>) -> PyResult<PyMatrix> {
+        let model = self.inner.clone();
+        let compiled = py.detach(|| guard(|| gen_matrix(model, &GenOptions::default())))?;
+        Ok(PyMatrix::from(compiled))
+    }
+
+    fn __str__(&self) -> String {
+        self.inner.to_string()
+    }
+}
+
+/// The generated matrix in coordinate (triplet) form, with row and column names
+#[pyclass(name = "Matrix", module = "mosox")]
+struct PyMatrix {
+    /// Row names, eg `EBa11_EnergyBalanceEachTS5[RE1,ID,ELC,2030]`
+    #[pyo3(get)]
+    row_names: Vec<String>,
+    /// Column names, eg `NewCapacity[RE1,E01,2030]`
+    #[pyo3(get)]
+    col_names: Vec<String>,
+    /// MPS row types: `N` (objective), `L`, `E` or `G`
+    #[pyo3(get)]
+    row_types: Vec<String>,
+    rhs: Vec<f64>,
+    ranges: Vec<f64>,
+    col_lower: Vec<f64>,
+    col_upper: Vec<f64>,
+    rows: Vec<i64>,
+    cols: Vec<i64>,
+    vals: Vec<f64>,
+}
+
+#[pymethods]
+impl PyMatrix {
+    /// Right-hand side of each row
+    #[getter]
+    fn rhs<'py>(&self, py: Python<'py>) -> Bound<'py, PyArray1<f64>> {
+        PyArray1::from_slice(py, &self.rhs)
+    }
+
+    /// Range of each row: `hi - lo` for a `G` row from a double inequality
+    /// `lo <= expr <= hi` (whose `rhs` is `lo`), otherwise 0
+    #[getter]
+    fn ranges<'py>(&self, py: Python<'py>) -> Bound<'py, PyArray1<f64>> {
+        PyArray1::from_slice(py, &self.ranges)
+    }
+
+    /// Lower bound of each column (`-inf` if free)
+    #[getter]
+    fn col_lower<'py>(&self, py: Python<'py>) -> Bound<'py, PyArray1<f64>> {
+        PyArray1::from_slice(py, &self.col_lower)
+    }
+
+    /// Upper bound of each column (`inf` if free)
+    #[getter]
+    fn col_upper<'py>(&self, py: Python<'py>) -> Bound<'py, PyArray1<f64>> {
+        PyArray1::from_slice(py, &self.col_upper)
+    }
+
+    /// Row index of each nonzero
+    #[getter]
+    fn rows<'py>(&self, py: Python<'py>) -> Bound<'py, PyArray1<i64>> {
+        PyArray1::from_slice(py, &self.rows)
+    }
+
+    /// Column index of each nonzero
+    #[getter]
+    fn cols<'py>(&self, py: Python<'py>) -> Bound<'py, PyArray1<i64>> {
+        PyArray1::from_slice(py, &self.cols)
+    }
+
+    /// Value of each nonzero
+    #[getter]
+    fn vals<'py>(&self, py: Python<'py>) -> Bound<'py, PyArray1<f64>> {
+        PyArray1::from_slice(py, &self.vals)
+    }
+
+    /// (rows, cols) of the matrix
+    #[getter]
+    fn shape(&self) -> (usize, usize) {
+        (self.row_names.len(), self.col_names.len())
+    }
+
+    /// Build a `scipy.sparse.csc_array` from the nonzeros.
+    fn to_scipy<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyAny>> {
+        let sparse = py.import("scipy.sparse")?;
+        let coords = (self.vals(py), (self.rows(py), self.cols(py)));
+        let kwargs = [("shape", self.shape())].into_py_dict(py)?;
+        sparse.getattr("csc_array")?.call((coords,), Some(&kwargs))
+    }
+
+    fn __repr__(&self) -> String {
+        let (rows, cols) = self.shape();
+        format!(
+            "<mosox.Matrix rows={rows} cols={cols} nonzeros={}>",
+            self.vals.len()
+        )
+    }
+}
+
+impl From<Compiled> for PyMatrix {
+    fn from(compiled: Compiled) -> Self {
+        let mut row_names = Vec::with_capacity(compiled.cons.len());
+        let mut row_types = Vec::with_capacity(compiled.cons.len());
+        let mut rhs = Vec::with_capacity(compiled.cons.len());
+        let mut ranges = Vec::with_capacity(compiled.cons.len());
+        for (name, idx, row_type, val) in &compiled.cons {
+            row_names.push(index_name(*name, idx));
+            row_types.push(row_type.to_string());
+            rhs.push(*val);
+            ranges.push(match row_type {
+                RowType::Ranged(width) => *width,
+                _ => 0.0,
+            });
+        }
+
+        let mut col_names = Vec::with_capacity(compiled.vars.len());
+        let mut col_lower = Vec::with_capacity(compiled.vars.len());
+        let mut col_upper = Vec::with_capacity(compiled.vars.len());
+        let mut cols = Vec::with_capacity(compiled.vals.len());
+        for (j, ((name, idx), bounds)) in compiled.vars.iter().enumerate() {
+            col_names.push(index_name(*name, idx));
+            col_lower.push(bounds.lower);
+            col_upper.push(bounds.upper);
+            let count = compiled.col_starts[j + 1] - compiled.col_starts[j];
+            cols.extend(std::iter::repeat_n(j as i64, count));
+        }
+        let rows = compiled.row_ids.iter().map(|row| *row as i64).collect();
+
+        PyMatrix {
+            row_names,
+            col_names,
+            row_types,
+            rhs,
+            ranges,
+            col_lower,
+            col_upper,
+            rows,
+            cols,
+            vals: compiled.vals,
+        }
+    }
+}
+
+/// One data file or a list of them, applied in order
+#[derive(FromPyObject)]
+enum DataPaths {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl DataPaths {
+    fn into_vec(self) -> Vec<String> {
+        match self {
+            DataPaths::One(path) => vec![path],
+            DataPaths::Many(paths) => paths,
+        }
+    }
+}
+
+/// Load a model file and optional data file(s). With several data files, later
+/// files override the values of earlier ones.
+#[pyfunction]
+#[pyo3(signature = (path, data_path=None))]
+fn load(py: Python<'_>, path: &str, data_path: Option<DataPaths>) -> PyResult<PyModel> {
+    check_path(path)?;
+    let data_paths = data_path.map(DataPaths::into_vec).unwrap_or_default();
+    for data_path in &data_paths {
+        check_path(data_path)?;
+    }
+    let data_paths: Vec<&str> = data_paths.iter().map(String::as_str).collect();
+    let inner = py.detach(|| guard(|| merge_model(load_model_and_data(path, &data_paths))))?;
+    Ok(PyModel { inner })
+}
+
+/// Load a model file and optional data file(s) and generate the matrix in one go.
+#[pyfunction]
+#[pyo3(signature = (path, data_path=None))]
+fn generate(py: Python<'_>, path: &str, data_path: Option<DataPaths>) -> PyResult<PyMatrix> {
+    load(py, path, data_path)?.generate(py)
+}
+
+#[pymodule]
+#[pyo3(name = "mosox")]
+fn py_mosox(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_function(wrap_pyfunction!(load, m)?)?;
+    m.add_function(wrap_pyfunction!(generate, m)?)?;
+    m.add_class::<PyModel>()?;
+    m.add_class::<PyMatrix>()?;
+    m.add("MosoxError", m.py().get_type::<MosoxError>())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use numpy::{PyArrayMethods, PyUntypedArrayMethods};
+
+    use super::*;
+
+    // 6 rows and 9 columns, so a transposed matrix doesn't fit
+    const MODEL: &str = "tests/fixtures/golden.mod";
+
+    /// The (row, col, value) of each nonzero, found by name
+    fn nonzeros(matrix: &PyMatrix) -> Vec<(&str, &str, f64)> {
+        (0..matrix.vals.len())
+            .map(|k| {
+                (
+                    matrix.row_names[matrix.rows[k] as usize].as_str(),
+                    matrix.col_names[matrix.cols[k] as usize].as_str(),
+                    matrix.vals[k],
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_generate() {
+        Python::initialize();
+        Python::attach(|py| {
+            let matrix = generate(py, MODEL, None).unwrap();
+            assert_eq!(matrix.shape(), (6, 9));
+            assert_eq!(matrix.rows.len(), 21);
+            assert_eq!(matrix.cols.len(), 21);
+            assert_eq!(matrix.vals.len(), 21);
+            let nonzeros = nonzeros(&matrix);
+            assert!(nonzeros.contains(&("total", "ship[b]", 1e10)));
+            assert!(nonzeros.contains(&("balance", "shift", -1.0)));
+            assert_eq!(matrix.row_types[5], "N");
+        });
+    }
+
+    #[test]
+    fn test_errors() {
+        Python::initialize();
+        Python::attach(|py| {
+            let err = generate(py, "tests/fixtures/missing.mod", None)
+                .err()
+                .unwrap();
+            assert!(err.is_instance_of::<PyFileNotFoundError>(py));
+            // A file that isn't GMPL panics in the parser
+            let err = generate(py, "Cargo.toml", None).err().unwrap();
+            assert!(err.is_instance_of::<MosoxError>(py));
+        });
+    }
+
+    /// Needs numpy and scipy
+    #[test]
+    fn test_to_scipy() {
+        Python::initialize();
+        Python::attach(|py| {
+            let matrix = generate(py, MODEL, None).unwrap();
+            assert_eq!(matrix.rows(py).len(), 21);
+            assert_eq!(matrix.cols(py).len(), 21);
+            assert_eq!(matrix.vals(py).to_vec().unwrap(), matrix.vals);
+            assert_eq!(matrix.rhs(py).len(), 6);
+            assert_eq!(matrix.col_lower(py).len(), 9);
+
+            let dense = matrix
+                .to_scipy(py)
+                .unwrap()
+                .call_method0("toarray")
+                .unwrap();
+            let shape: (usize, usize) = dense.getattr("shape").unwrap().extract().unwrap();
+            assert_eq!(shape, (6, 9));
+            for k in 0..matrix.vals.len() {
+                let at = (matrix.rows[k], matrix.cols[k]);
+                let val: f64 = dense.get_item(at).unwrap().extract().unwrap();
+                assert_eq!(val, matrix.vals[k]);
+            }
+        });
+    }
+}
@@ -1,4 +1,5 @@
 use assert_cmd::prelude::*;
+use predicates::prelude::*;
 use std::process::Command;
 
 #[test]
@@ -14,3 +15,201 @@ fn run_bad_file() {
     cmd.arg("balance").arg("doesntexist.mod");
     cmd.assert().failure();
 }
+
+#[test]
+fn run_graph() {
+    let mut cmd = Command::cargo_bin("mosox").unwrap();
+    cmd.arg("graph")
+        .arg("examples/example_3.mod")
+        .arg("-f")
+        .arg("json");
+    cmd.assert().success().stdout(predicates::str::contains(
+        r#"{"from":"supply_limit","to":"supply"}"#,
+    ));
+
+    let mut cmd = Command::cargo_bin("mosox").unwrap();
+    cmd.arg("graph")
+        .arg("examples/osemosys.mod")
+        .arg("--uses")
+        .arg("CapacityFactor");
+    cmd.assert()
+        .success()
+        .stdout(predicates::str::contains("CAa4_Constraint_Capacity"));
+}
+
+#[test]
+fn run_check_undeclared() {
+    let mut cmd = Command::cargo_bin("mosox").unwrap();
+    cmd.arg("check").arg("tests/fixtures/undeclared.mod");
+    cmd.assert().failure().stderr(predicates::str::contains(
+        "c refers to undeclared symbol Demand",
+    ));
+}
+
+#[test]
+fn run_generate_strict() {
+    let mut cmd = Command::cargo_bin("mosox").unwrap();
+    cmd.arg("generate").arg("tests/fixtures/strict.mod");
+    cmd.assert()
+        .success()
+        .stdout(predicates::str::contains(" G  c\n"))
+        .stderr(predicates::str::contains(
+            "constraint c: > is treated as >=",
+        ));
+
+    let mut cmd = Command::cargo_bin("mosox").unwrap();
+    cmd.arg("generate").arg("tests/fixtures/not_equal.mod");
+    cmd.assert().failure().stderr(predicates::str::contains(
+        "constraint c: <> is not allowed in a constraint",
+    ));
+}
+
+#[test]
+fn run_generate_reduce() {
+    let mut cmd = Command::cargo_bin("mosox").unwrap();
+    cmd.arg("generate")
+        .arg("tests/fixtures/reduce.mod")
+        .arg("--reduce");
+    cmd.assert().failure().stderr(predicates::str::contains(
+        "d is empty and can't hold: 0 >= 1",
+    ));
+}
+
+#[test]
+fn run_generate_restrict_undeclared() {
+    let mut cmd = Command::cargo_bin("mosox").unwrap();
+    cmd.arg("generate")
+        .arg("examples/example_3.mod")
+        .arg("--restrict")
+        .arg("REGION=RE1");
+    cmd.assert().failure().stderr(predicates::str::contains(
+        "there's no set named REGION to restrict",
+    ));
+}
+
+#[test]
+fn run_stats() {
+    let mut cmd = Command::cargo_bin("mosox").unwrap();
+    cmd.arg("stats").arg("examples/example_3.mod");
+    cmd.assert().success().stdout(predicates::str::contains(
+        "rows: 6, columns: 6, nonzeros: 18",
+    ));
+}
+
+#[test]
+fn run_explain() {
+    let mut cmd = Command::cargo_bin("mosox").unwrap();
+    cmd.arg("explain")
+        .arg("examples/example_3.mod")
+        .arg("--row")
+        .arg("supply_limit[P1]");
+    cmd.assert()
+        .success()
+        .stdout(predicates::str::contains("+100    (supply[P1] = 100)"));
+
+    let mut cmd = Command::cargo_bin("mosox").unwrap();
+    cmd.arg("explain")
+        .arg("examples/example_3.mod")
+        .arg("--row")
+        .arg("supply_limit[P9]");
+    cmd.assert().failure().stderr(predicates::str::contains(
+        "supply_limit[P9] isn't in the domain of supply_limit",
+    ));
+}
+
+#[test]
+fn run_generate_overrides() {
+    let mut cmd = Command::cargo_bin("mosox").unwrap();
+    cmd.arg("generate")
+        .arg("examples/example_3.mod")
+        .arg("--drop-constraint")
+        .arg("meet_*")
+        .arg("--set-param")
+        .arg("supply[P1]=7");
+    cmd.assert()
+        .success()
+        .stdout(predicates::str::contains(" RHS1 supply_limit[P1] 7\n"))
+        .stdout(predicates::str::contains("meet_demand").not());
+
+    let mut cmd = Command::cargo_bin("mosox").unwrap();
+    cmd.arg("generate")
+        .arg("examples/example_3.mod")
+        .arg("--set-param")
+        .arg("nope=1");
+    cmd.assert()
+        .failure()
+        .stderr(predicates::str::contains("there's no param named nope"));
+
+    // Overrides are checked against the param's domain and conditions
+    let mut cmd = Command::cargo_bin("mosox").unwrap();
+    cmd.arg("generate")
+        .arg("tests/fixtures/set_param.mod")
+        .arg("--set-param")
+        .arg("p[z]=3")
+        .arg("--set-param")
+        .arg("p[a]=-3");
+    cmd.assert()
+        .failure()
+        .stderr(predicates::str::contains(
+            "param p: keys outside its domain: p[z]",
+        ))
+        .stderr(predicates::str::contains(
+            "param p: values breaking >= 0: p[a] = -3",
+        ));
+}
+
+#[test]
+fn run_generate_objective() {
+    let path = "tests/fixtures/objectives.mod";
+
+    let mut cmd = Command::cargo_bin("mosox").unwrap();
+    cmd.arg("generate")
+        .arg(path)
+        .arg("--objective")
+        .arg("emissions");
+    cmd.assert().success().stdout(predicates::str::contains(
+        "ROWS\n G  c\n N  emissions\n N  cost\n",
+    ));
+
+    let mut cmd = Command::cargo_bin("mosox").unwrap();
+    cmd.arg("generate")
+        .arg(path)
+        .arg("--objective")
+        .arg("profit");
+    cmd.assert().failure().stderr(predicates::str::contains(
+        "there's no objective named profit",
+    ));
+}
+
+#[test]
+fn run_generate_indexed_objective() {
+    let mut cmd = Command::cargo_bin("mosox").unwrap();
+    cmd.arg("generate")
+        .arg("tests/fixtures/indexed_objective.mod")
+        .arg("--objective")
+        .arg("cost[b]");
+    cmd.assert().success().stdout(predicates::str::contains(
+        "ROWS\n G  c\n N  cost[b]\n N  cost[a]\n",
+    ));
+
+    let mut cmd = Command::cargo_bin("mosox").unwrap();
+    cmd.arg("generate")
+        .arg("tests/fixtures/indexed_objective.mod")
+        .arg("--objective")
+        .arg("cost[z]");
+    cmd.assert().failure().stderr(predicates::str::contains(
+        "cost[z] isn't in the domain of cost",
+    ));
+
+    let mut cmd = Command::cargo_bin("mosox").unwrap();
+    cmd.arg("generate")
+        .arg("tests/fixtures/indexed_objective.mod")
+        .arg("--restrict")
+        .arg("R=a")
+        .arg("--objective")
+        .arg("cost[b]");
+    cmd.assert().failure().stderr(
+        predicates::str::contains("cost[b] isn't in the domain of cost")
+            .and(predicates::str::contains("panicked").not()),
+    );
+}
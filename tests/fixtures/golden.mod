@@ -0,0 +1,18 @@
+# Written by the original sequential MPS writer as golden.mps, which the
+# parallel writer has to match byte for byte
+set I;
+param cost{i in I};
+param cap{i in I};
+var ship{i in I} >= 0;
+var spill{i in I} <= 5;
+var slack >= -2;
+var shift;
+var fixed = 3;
+minimize total: sum{i in I} (cost[i] * ship[i] + 0.1 * spill[i]) + slack + shift + fixed;
+s.t. supply{i in I}: ship[i] + spill[i] <= cap[i];
+s.t. balance: sum{i in I} ship[i] / 3 - shift = 1e-7;
+s.t. floor: slack + fixed >= -1.5;
+data;
+set I := a b c;
+param cost := a 1.5 b 1e10 c 0.3;
+param cap := a 7.25 b 1 c 7.25;
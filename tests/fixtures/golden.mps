@@ -0,0 +1,44 @@
+NAME golden
+ROWS
+ L  supply[a]
+ L  supply[b]
+ L  supply[c]
+ E  balance
+ G  floor
+ N  total
+COLUMNS
+ ship[a] supply[a] 1
+ ship[a] balance 0.3333333333333333
+ ship[a] total 1.5
+ spill[a] supply[a] 1
+ spill[a] total 0.1
+ ship[b] supply[b] 1
+ ship[b] balance 0.3333333333333333
+ ship[b] total 10000000000
+ spill[b] supply[b] 1
+ spill[b] total 0.1
+ ship[c] supply[c] 1
+ ship[c] balance 0.3333333333333333
+ ship[c] total 0.3
+ spill[c] supply[c] 1
+ spill[c] total 0.1
+ shift balance -1
+ shift total 1
+ slack floor 1
+ slack total 1
+ fixed floor 1
+ fixed total 1
+RHS
+ RHS1 supply[a] 7.25
+ RHS1 supply[b] 1
+ RHS1 supply[c] 7.25
+ RHS1 balance 0.0000001
+ RHS1 floor -1.5
+BOUNDS
+ UP BND1 spill[a] 5
+ UP BND1 spill[b] 5
+ UP BND1 spill[c] 5
+ FR BND1 shift
+ LO BND1 slack -2
+ FX BND1 fixed 3
+ENDATA
@@ -0,0 +1,4 @@
+set R := a b;
+var x{r in R} >= 0;
+minimize cost{r in R}: x[r];
+s.t. c: sum{r in R} x[r] >= 1;
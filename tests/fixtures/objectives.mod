@@ -0,0 +1,4 @@
+var x >= 0;
+minimize cost: x;
+minimize emissions: 2 * x;
+s.t. c: x >= 1;
@@ -0,0 +1,4 @@
+var x >= 0;
+minimize cost: x;
+s.t. c: 2 * x <= 3;
+s.t. d: x - x >= 1;
@@ -0,0 +1,7 @@
+set R := a b;
+param p{r in R} >= 0;
+var x{r in R} >= 0;
+minimize cost: sum{r in R} p[r] * x[r];
+s.t. c: sum{r in R} x[r] >= 1;
+data;
+param p := a 1 b 2;
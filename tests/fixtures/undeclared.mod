@@ -0,0 +1,3 @@
+var x >= 0;
+minimize cost: x;
+s.t. c: x >= Demand;
@@ -4,3 +4,270 @@ use mosox::load_model;
 fn test_load() {
     load_model("examples/osemosys.mod");
 }
+
+#[test]
+fn test_incremental_update() {
+    use mosox::{ChangeKind, Generator, load_data, merge_model};
+
+    let model = merge_model(load_model("examples/example_3.mod"));
+    let mut generator = Generator::new(model);
+    let rows_before = generator.compiled().cons.len();
+
+    let update = generator
+        .update(load_data("examples/example_3_scenario.dat"))
+        .unwrap();
+    assert_eq!(update.regenerated, vec!["supply_limit"]);
+    assert_eq!(update.rows.len(), 1);
+    assert_eq!(update.rows[0].row, "supply_limit[P1]");
+    assert_eq!(update.rows[0].kind, ChangeKind::Modified);
+
+    let compiled = generator.compiled();
+    assert_eq!(compiled.cons.len(), rows_before);
+    assert!(compiled.cons.iter().any(|(_, _, _, rhs)| *rhs == 120.0));
+}
+
+#[test]
+fn test_incremental_derived() {
+    use mosox::{Generator, generate_matrix, merge_model, parse_data, parse_model};
+
+    let model = "set I;
+        param w{i in I};
+        param k;
+        param v{i in I} := 2 * w[i];
+        set BIG := setof{i in I: w[i] > 1} i;
+        var x{i in I} >= 0;
+        minimize cost: sum{i in I} x[i];
+        s.t. big{i in BIG}: x[i] >= v[i];
+        s.t. cap: sum{i in I} x[i] <= k;";
+    let base = "set I := a b c;\nparam w := a 1 b 2 c 3;\nparam k := 10;";
+    let mut generator = Generator::new(merge_model(
+        parse_model(model)
+            .into_iter()
+            .chain(parse_data(base))
+            .collect(),
+    ));
+
+    let update = generator.update(parse_data("param w := a 5;")).unwrap();
+    assert_eq!(update.regenerated, vec!["big"]);
+    let update = generator.update(parse_data("param k := 20;")).unwrap();
+    assert_eq!(update.regenerated, vec!["cap"]);
+
+    let all = [base, "param w := a 5;", "param k := 20;"];
+    let mut entries = parse_model(model);
+    for data in all {
+        entries.extend(parse_data(data));
+    }
+    let fresh = generate_matrix(merge_model(entries));
+    let updated = generator.compiled();
+    assert!(updated.cons == fresh.cons);
+    assert_eq!(updated.vals, fresh.vals);
+    assert_eq!(updated.row_ids, fresh.row_ids);
+
+    let err = generator
+        .update(parse_data("param nope := 1;"))
+        .unwrap_err();
+    assert_eq!(
+        err.message,
+        "data param nope has no matching model declaration"
+    );
+}
+
+#[test]
+fn test_layered_data() {
+    use mosox::{generate_matrix, load_model_and_data, merge_model};
+
+    let entries = load_model_and_data(
+        "examples/example_3.mod",
+        &["examples/example_3_layered.dat"],
+    );
+    let compiled = generate_matrix(merge_model(entries));
+    let mut rhs: Vec<f64> = compiled.cons.iter().map(|(_, _, _, rhs)| *rhs).collect();
+    rhs.sort_by(f64::total_cmp);
+    // Objective, supply (P1 from the included scenario) and demand (only W3 overridden)
+    assert_eq!(rhs, vec![0.0, 60.0, 70.0, 80.0, 120.0, 150.0]);
+}
+
+#[test]
+fn test_load_in_memory() {
+    use std::collections::HashMap;
+
+    use mosox::{generate_matrix, load_model_and_data_from, merge_model, parse_data, parse_model};
+
+    let model = "set P; param cap{p in P}; var x{p in P} >= 0;
+        minimize cost: sum{p in P} x[p];
+        s.t. limit{p in P}: x[p] <= cap[p];";
+    let files = HashMap::from([
+        ("model.mod".to_string(), model.to_string()),
+        (
+            "data/base.dat".to_string(),
+            "include \"../shared.dat\";\nparam cap := a 1 b 2;".to_string(),
+        ),
+        ("shared.dat".to_string(), "set P := a b;".to_string()),
+        (
+            "data/scenario.dat".to_string(),
+            "param cap := b 5;".to_string(),
+        ),
+    ]);
+    let entries = load_model_and_data_from(
+        &files,
+        "model.mod",
+        &["./data/base.dat", "data/scenario.dat"],
+    );
+    let mut rhs: Vec<f64> = generate_matrix(merge_model(entries))
+        .cons
+        .iter()
+        .map(|(_, _, _, rhs)| *rhs)
+        .collect();
+    rhs.sort_by(f64::total_cmp);
+    assert_eq!(rhs, vec![0.0, 1.0, 5.0]);
+
+    let mut entries = parse_model(model);
+    entries.extend(parse_data("set P := a;\nparam cap := a 3;"));
+    assert_eq!(generate_matrix(merge_model(entries)).cons.len(), 2);
+}
+
+#[test]
+fn test_scenario_overrides() {
+    use mosox::{generate_matrix, merge_model};
+
+    let mut model = merge_model(load_model("examples/example_3.mod"));
+    assert_eq!(model.drop_constraints("meet_*"), 1);
+    assert_eq!(model.drop_constraints("total_cost"), 0);
+    model.set_param("supply", &["P1"], 7.0).unwrap();
+    let err = model.set_param("supply", &["P1", "W1"], 7.0).unwrap_err();
+    assert_eq!(err.message, "supply has 1 indices, but 2 were given");
+
+    let compiled = generate_matrix(model);
+    let rhs: Vec<f64> = compiled.cons.iter().map(|(_, _, _, rhs)| *rhs).collect();
+    // The supply limits (P1 overridden) and the objective
+    assert_eq!(rhs, vec![7.0, 150.0, 0.0]);
+}
+
+#[test]
+fn test_multiple_objectives() {
+    use mosox::{generate_matrix, merge_model, parse_model};
+
+    let text = "var x >= 0;
+        var y >= 0;
+        minimize cost: 2 * x + y;
+        minimize emissions: x + 3 * y;
+        s.t. demand: x + y >= 1;";
+    // The row types, and the coefficients of the minimised (first N) row
+    let objective = |model| -> (Vec<String>, Vec<f64>) {
+        let compiled = generate_matrix(model);
+        let row_types = compiled
+            .cons
+            .iter()
+            .map(|(_, _, row_type, _)| row_type.to_string())
+            .collect();
+        let coeffs = (0..compiled.vars.len())
+            .flat_map(|j| compiled.col(j).filter(|(row, _)| *row == 1))
+            .map(|(_, val)| val)
+            .collect();
+        (row_types, coeffs)
+    };
+
+    let model = merge_model(parse_model(text));
+    assert_eq!(
+        objective(model.clone()),
+        (vec!["G".into(), "N".into(), "N".into()], vec![2.0, 1.0])
+    );
+
+    let mut selected = model.clone();
+    selected.select_objective("emissions").unwrap();
+    assert_eq!(objective(selected).1, vec![1.0, 3.0]);
+    let err = model.clone().select_objective("demand").unwrap_err();
+    assert_eq!(err.message, "there's no objective named demand");
+
+    let mut weighted = model;
+    weighted
+        .weight_objectives("total", &[("cost", 1.0), ("emissions", 50.0)])
+        .unwrap();
+    let (row_types, coeffs) = objective(weighted);
+    assert_eq!(row_types.len(), 4);
+    assert_eq!(coeffs, vec![52.0, 151.0]);
+
+    // A maximised objective counts against the minimised sum
+    let text = text.replace("minimize emissions", "maximize emissions");
+    let mut weighted = merge_model(parse_model(&text));
+    weighted
+        .weight_objectives("total", &[("cost", 1.0), ("emissions", 50.0)])
+        .unwrap();
+    assert_eq!(objective(weighted).1, vec![-48.0, -149.0]);
+}
+
+#[test]
+fn test_indexed_objective() {
+    use mosox::{GenOptions, generate_matrix, merge_model, parse_model, validate_objective};
+
+    let text = "set R;
+        param c{r in R};
+        var x{r in R} >= 0;
+        minimize cost{r in R}: c[r] * x[r];
+        s.t. demand: sum{r in R} x[r] >= 1;
+        data;
+        set R := a b c;
+        param c := a 1 b 2 c 3;";
+    // The coefficients of each row after the constraint, N rows in order
+    let objectives = |model| -> Vec<Vec<f64>> {
+        let compiled = generate_matrix(model);
+        (1..compiled.cons.len())
+            .map(|row| {
+                (0..compiled.vars.len())
+                    .flat_map(|j| compiled.col(j).filter(|(i, _)| *i == row))
+                    .map(|(_, val)| val)
+                    .collect()
+            })
+            .collect()
+    };
+
+    let model = merge_model(parse_model(text));
+    assert_eq!(
+        objectives(model.clone()),
+        vec![vec![1.0], vec![2.0], vec![3.0]]
+    );
+
+    let mut selected = model.clone();
+    selected.select_objective("cost[b]").unwrap();
+    assert_eq!(objectives(selected), vec![vec![2.0], vec![1.0], vec![3.0]]);
+    // The model is left as it was when the row can't be picked
+    let mut unselected = model.clone();
+    let err = unselected.select_objective("cost[a,b]").unwrap_err();
+    assert_eq!(err.message, "cost has 1 indices, but 2 were given");
+    assert!(
+        unselected
+            .constraints
+            .iter()
+            .all(|con| con.active.is_none())
+    );
+    assert_eq!(objectives(unselected), objectives(model.clone()));
+
+    let err = model.clone().select_objective("cost[b").unwrap_err();
+    assert_eq!(err.message, "missing ']' in 'cost[b'");
+    let err = model.clone().select_objective("cost[b]]]").unwrap_err();
+    assert_eq!(err.message, "stray bracket in 'cost[b]]]'");
+
+    // Whether the row exists depends on the data, so it's validated separately
+    let mut missing = model.clone();
+    missing.select_objective("cost[z]").unwrap();
+    let diags = validate_objective(&missing, &GenOptions::default());
+    assert_eq!(diags.len(), 1);
+    assert_eq!(diags[0].message, "cost[z] isn't in the domain of cost");
+    assert!(validate_objective(&model, &GenOptions::default()).is_empty());
+
+    // Nor can it be one that a restriction leaves out
+    let mut restricted = model.clone();
+    restricted.select_objective("cost[b]").unwrap();
+    let options = GenOptions {
+        restrict: vec![("R".to_string(), vec!["a".to_string()])],
+        ..GenOptions::default()
+    };
+    let diags = validate_objective(&restricted, &options);
+    assert_eq!(diags[0].message, "cost[b] isn't in the domain of cost");
+
+    let mut weighted = model;
+    weighted
+        .weight_objectives("total", &[("cost", 2.0)])
+        .unwrap();
+    assert_eq!(objectives(weighted)[0], vec![2.0, 4.0, 6.0]);
+}